@@ -0,0 +1,75 @@
+//! Throughput (events per second) of parsing, and of every embedded converter.
+//! Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hawktracer_converter_lib as hcl;
+use hawktracer_parser::{Event, EventKlassRegistry};
+
+// The test capture is small; it's repeated to get a stream of ~10k events.
+const CAPTURE_COPIES: usize = 200;
+const FORMATS: [&str; 4] = ["chrome-tracing", "flamegraph", "debug", "json_debug"];
+
+fn load_capture() -> std::vec::Vec<u8> {
+    let capture = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/resources/test/test_trace.htdump"
+    ))
+    .unwrap();
+    capture.repeat(CAPTURE_COPIES)
+}
+
+fn parse_events(data: &[u8]) -> (std::vec::Vec<Event>, EventKlassRegistry) {
+    let mut reader = hawktracer_parser::event_reader::EventReader::new(
+        hawktracer_parser::data_provider::DataProvider::new(Box::new(std::io::Cursor::new(
+            data.to_vec(),
+        ))),
+    );
+    let mut reg = EventKlassRegistry::new();
+    let mut events = vec![];
+    while let Ok(event) = reader.read_event(&mut reg) {
+        events.push(event.flat_event());
+    }
+    (events, reg)
+}
+
+fn create_label_getter() -> hcl::LabelGetter {
+    hcl::LabelGetter::new(
+        hcl::LabelMap::new(),
+        vec!["label".to_owned(), "name".to_owned()],
+    )
+}
+
+fn bench_parsing(c: &mut Criterion) {
+    let data = load_capture();
+    let event_count = parse_events(&data).0.len();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(event_count as u64));
+    group.bench_function("htdump", |b| b.iter(|| parse_events(&data)));
+    group.finish();
+}
+
+fn bench_converters(c: &mut Criterion) {
+    let (events, reg) = parse_events(&load_capture());
+    let manager = hcl::ConverterManager::new();
+
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    for format in FORMATS.iter() {
+        group.bench_function(*format, |b| {
+            b.iter(|| {
+                // Some converters write the output on drop, so it's part of the measurement.
+                let mut converter = manager
+                    .create_converter(format, Box::new(std::io::sink()), create_label_getter())
+                    .unwrap();
+                for event in &events {
+                    let _ = converter.process_event(event, &reg);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parsing, bench_converters);
+criterion_main!(benches);
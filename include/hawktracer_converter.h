@@ -0,0 +1,66 @@
+/*
+ * Conversion of HawkTracer data from C/C++ applications.
+ *
+ * Link with the hawktracer_converter_lib shared or static library. HawkTracer
+ * data (e.g. buffers flushed by a HawkTracer listener) is fed to a conversion,
+ * and converted once the conversion is finished:
+ *
+ *     htc_conversion* conversion = htc_conversion_create("chrome-tracing");
+ *     htc_conversion_add_map_file(conversion, "labels.map");
+ *     htc_conversion_feed(conversion, data, size);
+ *     if (htc_conversion_finish_to_file(conversion, "trace.json") != 0)
+ *         fprintf(stderr, "%s\n", htc_conversion_get_error(conversion));
+ *     htc_conversion_destroy(conversion);
+ *
+ * Functions returning int return 0 on success; the reason of a failure is
+ * given by htc_conversion_get_error().
+ */
+#ifndef HAWKTRACER_CONVERTER_H
+#define HAWKTRACER_CONVERTER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct htc_conversion htc_conversion;
+
+/* Writes data to the conversion output; returns 0 on success. */
+typedef int (*htc_conversion_write_fn)(void* write_context, const uint8_t* data, size_t size);
+
+/* Returns NULL if the format (e.g. "chrome-tracing", "flamegraph") doesn't exist. */
+htc_conversion* htc_conversion_create(const char* format);
+
+/* Sets a converter option, as listed by `hawktracer-converter --help`. */
+int htc_conversion_set_option(htc_conversion* conversion, const char* name, const char* value);
+
+int htc_conversion_add_map_file(htc_conversion* conversion, const char* path);
+
+/* Data is copied, and kept until the conversion is finished. */
+int htc_conversion_feed(htc_conversion* conversion, const uint8_t* data, size_t size);
+
+/* A conversion can be finished only once. The file is compressed if its
+ * extension is .gz or .zst. Fails if the output can't be written; statistics
+ * are available even then. */
+int htc_conversion_finish_to_file(htc_conversion* conversion, const char* path);
+int htc_conversion_finish_to_callback(htc_conversion* conversion,
+                                      htc_conversion_write_fn write,
+                                      void* write_context);
+
+/* Statistics of the finished conversion. */
+uint64_t htc_conversion_get_event_count(const htc_conversion* conversion);
+uint64_t htc_conversion_get_failed_event_count(const htc_conversion* conversion);
+
+/* Message of the last error, or NULL; valid until the next call on the conversion. */
+const char* htc_conversion_get_error(const htc_conversion* conversion);
+
+/* Accepts NULL. */
+void htc_conversion_destroy(htc_conversion* conversion);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HAWKTRACER_CONVERTER_H */
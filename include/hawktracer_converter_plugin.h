@@ -0,0 +1,107 @@
+/*
+ * Interface of hawktracer-converter plugins.
+ *
+ * A plugin is a shared library exporting HAWKTRACER_CONVERTER_PLUGIN_ENTRY,
+ * which returns a descriptor of the converter. Structures are only ever
+ * extended at the end, and every change of the interface bumps
+ * HTC_PLUGIN_ABI_VERSION; plugins built for a different version are rejected.
+ */
+#ifndef HAWKTRACER_CONVERTER_PLUGIN_H
+#define HAWKTRACER_CONVERTER_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define HTC_PLUGIN_ABI_VERSION 1u
+
+#if defined(_WIN32)
+#define HTC_PLUGIN_EXPORT __declspec(dllexport)
+#else
+#define HTC_PLUGIN_EXPORT __attribute__((visibility("default")))
+#endif
+
+#define HAWKTRACER_CONVERTER_PLUGIN_ENTRY hawktracer_converter_plugin_entry
+
+/* Writes data to the converter output; returns 0 on success. */
+typedef int (*htc_write_fn)(void* write_context, const uint8_t* data, size_t size);
+
+#define HTC_VALUE_SIGNED 0u
+#define HTC_VALUE_UNSIGNED 1u
+#define HTC_VALUE_STRING 2u
+
+typedef struct
+{
+    const char* name;
+    /* One of HTC_VALUE_*; only the matching value member is set. */
+    uint32_t value_type;
+    int64_t signed_value;
+    uint64_t unsigned_value;
+    const char* string_value;
+} htc_field;
+
+typedef struct
+{
+    uint32_t klass_id;
+    /* NULL if the klass is not known. */
+    const char* klass_name;
+    /* Resolved label; NULL if the event doesn't have one. */
+    const char* label;
+    /* NULL if no category is mapped. */
+    const char* category;
+    const htc_field* fields;
+    size_t field_count;
+} htc_event;
+
+#define HTC_OPTION_BOOL 0u
+#define HTC_OPTION_INTEGER 1u
+#define HTC_OPTION_STRING 2u
+#define HTC_OPTION_CHOICE 3u
+
+typedef struct
+{
+    const char* name;
+    /* One of HTC_OPTION_*. */
+    uint32_t option_type;
+    /* '|'-separated list of values of a HTC_OPTION_CHOICE option, NULL otherwise. */
+    const char* choices;
+    const char* default_value;
+    const char* help;
+} htc_option_spec;
+
+typedef struct
+{
+    const char* name;
+    const char* value;
+} htc_option_value;
+
+typedef struct
+{
+    /* Must be HTC_PLUGIN_ABI_VERSION. */
+    uint32_t abi_version;
+    /* Format name, as used with --format. */
+    const char* name;
+    const htc_option_spec* options;
+    size_t option_count;
+    /* Returns NULL on failure. Every declared option is passed, with either
+     * a user-defined or a default value. Before the conversion, a converter
+     * writing nowhere is created and destroyed to check the options. */
+    void* (*create)(htc_write_fn write, void* write_context,
+                    const htc_option_value* options, size_t option_count);
+    /* Returns 0 on success. The event, and all the strings it points to,
+     * are only valid during the call. */
+    int (*process_event)(void* converter, const htc_event* event);
+    /* Finishes the output and releases the converter. */
+    void (*destroy)(void* converter);
+} htc_plugin_descriptor;
+
+HTC_PLUGIN_EXPORT const htc_plugin_descriptor* HAWKTRACER_CONVERTER_PLUGIN_ENTRY(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HAWKTRACER_CONVERTER_PLUGIN_H */
@@ -0,0 +1,256 @@
+/// Compression of the converter output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Compression, String> {
+        match value {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression '{}'", value)),
+        }
+    }
+}
+
+impl Compression {
+    /// Detects compression from the extension of the file, e.g. `trace.json.gz`.
+    pub fn from_path(path: &str) -> Option<Compression> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        [Compression::Gzip, Compression::Zstd]
+            .iter()
+            .find(|compression| extension.eq_ignore_ascii_case(compression.get_file_extension()))
+            .copied()
+    }
+
+    /// Extension (without the dot) appended to names of compressed files.
+    pub fn get_file_extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    pub fn get_level_range(&self) -> std::ops::RangeInclusive<i32> {
+        match self {
+            Compression::Gzip => 0..=9,
+            Compression::Zstd => zstd::compression_level_range(),
+        }
+    }
+
+    pub fn get_default_level(&self) -> i32 {
+        match self {
+            Compression::Gzip => 6,
+            Compression::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+
+    pub fn check_level(&self, level: i32) -> Result<(), String> {
+        let range = self.get_level_range();
+        if range.contains(&level) {
+            Ok(())
+        } else {
+            Err(format!(
+                "{:?} compression level must be between {} and {}",
+                self,
+                range.start(),
+                range.end()
+            ))
+        }
+    }
+
+    /// Compresses everything written to the returned writer. Flushing the writer
+    /// finishes the compressed stream (no data can be written after), so errors
+    /// of finishing it are reported; converters flush the output once, when
+    /// they're finished. A writer dropped unflushed finishes the stream, ignoring
+    /// errors. The default level is used if `level` is not set.
+    pub fn wrap_writer(
+        &self,
+        writable: Box<dyn std::io::Write>,
+        level: Option<i32>,
+    ) -> std::io::Result<Box<dyn std::io::Write>> {
+        let level = level.unwrap_or_else(|| self.get_default_level());
+        self.check_level(level)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+        let encoder = match self {
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writable,
+                flate2::Compression::new(level as u32),
+            )),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writable, level)?),
+        };
+        Ok(Box::new(CompressedWriter {
+            encoder,
+            is_finished: false,
+        }))
+    }
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Box<dyn std::io::Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn std::io::Write>>),
+}
+
+struct CompressedWriter {
+    encoder: Encoder,
+    is_finished: bool,
+}
+
+impl CompressedWriter {
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.is_finished = true;
+        match &mut self.encoder {
+            Encoder::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl std::io::Write for CompressedWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.is_finished {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "compressed stream is already finished",
+            ));
+        }
+        match &mut self.encoder {
+            Encoder::Gzip(encoder) => encoder.write(data),
+            Encoder::Zstd(encoder) => encoder.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.is_finished {
+            Ok(())
+        } else {
+            self.finish()
+        }
+    }
+}
+
+impl Drop for CompressedWriter {
+    fn drop(&mut self) {
+        if !self.is_finished {
+            // Errors can't be reported from drop.
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<std::vec::Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn compress(compression: Compression, data: &[u8]) -> std::vec::Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let mut writer = compression
+            .wrap_writer(Box::new(buffer.clone()), None)
+            .unwrap();
+        writer.write_all(data).unwrap();
+        drop(writer);
+        let compressed = buffer.0.borrow().clone();
+        compressed
+    }
+
+    #[test]
+    fn compressed_output_should_be_finished_on_drop() {
+        let data = "[{\"name\":\"event\",\"ph\":\"X\"},".repeat(100);
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&compress(Compression::Gzip, data.as_bytes())[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        let compressed = compress(Compression::Zstd, data.as_bytes());
+        assert!(compressed.len() < data.len());
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data.as_bytes());
+    }
+
+    #[test]
+    fn flush_should_finish_compressed_stream_and_report_errors() {
+        struct FailingWriter;
+
+        impl std::io::Write for FailingWriter {
+            fn write(&mut self, _data: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "disk full"))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for compression in &[Compression::Gzip, Compression::Zstd] {
+            let buffer = SharedBuffer::default();
+            let mut writer = compression
+                .wrap_writer(Box::new(buffer.clone()), None)
+                .unwrap();
+            writer.write_all(b"data").unwrap();
+            writer.flush().unwrap();
+            let compressed = buffer.0.borrow().clone();
+            assert!(!compressed.is_empty());
+            assert!(writer.write_all(b"more data").is_err());
+            drop(writer);
+            assert_eq!(*buffer.0.borrow(), compressed);
+
+            let mut writer = compression
+                .wrap_writer(Box::new(FailingWriter), None)
+                .unwrap();
+            assert!(writer
+                .write_all(b"data")
+                .and_then(|_| writer.flush())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn compression_should_be_detected_from_path() {
+        assert_eq!(
+            Compression::from_path("trace.json.gz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_path("trace.json.ZST"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_path("trace.json"), None);
+        assert_eq!(Compression::from_path("gz"), None);
+    }
+
+    #[test]
+    fn wrap_writer_should_fail_for_invalid_level() {
+        assert!(Compression::Gzip
+            .wrap_writer(Box::new(std::io::sink()), Some(10))
+            .is_err());
+        assert!(Compression::Zstd.check_level(19).is_ok());
+        assert!(Compression::Zstd.check_level(100).is_err());
+    }
+}
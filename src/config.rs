@@ -0,0 +1,216 @@
+use serde::Deserialize;
+
+/// Converter configuration, loaded from a TOML file. Command line
+/// arguments take precedence over values defined in the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub labels: LabelConfig,
+    pub demangle: DemangleConfig,
+    pub symbols: SymbolConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelConfig {
+    /// Event fields checked (in order) for the label.
+    pub fields: Option<std::vec::Vec<String>>,
+    /// Label fields for specific event klasses, checked before the default ones.
+    pub klass_fields: std::collections::HashMap<String, std::vec::Vec<String>>,
+    /// Template composing the label from event fields, e.g. `{label}(id={id})`.
+    pub template: Option<String>,
+    /// Label templates for specific event klasses.
+    pub klass_templates: std::collections::HashMap<String, String>,
+    /// Files with regex rewrite rules applied to every label, in order.
+    pub rewrite_rules: std::vec::Vec<String>,
+    /// Event klasses carrying mappings, in addition to `HT_StringMappingEvent`.
+    pub mapping_klasses: std::vec::Vec<MappingKlassConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingKlassConfig {
+    pub klass: String,
+    /// Defaults to `identifier`.
+    pub identifier: Option<String>,
+    /// Defaults to `label`.
+    pub label: Option<String>,
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DemangleConfig {
+    pub enabled: bool,
+    pub drop_template_args: bool,
+    pub drop_arguments: bool,
+    pub keep_segments: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SymbolConfig {
+    /// Binaries used for resolving code addresses, as `PATH[@LOAD_BASE]`.
+    pub files: std::vec::Vec<String>,
+    /// Append source file and line to resolved labels (enabled by default).
+    pub locations: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Parse(err) => write!(f, "invalid configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn load_from_file(path: &str) -> Result<Config, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Config::load_from_str(&content)
+    }
+
+    pub fn load_from_str(content: &str) -> Result<Config, ConfigError> {
+        toml::from_str(content).map_err(ConfigError::Parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_from_str_should_read_label_fields() {
+        let config = Config::load_from_str(
+            r#"
+            [labels]
+            fields = ["function", "name"]
+
+            [labels.klass_fields]
+            MyIoEvent = ["path"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.labels.fields.unwrap(),
+            vec!["function".to_owned(), "name".to_owned()]
+        );
+        assert_eq!(config.labels.klass_fields["MyIoEvent"], vec!["path"]);
+    }
+
+    #[test]
+    fn load_from_str_should_read_label_templates() {
+        let config = Config::load_from_str(
+            r#"
+            [labels]
+            template = "{label} [{category}]"
+
+            [labels.klass_templates]
+            LoadEvent = "load_texture(id={id})"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.labels.template.unwrap(), "{label} [{category}]");
+        assert_eq!(
+            config.labels.klass_templates["LoadEvent"],
+            "load_texture(id={id})"
+        );
+    }
+
+    #[test]
+    fn load_from_str_should_read_rewrite_rules() {
+        let config = Config::load_from_str(
+            r#"
+            [labels]
+            rewrite_rules = ["frames.rules", "tiles.rules"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.labels.rewrite_rules,
+            vec!["frames.rules", "tiles.rules"]
+        );
+        assert!(Config::load_from_str("")
+            .unwrap()
+            .labels
+            .rewrite_rules
+            .is_empty());
+    }
+
+    #[test]
+    fn load_from_str_should_read_mapping_klasses() {
+        let config = Config::load_from_str(
+            r#"
+            [[labels.mapping_klasses]]
+            klass = "MyMappingEvent"
+            identifier = "key"
+            category = "group"
+            "#,
+        )
+        .unwrap();
+
+        let mapping_klass = &config.labels.mapping_klasses[0];
+        assert_eq!(mapping_klass.klass, "MyMappingEvent");
+        assert_eq!(mapping_klass.identifier.as_deref(), Some("key"));
+        assert!(mapping_klass.label.is_none());
+        assert_eq!(mapping_klass.category.as_deref(), Some("group"));
+    }
+
+    #[test]
+    fn load_from_str_should_read_demangle_options() {
+        let config = Config::load_from_str(
+            r#"
+            [demangle]
+            enabled = true
+            drop_arguments = true
+            keep_segments = 2
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.demangle.enabled);
+        assert!(config.demangle.drop_arguments);
+        assert!(!config.demangle.drop_template_args);
+        assert_eq!(config.demangle.keep_segments, Some(2));
+    }
+
+    #[test]
+    fn load_from_str_should_read_symbol_files() {
+        let config = Config::load_from_str(
+            r#"
+            [symbols]
+            files = ["app@0x400000", "libfoo.so"]
+            locations = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.symbols.files, vec!["app@0x400000", "libfoo.so"]);
+        assert_eq!(config.symbols.locations, Some(false));
+    }
+
+    #[test]
+    fn load_from_str_should_accept_empty_config() {
+        let config = Config::load_from_str("").unwrap();
+
+        assert!(config.labels.fields.is_none());
+        assert!(config.labels.klass_fields.is_empty());
+    }
+
+    #[test]
+    fn load_from_str_should_reject_unknown_keys() {
+        assert!(Config::load_from_str("[labels]\nfield = [\"name\"]").is_err());
+    }
+}
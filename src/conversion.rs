@@ -0,0 +1,591 @@
+use crate::Compression;
+use crate::Converter;
+use crate::ConverterManager;
+use crate::ConverterOptions;
+use crate::ErrorStats;
+use crate::EventFilter;
+use crate::FilteredConverter;
+use crate::LabelGetter;
+use crate::LabelMap;
+use crate::MappingFileError;
+use crate::OptionError;
+use crate::TimeOrigin;
+use crate::TimeTransform;
+use crate::TransformedConverter;
+use hawktracer_parser::event_reader::EventReader;
+use hawktracer_parser::{CoreEventKlassId, Event, EventKlassRegistry};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Error which prevents the conversion from starting.
+#[derive(Debug)]
+pub enum SetupError {
+    UnknownFormat(String),
+    Option(OptionError),
+    MappingFile(String, MappingFileError),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for SetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SetupError::UnknownFormat(format) => write!(f, "unknown format '{}'", format),
+            SetupError::Option(err) => write!(f, "{}", err),
+            SetupError::MappingFile(path, err) => {
+                write!(f, "failed to load mapping from {}: {}", path, err)
+            }
+            SetupError::Io(err) => write!(f, "unable to read source: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+impl From<OptionError> for SetupError {
+    fn from(err: OptionError) -> SetupError {
+        SetupError::Option(err)
+    }
+}
+
+impl From<std::io::Error> for SetupError {
+    fn from(err: std::io::Error) -> SetupError {
+        SetupError::Io(err)
+    }
+}
+
+/// Statistics of a finished conversion.
+pub struct ConversionStats {
+    pub errors: ErrorStats,
+    /// Events skipped, because they preceded the time origin.
+    pub events_before_time_origin: u64,
+    /// Events whose time was out of range after the time transform.
+    pub clamped_times: u64,
+    /// Number of label identifiers without a mapping.
+    pub unresolved_labels: usize,
+}
+
+/// Event accepted by the filter, with time transformed and the label resolved.
+pub struct ResolvedEvent {
+    pub event: Event,
+    pub klass_name: Option<String>,
+    pub label: Option<String>,
+    pub category: Option<String>,
+}
+
+enum Source {
+    File(String),
+    Reader(Box<dyn std::io::Read>),
+}
+
+/// Converts a HawkTracer capture in-process, e.g.:
+/// ```no_run
+/// # use hawktracer_converter_lib::Conversion;
+/// let stats = Conversion::new("trace.htdump")
+///     .format("chrome-tracing")
+///     .map_file("labels.map")
+///     .run(Box::new(std::fs::File::create("trace.json").unwrap()))
+///     .unwrap();
+/// println!("{} events failed", stats.errors.get_failed_event_count());
+/// ```
+pub struct Conversion {
+    source: Source,
+    format: String,
+    options: std::vec::Vec<(String, String)>,
+    converter_manager: Option<ConverterManager>,
+    map_files: std::vec::Vec<String>,
+    label_fields: std::vec::Vec<String>,
+    is_deferred: bool,
+    filter: Option<EventFilter>,
+    time_origin: Option<TimeOrigin>,
+    time_offset: Option<i64>,
+    time_scale: Option<f64>,
+    running_flag: Option<Arc<AtomicBool>>,
+    compression: Option<(Compression, Option<i32>)>,
+}
+
+impl Conversion {
+    /// Conversion of a capture file.
+    pub fn new(path: &str) -> Conversion {
+        Conversion::with_source(Source::File(path.to_owned()))
+    }
+
+    /// Conversion of a capture read from any source, e.g. a socket.
+    pub fn from_reader(reader: Box<dyn std::io::Read>) -> Conversion {
+        Conversion::with_source(Source::Reader(reader))
+    }
+
+    fn with_source(source: Source) -> Conversion {
+        Conversion {
+            source,
+            format: "chrome-tracing".to_owned(),
+            options: vec![],
+            converter_manager: None,
+            map_files: vec![],
+            label_fields: vec!["label".to_owned(), "name".to_owned()],
+            is_deferred: false,
+            filter: None,
+            time_origin: None,
+            time_offset: None,
+            time_scale: None,
+            running_flag: None,
+            compression: None,
+        }
+    }
+
+    /// Output format; `chrome-tracing` by default.
+    pub fn format(mut self, format: &str) -> Conversion {
+        self.format = format.to_owned();
+        self
+    }
+
+    /// Option of the converter, see `ConverterFactory::get_options`.
+    pub fn option(mut self, name: &str, value: &str) -> Conversion {
+        self.options.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Manager providing the converters, e.g. with plugins loaded.
+    pub fn converter_manager(mut self, converter_manager: ConverterManager) -> Conversion {
+        self.converter_manager = Some(converter_manager);
+        self
+    }
+
+    pub fn map_file(mut self, path: &str) -> Conversion {
+        self.map_files.push(path.to_owned());
+        self
+    }
+
+    /// Event fields used as a label, in order of precedence; `label` and
+    /// `name` by default.
+    pub fn label_fields(mut self, fields: &[&str]) -> Conversion {
+        self.label_fields = fields.iter().map(|field| (*field).to_owned()).collect();
+        self
+    }
+
+    /// Scans capture files for mappings before the conversion, so events
+    /// preceding their mapping events get labels.
+    pub fn deferred_labels(mut self, is_deferred: bool) -> Conversion {
+        self.is_deferred = is_deferred;
+        self
+    }
+
+    /// Converts only events accepted by the filter. Its label getter is
+    /// replaced with a copy of the conversion's one, so it knows the same mappings.
+    pub fn filter(mut self, filter: EventFilter) -> Conversion {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn time_origin(mut self, origin: TimeOrigin) -> Conversion {
+        self.time_origin = Some(origin);
+        self
+    }
+
+    /// Offset (in nanoseconds) added to timestamps.
+    pub fn time_offset(mut self, offset: i64) -> Conversion {
+        self.time_offset = Some(offset);
+        self
+    }
+
+    pub fn time_scale(mut self, scale: f64) -> Conversion {
+        self.time_scale = Some(scale);
+        self
+    }
+
+    /// The conversion stops once the flag is cleared, e.g. on Ctrl+C.
+    pub fn running_flag(mut self, running_flag: Arc<AtomicBool>) -> Conversion {
+        self.running_flag = Some(running_flag);
+        self
+    }
+
+    /// Compresses the output; the default level is used if `level` is not set.
+    pub fn compression(mut self, compression: Compression, level: Option<i32>) -> Conversion {
+        self.compression = Some((compression, level));
+        self
+    }
+
+    /// Converts the capture, writing the output to `writable`.
+    pub fn run(mut self, writable: Box<dyn std::io::Write>) -> Result<ConversionStats, SetupError> {
+        let converter_manager = self.converter_manager.take().unwrap_or_default();
+        let schema = converter_manager
+            .get_options(&self.format)
+            .ok_or_else(|| SetupError::UnknownFormat(self.format.clone()))?;
+        let option_values: std::vec::Vec<(&str, &str)> = self
+            .options
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let options = ConverterOptions::parse(&schema, &option_values)?;
+        converter_manager.check_options(&self.format, &options)?;
+
+        let writable = match self.compression {
+            Some((compression, level)) => compression.wrap_writer(writable, level)?,
+            None => writable,
+        };
+        let (mut reader, setup) = self.create_pipeline()?;
+        let converter = converter_manager
+            .create_converter_with_options(
+                &self.format,
+                writable,
+                setup.get_label_getter().clone(),
+                &options,
+            )
+            .ok_or_else(|| SetupError::UnknownFormat(self.format.clone()))?;
+        let mut converter = setup.wrap_converter(converter);
+
+        let mut reg = EventKlassRegistry::new();
+        let mut errors = ErrorStats::new();
+        while self.is_running() {
+            match reader.read_event(&mut reg) {
+                Ok(event) => {
+                    let event = event.flat_event();
+                    let result = converter.process_event(&event, &reg);
+                    errors.record(&event, &reg, &result);
+                }
+                Err(_) => break,
+            }
+        }
+
+        // Some converters write the output only once they're finished.
+        errors.record_finish(&converter.finish());
+
+        let (events_before_time_origin, clamped_times) =
+            converter.get_time_transform().map_or((0, 0), |transform| {
+                (transform.get_dropped_count(), transform.get_clamped_count())
+            });
+        let unresolved_labels = converter.get_label_getter().map_or(0, |label_getter| {
+            label_getter.get_label_map().get_unresolved().len()
+        });
+        Ok(ConversionStats {
+            errors,
+            events_before_time_origin,
+            clamped_times,
+            unresolved_labels,
+        })
+    }
+
+    /// Iterates over events which would be passed to a converter; core events
+    /// (describing klasses) and mapping events are not included.
+    pub fn events(mut self) -> Result<ResolvedEvents, SetupError> {
+        let (reader, setup) = self.create_pipeline()?;
+        Ok(ResolvedEvents {
+            reader,
+            reg: EventKlassRegistry::new(),
+            label_getter: setup.label_getter,
+            filter: setup.filter,
+            transform: setup.transform,
+            running_flag: self.running_flag,
+        })
+    }
+
+    fn is_running(&self) -> bool {
+        self.running_flag
+            .as_ref()
+            .map_or(true, |flag| flag.load(Ordering::SeqCst))
+    }
+
+    fn create_label_getter(&self) -> Result<LabelGetter, SetupError> {
+        let mut label_map = LabelMap::new();
+        for path in &self.map_files {
+            label_map
+                .load_from_file(path)
+                .map_err(|err| SetupError::MappingFile(path.clone(), err))?;
+        }
+        Ok(LabelGetter::new(label_map, self.label_fields.clone()))
+    }
+
+    fn create_pipeline(&mut self) -> Result<(EventReader, ConversionSetup), SetupError> {
+        let mut setup = ConversionSetup::new(self.create_label_getter()?);
+        if self.time_origin.is_some() || self.time_offset.is_some() || self.time_scale.is_some() {
+            let mut transform = TimeTransform::new(setup.get_label_getter().clone());
+            if let Some(origin) = self.time_origin.take() {
+                transform.set_origin(origin);
+            }
+            transform.set_offset(self.time_offset.unwrap_or(0));
+            transform.set_scale(self.time_scale.unwrap_or(1.0));
+            setup.set_time_transform(transform);
+        }
+
+        let source = std::mem::replace(&mut self.source, Source::File(String::new()));
+        let reader = match source {
+            Source::File(path) => {
+                if self.is_deferred || setup.has_time_transform() {
+                    setup.prescan(Box::new(std::fs::File::open(&path)?), self.is_deferred);
+                }
+                create_event_reader(Box::new(std::fs::File::open(path)?))
+            }
+            Source::Reader(reader) => create_event_reader(reader),
+        };
+
+        if let Some(mut filter) = self.filter.take() {
+            filter.set_label_getter(setup.get_label_getter().clone());
+            setup.set_filter(filter);
+        }
+        Ok((reader, setup))
+    }
+}
+
+/// Label state, time transform and filter shared by all the outputs of a
+/// conversion. Every converter (and the filter, and the transform) needs its
+/// own label getter; they should be copies of `get_label_getter()`, made once
+/// mappings are prescanned, so they all know the same mappings.
+pub struct ConversionSetup {
+    label_getter: LabelGetter,
+    transform: Option<TimeTransform>,
+    filter: Option<EventFilter>,
+}
+
+impl ConversionSetup {
+    pub fn new(label_getter: LabelGetter) -> ConversionSetup {
+        ConversionSetup {
+            label_getter,
+            transform: None,
+            filter: None,
+        }
+    }
+
+    pub fn get_label_getter(&self) -> &LabelGetter {
+        &self.label_getter
+    }
+
+    /// Should be set before prescanning, so the time origin is found.
+    pub fn set_time_transform(&mut self, transform: TimeTransform) {
+        self.transform = Some(transform);
+    }
+
+    pub fn has_time_transform(&self) -> bool {
+        self.transform.is_some()
+    }
+
+    pub fn set_filter(&mut self, filter: EventFilter) {
+        self.filter = Some(filter);
+    }
+
+    /// Reads the whole capture before converting it: collects mappings (if
+    /// `learn_mappings` is set), so events preceding their mapping events get
+    /// labels, and finds the time origin, so no events precede it.
+    pub fn prescan(&mut self, source: Box<dyn std::io::Read>, learn_mappings: bool) {
+        let mut reader = create_event_reader(source);
+        let mut reg = EventKlassRegistry::new();
+        while let Ok(event) = reader.read_event(&mut reg) {
+            let event = event.flat_event();
+            if let Some(transform) = self.transform.as_mut() {
+                transform.prescan_event(&event, &reg);
+            }
+            if learn_mappings && self.label_getter.is_mapping_event(&event) {
+                self.label_getter.learn_mapping(&event);
+            }
+        }
+    }
+
+    /// Wraps the converter, so it gets events accepted by the filter, with
+    /// transformed time; filters see the transformed time too.
+    pub fn wrap_converter(self, converter: Box<dyn Converter>) -> Box<dyn Converter> {
+        let mut converter = converter;
+        if let Some(filter) = self.filter {
+            converter = Box::new(FilteredConverter::new(filter, converter));
+        }
+        if let Some(transform) = self.transform {
+            converter = Box::new(TransformedConverter::new(transform, converter));
+        }
+        converter
+    }
+}
+
+fn create_event_reader(source: Box<dyn std::io::Read>) -> EventReader {
+    let provider = hawktracer_parser::data_provider::DataProvider::new(source);
+    EventReader::new(provider)
+}
+
+/// Iterator over resolved events of a capture, see `Conversion::events`.
+pub struct ResolvedEvents {
+    reader: EventReader,
+    reg: EventKlassRegistry,
+    label_getter: LabelGetter,
+    filter: Option<EventFilter>,
+    transform: Option<TimeTransform>,
+    running_flag: Option<Arc<AtomicBool>>,
+}
+
+impl ResolvedEvents {
+    /// Klasses of the events read so far.
+    pub fn get_registry(&self) -> &EventKlassRegistry {
+        &self.reg
+    }
+
+    pub fn get_label_getter(&self) -> &LabelGetter {
+        &self.label_getter
+    }
+
+    fn resolve(&mut self, event: Event) -> Option<ResolvedEvent> {
+        let event = match self.transform.as_mut() {
+            Some(transform) => transform.transform(&event, &self.reg)?,
+            None => event,
+        };
+        if let Some(filter) = self.filter.as_mut() {
+            if !filter.is_accepted(&event, &self.reg) {
+                return None;
+            }
+        }
+        if CoreEventKlassId::is_core_klass(event.get_klass_id())
+            || self.label_getter.is_mapping_event(&event)
+        {
+            // Mappings are learned by the label getter.
+            self.label_getter.get_label(&event, &self.reg);
+            return None;
+        }
+
+        let klass_name = self
+            .reg
+            .get_klass_by_id(event.get_klass_id())
+            .map(|klass| klass.get_name().clone());
+        let category = self.label_getter.get_category(&event, &self.reg).cloned();
+        let label = self
+            .label_getter
+            .get_label(&event, &self.reg)
+            .map(|(_, label)| label.clone());
+        Some(ResolvedEvent {
+            event,
+            klass_name,
+            label,
+            category,
+        })
+    }
+}
+
+impl Iterator for ResolvedEvents {
+    type Item = ResolvedEvent;
+
+    fn next(&mut self) -> Option<ResolvedEvent> {
+        while self
+            .running_flag
+            .as_ref()
+            .map_or(true, |flag| flag.load(Ordering::SeqCst))
+        {
+            let event = self.reader.read_event(&mut self.reg).ok()?.flat_event();
+            if let Some(event) = self.resolve(event) {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FilterExpression;
+
+    const TEST_TRACE: &str = "resources/test/test_trace.htdump";
+
+    #[derive(Clone)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<std::vec::Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn run_should_convert_capture_and_return_stats() {
+        let mut filter = EventFilter::default();
+        filter.set_expression(FilterExpression::parse("label == \"_test\"").unwrap());
+        let buffer = SharedBuffer(Default::default());
+        let stats = Conversion::new(TEST_TRACE)
+            .format("chrome-tracing")
+            .filter(filter)
+            .time_origin(TimeOrigin::FirstEvent)
+            .run(Box::new(buffer.clone()))
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(output.matches("\"name\":\"_test\"").count(), 1);
+        assert!(!output.contains("_second_tracepoint"));
+        assert_eq!(stats.errors.get_failed_event_count(), 0);
+        assert_eq!(stats.events_before_time_origin, 0);
+    }
+
+    #[test]
+    fn run_should_report_errors_of_finishing_the_output() {
+        struct FailingWriter;
+
+        impl std::io::Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "disk full"))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // Flame graphs are written only once the conversion is finished.
+        let stats = Conversion::new(TEST_TRACE)
+            .format("flamegraph")
+            .run(Box::new(FailingWriter))
+            .unwrap();
+
+        assert_eq!(stats.errors.get_failed_event_count(), 0);
+        assert_eq!(stats.errors.get_finish_errors(), ["I/O error: disk full"]);
+    }
+
+    #[test]
+    fn run_should_fail_for_unknown_format_or_option() {
+        let result = Conversion::new(TEST_TRACE)
+            .format("unknown")
+            .run(Box::new(std::io::sink()));
+        assert!(matches!(result, Err(SetupError::UnknownFormat(_))));
+
+        let result = Conversion::new(TEST_TRACE)
+            .option("precision", "ms")
+            .run(Box::new(std::io::sink()));
+        assert!(matches!(result, Err(SetupError::Option(_))));
+    }
+
+    #[test]
+    fn setup_prescan_should_learn_mappings_only_if_asked() {
+        let written_mappings = |learn_mappings| {
+            let mut setup = ConversionSetup::new(LabelGetter::new(LabelMap::new(), vec![]));
+            setup.prescan(
+                Box::new(std::fs::File::open(TEST_TRACE).unwrap()),
+                learn_mappings,
+            );
+            let mut written = std::vec::Vec::new();
+            setup
+                .get_label_getter()
+                .get_label_map()
+                .write_mapping(&mut written, crate::MappingFileFormat::Text)
+                .unwrap();
+            written
+        };
+
+        assert!(!written_mappings(true).is_empty());
+        assert!(written_mappings(false).is_empty());
+    }
+
+    #[test]
+    fn events_should_return_resolved_events() {
+        let events: std::vec::Vec<ResolvedEvent> = Conversion::new(TEST_TRACE)
+            .time_origin(TimeOrigin::FirstEvent)
+            .events()
+            .unwrap()
+            .collect();
+
+        assert_eq!(events.len(), 12);
+        let labeled: std::vec::Vec<&ResolvedEvent> = events
+            .iter()
+            .filter(|event| event.klass_name.as_deref() == Some("HT_CallstackIntEvent"))
+            .collect();
+        assert_eq!(labeled.len(), 11);
+        assert_eq!(labeled[0].label.as_deref(), Some("_second_tracepoint"));
+        assert!(labeled
+            .iter()
+            .all(|event| event.event.get_value_u64("timestamp").unwrap() < 1_000_000_000));
+    }
+}
@@ -1,6 +1,15 @@
 use crate::converters::Converter;
 use crate::converters::ConverterFactory;
+use crate::ConverterMetadata;
+use crate::ConverterOptions;
 use crate::LabelGetter;
+use crate::OptionError;
+use crate::OptionSpec;
+use crate::PluginConverterFactory;
+use crate::PluginError;
+
+// Converters write every event separately; without buffering, each write is a syscall.
+const OUTPUT_BUFFER_SIZE: usize = 256 * 1024;
 
 pub struct ConverterManager {
     factories: std::vec::Vec<Box<dyn ConverterFactory>>,
@@ -26,20 +35,105 @@ impl ConverterManager {
         self.register_static_factory(crate::converters::DebugConverterFactory {});
         self.register_static_factory(crate::converters::JSONDebugConverterFactory {});
         self.register_static_factory(crate::converters::FlamegraphConverterFactory {});
+        #[cfg(feature = "script")]
+        self.register_static_factory(crate::converters::ScriptConverterFactory {});
+    }
+
+    fn find_factory(&self, name: &str) -> Option<&dyn ConverterFactory> {
+        self.factories
+            .iter()
+            .find(|factory| factory.get_name() == name)
+            .map(|factory| factory.as_ref())
     }
 
+    /// Creates a converter with default values of all its options.
     pub fn create_converter(
         &self,
         name: &str,
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
     ) -> Option<Box<dyn Converter>> {
-        for factory in &self.factories {
-            if name == factory.get_name() {
-                return Some(factory.construct(writable, label_getter));
-            }
+        let options = ConverterOptions::defaults(&self.get_options(name)?).ok()?;
+        self.create_converter_with_options(name, writable, label_getter, &options)
+    }
+
+    /// Creates a converter; `options` should be parsed against the schema
+    /// returned by `get_options`. The output is buffered, and flushed when
+    /// the converter is finished (see `Converter::finish`).
+    pub fn create_converter_with_options(
+        &self,
+        name: &str,
+        writable: Box<dyn std::io::Write>,
+        label_getter: LabelGetter,
+        options: &ConverterOptions,
+    ) -> Option<Box<dyn Converter>> {
+        self.find_factory(name).map(|factory| {
+            factory.construct(
+                Box::new(std::io::BufWriter::with_capacity(
+                    OUTPUT_BUFFER_SIZE,
+                    writable,
+                )),
+                label_getter,
+                options,
+            )
+        })
+    }
+
+    /// Returns options declared by the converter, or `None` if the converter doesn't exist.
+    pub fn get_options(&self, name: &str) -> Option<std::vec::Vec<OptionSpec>> {
+        self.find_factory(name).map(|factory| factory.get_options())
+    }
+
+    /// Returns metadata of the converter, or `None` if the converter doesn't exist.
+    pub fn get_metadata(&self, name: &str) -> Option<ConverterMetadata> {
+        self.find_factory(name)
+            .map(|factory| factory.get_metadata())
+    }
+
+    /// Returns the first converter writing files with the extension (case-insensitive).
+    pub fn find_converter_by_extension(&self, extension: &str) -> Option<&str> {
+        self.factories
+            .iter()
+            .find(|factory| {
+                factory
+                    .get_metadata()
+                    .file_extension
+                    .eq_ignore_ascii_case(extension)
+            })
+            .map(|factory| factory.get_name())
+    }
+
+    /// Checks option values of the converter; fails if the converter doesn't exist.
+    pub fn check_options(&self, name: &str, options: &ConverterOptions) -> Result<(), OptionError> {
+        match self.find_factory(name) {
+            Some(factory) => factory.check_options(options),
+            None => Err(OptionError {
+                option: String::new(),
+                reason: format!("unknown format '{}'", name),
+            }),
+        }
+    }
+
+    /// Creates a converter forwarding events to converters of all the given formats;
+    /// returns `None` if any of the formats doesn't exist.
+    pub fn create_composite_converter(
+        &self,
+        outputs: std::vec::Vec<(&str, Box<dyn std::io::Write>, LabelGetter, ConverterOptions)>,
+    ) -> Option<Box<dyn Converter>> {
+        let mut converters = outputs
+            .into_iter()
+            .map(|(name, writable, label_getter, options)| {
+                self.create_converter_with_options(name, writable, label_getter, &options)
+            })
+            .collect::<Option<std::vec::Vec<_>>>()?;
+
+        if converters.len() == 1 {
+            converters.pop()
+        } else {
+            Some(Box::new(crate::converters::CompositeConverter::new(
+                converters,
+            )))
         }
-        None
     }
 
     pub fn get_converters(&self) -> std::vec::Vec<&str> {
@@ -55,29 +149,72 @@ impl ConverterManager {
     pub fn register_static_factory<T: ConverterFactory + 'static>(&mut self, format: T) {
         self.factories.push(Box::new(format));
     }
+
+    /// Loads a converter plugin from a shared library; returns the name of the format.
+    pub fn load_plugin(&mut self, path: &std::path::Path) -> Result<String, PluginError> {
+        let factory = PluginConverterFactory::load(path)?;
+        let name = factory.get_name().to_owned();
+        if self.find_factory(&name).is_some() {
+            return Err(PluginError::InvalidDescriptor {
+                path: path.to_string_lossy().into_owned(),
+                reason: format!("format '{}' already exists", name),
+            });
+        }
+        self.register_static_factory(factory);
+        Ok(name)
+    }
+
+    /// Loads all the shared libraries from the directory as converter plugins.
+    pub fn load_plugins_from_directory(
+        &mut self,
+        directory: &std::path::Path,
+    ) -> Result<std::vec::Vec<String>, PluginError> {
+        let mut paths = std::vec::Vec::new();
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path.extension().and_then(|ext| ext.to_str())
+                    == Some(std::env::consts::DLL_EXTENSION)
+            {
+                paths.push(path);
+            }
+        }
+        // Directory order is not specified, and it matters for the default format.
+        paths.sort();
+
+        paths.iter().map(|path| self.load_plugin(path)).collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ConversionError;
     use crate::LabelMap;
+    use crate::OptionType;
     use std::iter::Iterator;
 
-    struct DummyConverter {}
+    struct DummyConverter {
+        fail: bool,
+    }
 
     impl Converter for DummyConverter {
         fn process_event(
             &mut self,
             _event: &hawktracer_parser::Event,
             _reg: &hawktracer_parser::EventKlassRegistry,
-        ) -> Result<(), Box<dyn std::error::Error>> {
-            Ok(())
+        ) -> Result<(), ConversionError> {
+            if self.fail {
+                Err(ConversionError::MissingField("dummy".to_owned()))
+            } else {
+                Ok(())
+            }
         }
     }
 
     impl DummyConverter {
-        pub fn new() -> DummyConverter {
-            DummyConverter {}
+        pub fn new(fail: bool) -> DummyConverter {
+            DummyConverter { fail }
         }
     }
 
@@ -88,13 +225,104 @@ mod tests {
             &self,
             _writable: Box<dyn std::io::Write>,
             _label_getter: LabelGetter,
+            options: &ConverterOptions,
         ) -> Box<dyn Converter> {
-            Box::new(DummyConverter::new())
+            Box::new(DummyConverter::new(
+                options.get_bool("fail").unwrap_or(false),
+            ))
         }
 
         fn get_name(&self) -> &str {
             "dummy"
         }
+
+        fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+            vec![OptionSpec::new(
+                "fail",
+                OptionType::Bool,
+                "false",
+                "Fail on every event",
+            )]
+        }
+    }
+
+    fn process_dummy_event(converter: &mut Box<dyn Converter>) -> bool {
+        converter
+            .process_event(
+                &hawktracer_parser::Event::new(1, std::collections::HashMap::new()),
+                &hawktracer_parser::EventKlassRegistry::new(),
+            )
+            .is_ok()
+    }
+
+    struct CountingWritable {
+        write_count: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl std::io::Write for CountingWritable {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.write_count.set(self.write_count.get() + 1);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn created_converter_should_buffer_output() {
+        let manager = ConverterManager::new();
+        let write_count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut converter = manager
+            .create_converter(
+                "chrome-tracing",
+                Box::new(CountingWritable {
+                    write_count: write_count.clone(),
+                }),
+                LabelGetter::new(LabelMap::new(), vec!["label".to_owned()]),
+            )
+            .unwrap();
+
+        let reg = hawktracer_parser::EventKlassRegistry::new();
+        for timestamp in 0..100 {
+            let mut values = std::collections::HashMap::new();
+            values.insert(
+                "timestamp".to_owned(),
+                hawktracer_parser::Value::U64(timestamp),
+            );
+            values.insert("duration".to_owned(), hawktracer_parser::Value::U64(10));
+            values.insert(
+                "label".to_owned(),
+                hawktracer_parser::Value::Str("event".to_owned()),
+            );
+            converter
+                .process_event(&hawktracer_parser::Event::new(100, values), &reg)
+                .unwrap();
+        }
+        assert_eq!(write_count.get(), 0);
+
+        drop(converter);
+        assert_eq!(write_count.get(), 1);
+    }
+
+    #[test]
+    fn find_converter_by_extension_should_return_first_matching_converter() {
+        let mut manager = ConverterManager::new();
+        manager.register_static_factory(DummyConverterFactory {});
+
+        assert_eq!(
+            manager.find_converter_by_extension("json"),
+            Some("chrome-tracing")
+        );
+        assert_eq!(
+            manager.find_converter_by_extension("HTML"),
+            Some("flamegraph")
+        );
+        assert_eq!(manager.find_converter_by_extension("out"), Some("dummy"));
+        assert_eq!(manager.find_converter_by_extension("httrace"), None);
+        assert!(!manager.get_metadata("flamegraph").unwrap().is_streaming);
+        assert!(manager.get_metadata("invalid-converter").is_none());
     }
 
     #[test]
@@ -111,6 +339,28 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn create_composite_converter_should_fail_if_any_converter_does_not_exist() {
+        let mut manager: ConverterManager = Default::default();
+        manager.register_static_factory(DummyConverterFactory {});
+
+        let outputs: std::vec::Vec<(&str, Box<dyn std::io::Write>, LabelGetter, ConverterOptions)> = vec![
+            (
+                "dummy",
+                Box::new(std::io::sink()),
+                LabelGetter::new(LabelMap::new(), vec![]),
+                ConverterOptions::default(),
+            ),
+            (
+                "invalid-converter",
+                Box::new(std::io::sink()),
+                LabelGetter::new(LabelMap::new(), vec![]),
+                ConverterOptions::default(),
+            ),
+        ];
+        assert!(manager.create_composite_converter(outputs).is_none());
+    }
+
     #[test]
     fn get_converters_should_return_newly_registered_converter() {
         let mut manager: ConverterManager = Default::default();
@@ -133,11 +383,27 @@ mod tests {
             .create_converter("dummy", Box::new(std::io::stdout()), label_getter)
             .unwrap();
 
-        assert!(converter
-            .process_event(
-                &hawktracer_parser::Event::new(1, std::collections::HashMap::new()),
-                &hawktracer_parser::EventKlassRegistry::new()
+        assert!(process_dummy_event(&mut converter));
+    }
+
+    #[test]
+    fn create_converter_with_options_should_pass_options_to_factory() {
+        let mut manager: ConverterManager = Default::default();
+        manager.register_static_factory(DummyConverterFactory {});
+
+        let schema = manager.get_options("dummy").unwrap();
+        let options = ConverterOptions::parse(&schema, &[("fail", "true")]).unwrap();
+        let label_getter = LabelGetter::new(LabelMap::new(), vec![]);
+        let mut converter = manager
+            .create_converter_with_options(
+                "dummy",
+                Box::new(std::io::sink()),
+                label_getter,
+                &options,
             )
-            .is_ok());
+            .unwrap();
+
+        assert!(!process_dummy_event(&mut converter));
+        assert!(manager.get_options("invalid-converter").is_none());
     }
 }
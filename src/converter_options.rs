@@ -0,0 +1,218 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionType {
+    Bool,
+    Integer,
+    String,
+    /// One of the listed values.
+    Choice(std::vec::Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+}
+
+/// Option declared by a converter factory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionSpec {
+    pub name: String,
+    pub option_type: OptionType,
+    /// Default value, in the same form as the value given by a user.
+    pub default: String,
+    pub help: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct OptionError {
+    pub option: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for OptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid option '{}': {}", self.option, self.reason)
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+impl std::fmt::Display for OptionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptionType::Bool => write!(f, "true|false"),
+            OptionType::Integer => write!(f, "integer"),
+            OptionType::String => write!(f, "string"),
+            OptionType::Choice(values) => write!(f, "{}", values.join("|")),
+        }
+    }
+}
+
+impl std::fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Integer(value) => write!(f, "{}", value),
+            OptionValue::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl OptionSpec {
+    pub fn new(name: &str, option_type: OptionType, default: &str, help: &str) -> OptionSpec {
+        OptionSpec {
+            name: name.to_owned(),
+            option_type,
+            default: default.to_owned(),
+            help: help.to_owned(),
+        }
+    }
+
+    pub fn parse_value(&self, value: &str) -> Result<OptionValue, OptionError> {
+        let error = |reason: String| OptionError {
+            option: self.name.clone(),
+            reason,
+        };
+
+        match &self.option_type {
+            OptionType::Bool => match value {
+                "true" | "yes" | "1" => Ok(OptionValue::Bool(true)),
+                "false" | "no" | "0" => Ok(OptionValue::Bool(false)),
+                _ => Err(error(format!("expected true or false, was '{}'", value))),
+            },
+            OptionType::Integer => value
+                .parse::<i64>()
+                .map(OptionValue::Integer)
+                .map_err(|err| error(format!("'{}': {}", value, err))),
+            OptionType::String => Ok(OptionValue::String(value.to_owned())),
+            OptionType::Choice(values) => {
+                if values.iter().any(|choice| choice == value) {
+                    Ok(OptionValue::String(value.to_owned()))
+                } else {
+                    Err(error(format!(
+                        "expected one of {}, was '{}'",
+                        values.join(", "),
+                        value
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Option values passed to a converter factory; every declared option
+/// has a value (the default one, if not set by a user).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConverterOptions {
+    values: std::collections::HashMap<String, OptionValue>,
+}
+
+impl ConverterOptions {
+    /// Validates `name=value` pairs against the schema, and fills in default values.
+    pub fn parse(
+        schema: &[OptionSpec],
+        values: &[(&str, &str)],
+    ) -> Result<ConverterOptions, OptionError> {
+        let mut options = ConverterOptions::default();
+        for spec in schema {
+            options
+                .values
+                .insert(spec.name.clone(), spec.parse_value(&spec.default)?);
+        }
+
+        for (name, value) in values {
+            let spec = schema
+                .iter()
+                .find(|spec| spec.name == *name)
+                .ok_or_else(|| OptionError {
+                    option: (*name).to_owned(),
+                    reason: "unknown option".to_owned(),
+                })?;
+            options
+                .values
+                .insert(spec.name.clone(), spec.parse_value(value)?);
+        }
+        Ok(options)
+    }
+
+    /// Default values of all the options.
+    pub fn defaults(schema: &[OptionSpec]) -> Result<ConverterOptions, OptionError> {
+        ConverterOptions::parse(schema, &[])
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OptionValue> {
+        self.values.get(name)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.values.get(name) {
+            Some(OptionValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_integer(&self, name: &str) -> Option<i64> {
+        match self.values.get(name) {
+            Some(OptionValue::Integer(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.values.get(name) {
+            Some(OptionValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_schema() -> std::vec::Vec<OptionSpec> {
+        vec![
+            OptionSpec::new(
+                "precision",
+                OptionType::Choice(vec!["us".to_owned(), "ns".to_owned()]),
+                "us",
+                "Timestamp precision",
+            ),
+            OptionSpec::new("limit", OptionType::Integer, "10", "Limit"),
+            OptionSpec::new("enabled", OptionType::Bool, "true", "Enabled"),
+        ]
+    }
+
+    #[test]
+    fn parse_should_fill_defaults_and_override_values() {
+        let options =
+            ConverterOptions::parse(&make_schema(), &[("precision", "ns"), ("limit", "-2")])
+                .unwrap();
+
+        assert_eq!(options.get_str("precision"), Some("ns"));
+        assert_eq!(options.get_integer("limit"), Some(-2));
+        assert_eq!(options.get_bool("enabled"), Some(true));
+        assert!(options.get("unknown").is_none());
+    }
+
+    #[test]
+    fn parse_should_reject_invalid_values_and_unknown_options() {
+        let schema = make_schema();
+
+        assert_eq!(
+            ConverterOptions::parse(&schema, &[("precision", "ms")])
+                .unwrap_err()
+                .option,
+            "precision"
+        );
+        assert!(ConverterOptions::parse(&schema, &[("limit", "x")]).is_err());
+        assert!(ConverterOptions::parse(&schema, &[("enabled", "maybe")]).is_err());
+        assert_eq!(
+            ConverterOptions::parse(&schema, &[("unknown", "1")])
+                .unwrap_err()
+                .reason,
+            "unknown option"
+        );
+    }
+}
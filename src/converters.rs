@@ -1,4 +1,9 @@
+use crate::ConversionError;
+use crate::ConverterOptions;
 use crate::LabelGetter;
+use crate::OptionError;
+use crate::OptionSpec;
+use crate::TimeTransform;
 
 mod debug_converter;
 pub use self::debug_converter::DebugConverterFactory;
@@ -10,19 +15,105 @@ pub use self::flamegraph_converter::FlamegraphConverterFactory;
 mod chrome_tracing_converter;
 pub use self::chrome_tracing_converter::ChromeTracingConverterFactory;
 
+#[cfg(feature = "script")]
+mod script_converter;
+#[cfg(feature = "script")]
+pub use self::script_converter::ScriptConverterFactory;
+
+mod composite_converter;
+pub use self::composite_converter::CompositeConverter;
+
+mod filtered_converter;
+pub use self::filtered_converter::FilteredConverter;
+
+mod transformed_converter;
+pub use self::transformed_converter::TransformedConverter;
+
 pub trait Converter {
     fn process_event(
         &mut self,
         event: &hawktracer_parser::Event,
         reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    ) -> Result<(), ConversionError>;
+
+    /// Writes the rest of the output (e.g. converters keeping events until the
+    /// end) and flushes it, so errors of writing it are reported; called once,
+    /// after the last event. Dropping an unfinished converter finishes it,
+    /// ignoring errors.
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        Ok(())
+    }
+
+    /// Gives access to the label state (e.g. mappings learned from the stream),
+    /// used for deferring events and for reports once the conversion is done.
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        None
+    }
+
+    /// Gives access to the time transform applied to events, if any, for
+    /// reports once the conversion is done.
+    fn get_time_transform(&self) -> Option<&TimeTransform> {
+        None
+    }
+}
+
+/// Describes a format and its output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConverterMetadata {
+    /// One-line description, shown in `--help`.
+    pub description: String,
+    /// Extension (without the dot) of output files, e.g. `json`.
+    pub file_extension: String,
+    pub mime_type: String,
+    /// True if events are written as they come; false if the converter keeps them
+    /// (and its memory usage grows) until the end of the conversion.
+    pub is_streaming: bool,
+}
+
+impl ConverterMetadata {
+    pub fn new(
+        description: &str,
+        file_extension: &str,
+        mime_type: &str,
+        is_streaming: bool,
+    ) -> ConverterMetadata {
+        ConverterMetadata {
+            description: description.to_owned(),
+            file_extension: file_extension.to_owned(),
+            mime_type: mime_type.to_owned(),
+            is_streaming,
+        }
+    }
+}
+
+impl Default for ConverterMetadata {
+    fn default() -> ConverterMetadata {
+        ConverterMetadata::new("", "out", "application/octet-stream", true)
+    }
 }
 
 pub trait ConverterFactory {
+    /// `options` contains a value for every option declared by `get_options`.
     fn construct(
         &self,
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        options: &ConverterOptions,
     ) -> Box<dyn Converter>;
     fn get_name(&self) -> &str;
+
+    fn get_metadata(&self) -> ConverterMetadata {
+        ConverterMetadata::default()
+    }
+
+    /// Options accepted by the converter.
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        vec![]
+    }
+
+    /// Validates option values beyond their types (e.g. that a file can be loaded),
+    /// so errors can be reported before the conversion starts.
+    fn check_options(&self, _options: &ConverterOptions) -> Result<(), OptionError> {
+        Ok(())
+    }
 }
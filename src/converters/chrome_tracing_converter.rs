@@ -1,74 +1,85 @@
 use crate::converters::Converter;
+use crate::ConversionError;
 use crate::ConverterFactory;
+use crate::ConverterMetadata;
+use crate::ConverterOptions;
 use crate::LabelGetter;
+use crate::{OptionSpec, OptionType};
 
-use hawktracer_parser::{Event, EventKlassRegistry, Value};
+use hawktracer_parser::{CoreEventKlassId, Event, EventKlassRegistry, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Precision {
+    Microseconds,
+    Nanoseconds,
+}
 
 struct ChromeTracingConverter {
     writable: Box<dyn std::io::Write>,
     header_written: bool,
     label_getter: LabelGetter,
+    precision: Precision,
+    // Reused by every event, so converting an event doesn't allocate.
+    event_buffer: std::vec::Vec<u8>,
+    category_buffer: String,
 }
 
-#[derive(Debug)]
-pub enum EventProcessingErrorKind {
-    MissingLabelField,
-    InvalidType,
-}
-
-#[derive(Debug)]
-pub struct EventProcessingError {
-    kind: EventProcessingErrorKind,
-    info: String,
-}
-
-impl EventProcessingError {
-    pub fn new(kind: EventProcessingErrorKind, info: String) -> EventProcessingError {
-        EventProcessingError { kind, info }
-    }
-}
-
-impl std::fmt::Display for EventProcessingError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Invalid label: {:?} {}", self.kind, self.info)
-    }
-}
-
-impl std::error::Error for EventProcessingError {}
-
 impl Converter for ChromeTracingConverter {
     fn process_event(
         &mut self,
         event: &Event,
         reg: &EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), ConversionError> {
         if !self.header_written {
             self.writable.write_all(b"[")?;
             self.header_written = true;
         }
 
-        let label_mapping = self.label_getter.get_label(event);
-        let label_field = match label_mapping {
-            Some(label_mapping) => label_mapping.0,
-            None => {
-                return Err(Box::new(EventProcessingError::new(
-                    EventProcessingErrorKind::MissingLabelField,
-                    String::new(),
-                )));
-            }
-        };
+        if CoreEventKlassId::is_core_klass(event.get_klass_id())
+            || self.label_getter.is_mapping_event(event)
+        {
+            // Klass descriptions and mappings are not trace events; the label
+            // getter still has to see them.
+            self.label_getter.get_label(event, reg);
+            return Ok(());
+        }
 
-        let label = match label_mapping {
-            Some(label_mapping) => label_mapping.1,
-            None => {
-                return Err(Box::new(EventProcessingError::new(
-                    EventProcessingErrorKind::InvalidType,
-                    label_field.clone(),
-                )));
-            }
+        // The category has to outlive the label getter borrow taken by the label.
+        self.category_buffer.clear();
+        let category = self.label_getter.get_category(event, reg);
+        if let Some(category) = category {
+            self.category_buffer.push_str(category);
+        }
+        let has_category = category.is_some();
+        let (label_field, label) = match self.label_getter.get_label(event, reg) {
+            Some(label_mapping) => label_mapping,
+            // Events with neither a label nor a duration (e.g. HT_SystemInfoEvent)
+            // are not trace events either.
+            None if event.get_raw_value("duration").is_none() => return Ok(()),
+            None => return Err(ConversionError::UnresolvedLabel),
         };
 
-        EventWriter::new(event, reg, &label, &label_field).write_event(&mut self.writable)
+        self.event_buffer.clear();
+        EventWriter::new(
+            event,
+            reg,
+            label,
+            label_field,
+            Some(self.category_buffer.as_str()).filter(|_| has_category),
+        )
+        .set_precision(self.precision)
+        .write_event(&mut self.event_buffer)?;
+        self.writable.write_all(&self.event_buffer)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        self.writable.flush()?;
+        Ok(())
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        Some(&self.label_getter)
     }
 }
 
@@ -76,20 +87,26 @@ impl ChromeTracingConverter {
     pub fn new(
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        precision: Precision,
     ) -> ChromeTracingConverter {
         ChromeTracingConverter {
             writable,
             header_written: false,
             label_getter,
+            precision,
+            event_buffer: vec![],
+            category_buffer: String::new(),
         }
     }
 }
 
 struct EventWriter<'a> {
     event: &'a Event,
-    used_fields: std::collections::HashSet<&'a str>,
     reg: &'a EventKlassRegistry,
     label: &'a str,
+    label_field: &'a str,
+    category: Option<&'a str>,
+    precision: Precision,
 }
 
 impl<'a> EventWriter<'a> {
@@ -100,77 +117,107 @@ impl<'a> EventWriter<'a> {
         reg: &'a EventKlassRegistry,
         label: &'a str,
         label_field: &'a str,
+        category: Option<&'a str>,
     ) -> EventWriter<'a> {
-        let mut used_fields = std::collections::HashSet::<&str>::new();
-        used_fields.insert("timestamp");
-        used_fields.insert("duration");
-        used_fields.insert("thread_id");
-        used_fields.insert(label_field);
-
         EventWriter {
             event,
-            used_fields,
             reg,
             label,
+            label_field,
+            category,
+            precision: Precision::Microseconds,
         }
     }
 
+    pub fn set_precision(mut self, precision: Precision) -> EventWriter<'a> {
+        self.precision = precision;
+        self
+    }
+
     fn ns_to_us(nano_secs: u64) -> u64 {
         nano_secs / 1000 + (nano_secs % 1000) / 500
     }
 
-    fn format_free_arg(field_name: &str, value: &Value) -> String {
-        format!("{:?}: {}", field_name, value)
+    // Trace Event Format timestamps are in microseconds, but may be fractional.
+    fn write_time(&self, writable: &mut dyn std::io::Write, nano_secs: u64) -> std::io::Result<()> {
+        match self.precision {
+            Precision::Microseconds => write!(writable, "{}", EventWriter::ns_to_us(nano_secs)),
+            Precision::Nanoseconds => {
+                write!(writable, "{}.{:03}", nano_secs / 1000, nano_secs % 1000)
+            }
+        }
+    }
+
+    fn write_free_arg(
+        writable: &mut dyn std::io::Write,
+        field_name: &str,
+        value: &Value,
+    ) -> std::io::Result<()> {
+        write!(writable, "{:?}: {}", field_name, value)
     }
 
-    fn get_free_args(&mut self) -> String {
-        let mut args_str = String::new();
+    // Fields written as trace event properties rather than arguments.
+    fn is_used_field(&self, field_name: &str, is_type_written: bool) -> bool {
+        match field_name {
+            "timestamp" | "duration" | "thread_id" => true,
+            "type" => is_type_written,
+            _ => field_name == self.label_field,
+        }
+    }
 
+    fn write_free_args(&self, writable: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let mut is_type_written = false;
         if let Ok(klass_id) = self.event.get_value_u32("type") {
             if let Some(klass) = self.reg.get_klass_by_id(klass_id) {
-                self.used_fields.insert("type");
-                args_str.push_str(&EventWriter::format_free_arg(
-                    "type",
-                    &Value::Str(klass.get_name().clone()),
-                ));
+                write!(writable, "{:?}: \"{}\"", "type", klass.get_name())?;
+                is_type_written = true;
             }
         }
 
+        let mut is_first = !is_type_written;
         for (field_name, value) in self.event.get_all_values() {
-            if self.used_fields.contains(&field_name[..]) {
+            if self.is_used_field(field_name, is_type_written) {
                 continue;
             }
 
-            if !args_str.is_empty() {
-                args_str.push(',');
+            if !is_first {
+                writable.write_all(b",")?;
             }
+            is_first = false;
 
-            args_str.push_str(&EventWriter::format_free_arg(field_name, value));
+            EventWriter::write_free_arg(writable, field_name, value)?;
         }
-        args_str
+        Ok(())
     }
 
-    pub fn write_event(
-        &mut self,
-        writable: &mut dyn std::io::Write,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let timestamp = EventWriter::ns_to_us(self.event.get_value_u64("timestamp")?);
-
-        let duration = match self.event.get_value_u64("duration") {
-            Ok(duration) => EventWriter::ns_to_us(duration),
-            Err(_) => 0,
-        };
+    pub fn write_event(&self, writable: &mut dyn std::io::Write) -> Result<(), ConversionError> {
+        let timestamp = self
+            .event
+            .get_value_u64("timestamp")
+            .map_err(|err| ConversionError::from_value_error("timestamp", err))?;
+        let duration = self.event.get_value_u64("duration").unwrap_or(0);
         let thread_id = match self.event.get_value_u32("thread_id") {
             Ok(thread_id) => thread_id,
             Err(_) => EventWriter::INVALID_THREAD_ID,
         };
 
-        let free_args = self.get_free_args();
-
-        writable.write_fmt(format_args!(
-            r#"{{"name":"{}","ph":"X","ts":{},"dur":{},"pid":0,"tid":{}, "args": {{ {} }} }},"#,
-            self.label, timestamp, duration, thread_id, free_args
-        ))?;
+        // Labels and categories come from mapping files, templates and rewrite
+        // rules, so they may need escaping.
+        writable.write_all(br#"{"name":"#)?;
+        serde_json::to_writer(&mut *writable, self.label).map_err(std::io::Error::from)?;
+        writable.write_all(b",")?;
+        if let Some(category) = self.category {
+            writable.write_all(br#""cat":"#)?;
+            serde_json::to_writer(&mut *writable, category).map_err(std::io::Error::from)?;
+            writable.write_all(b",")?;
+        }
+        writable.write_all(br#""ph":"X","ts":"#)?;
+        self.write_time(writable, timestamp)?;
+        writable.write_all(br#","dur":"#)?;
+        self.write_time(writable, duration)?;
+        write!(writable, r#","pid":0,"tid":{}, "args": {{ "#, thread_id)?;
+        self.write_free_args(writable)?;
+        writable.write_all(b" } },")?;
 
         Ok(())
     }
@@ -183,13 +230,40 @@ impl ConverterFactory for ChromeTracingConverterFactory {
         &self,
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        options: &ConverterOptions,
     ) -> Box<dyn Converter> {
-        Box::new(ChromeTracingConverter::new(writable, label_getter))
+        let precision = match options.get_str("precision") {
+            Some("ns") => Precision::Nanoseconds,
+            _ => Precision::Microseconds,
+        };
+        Box::new(ChromeTracingConverter::new(
+            writable,
+            label_getter,
+            precision,
+        ))
     }
 
     fn get_name(&self) -> &str {
         "chrome-tracing"
     }
+
+    fn get_metadata(&self) -> ConverterMetadata {
+        ConverterMetadata::new(
+            "Trace Event Format, for chrome://tracing and Perfetto",
+            "json",
+            "application/json",
+            true,
+        )
+    }
+
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        vec![OptionSpec::new(
+            "precision",
+            OptionType::Choice(vec!["us".to_owned(), "ns".to_owned()]),
+            "us",
+            "Precision of timestamps and durations (microseconds, or nanoseconds)",
+        )]
+    }
 }
 
 #[cfg(test)]
@@ -230,13 +304,15 @@ mod tests {
     }
 
     #[test]
-    fn format_free_arg_should_format_argument_correctly() {
-        assert_eq!(
-            EventWriter::format_free_arg("field", &Value::U16(12)),
-            "\"field\": 12"
-        );
+    fn write_free_arg_should_format_argument_correctly() {
+        let format_free_arg = |value: &Value| {
+            let mut buffer = vec![];
+            EventWriter::write_free_arg(&mut buffer, "field", value).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        assert_eq!(format_free_arg(&Value::U16(12)), "\"field\": 12");
         assert_eq!(
-            EventWriter::format_free_arg("field", &Value::Str("value".to_owned())),
+            format_free_arg(&Value::Str("value".to_owned())),
             "\"field\": \"value\""
         );
     }
@@ -254,6 +330,7 @@ mod tests {
             &EventKlassRegistry::new(),
             "label",
             "field",
+            None,
         )
         .write_event(&mut writable)
         .unwrap();
@@ -262,4 +339,89 @@ mod tests {
 
         assert_eq!("{\"name\":\"label\",\"ph\":\"X\",\"ts\":6,\"dur\":12,\"pid\":0,\"tid\":7, \"args\": { \"field1\": -45 } },", data);
     }
+
+    #[test]
+    fn write_event_should_keep_nanoseconds_if_requested() {
+        let mut writable = TestWritable::new();
+        let mut values = std::collections::HashMap::new();
+        values.insert("timestamp".to_owned(), Value::U64(5999));
+        values.insert("duration".to_owned(), Value::U64(12000));
+        values.insert("thread_id".to_owned(), Value::U32(7));
+        EventWriter::new(
+            &Event::new(99, values),
+            &EventKlassRegistry::new(),
+            "label",
+            "field",
+            None,
+        )
+        .set_precision(Precision::Nanoseconds)
+        .write_event(&mut writable)
+        .unwrap();
+
+        let data = std::str::from_utf8(writable.get_buffer()).unwrap();
+
+        assert_eq!("{\"name\":\"label\",\"ph\":\"X\",\"ts\":5.999,\"dur\":12.000,\"pid\":0,\"tid\":7, \"args\": {  } },", data);
+    }
+
+    #[test]
+    fn write_event_should_include_category_if_available() {
+        let mut writable = TestWritable::new();
+        let mut values = std::collections::HashMap::new();
+        values.insert("timestamp".to_owned(), Value::U64(5999));
+        values.insert("duration".to_owned(), Value::U64(12000));
+        values.insert("thread_id".to_owned(), Value::U32(7));
+        EventWriter::new(
+            &Event::new(99, values),
+            &EventKlassRegistry::new(),
+            "label",
+            "field",
+            Some("rendering"),
+        )
+        .write_event(&mut writable)
+        .unwrap();
+
+        let data = std::str::from_utf8(writable.get_buffer()).unwrap();
+
+        assert_eq!("{\"name\":\"label\",\"cat\":\"rendering\",\"ph\":\"X\",\"ts\":6,\"dur\":12,\"pid\":0,\"tid\":7, \"args\": {  } },", data);
+    }
+
+    #[test]
+    fn write_event_should_escape_category() {
+        let mut writable = TestWritable::new();
+        let mut values = std::collections::HashMap::new();
+        values.insert("timestamp".to_owned(), Value::U64(5999));
+        EventWriter::new(
+            &Event::new(99, values),
+            &EventKlassRegistry::new(),
+            "label",
+            "field",
+            Some("io\\\"disk\""),
+        )
+        .write_event(&mut writable)
+        .unwrap();
+
+        let data = std::str::from_utf8(writable.get_buffer()).unwrap();
+
+        assert!(data.contains(r#""cat":"io\\\"disk\"","#));
+    }
+
+    #[test]
+    fn write_event_should_escape_label() {
+        let mut writable = TestWritable::new();
+        let mut values = std::collections::HashMap::new();
+        values.insert("timestamp".to_owned(), Value::U64(5999));
+        EventWriter::new(
+            &Event::new(99, values),
+            &EventKlassRegistry::new(),
+            "read \"q\" C:\\x",
+            "field",
+            None,
+        )
+        .write_event(&mut writable)
+        .unwrap();
+
+        let data = std::str::from_utf8(writable.get_buffer()).unwrap();
+
+        assert!(data.starts_with(r#"{"name":"read \"q\" C:\\x","ph":"X","#));
+    }
 }
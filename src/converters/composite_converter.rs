@@ -0,0 +1,109 @@
+use crate::converters::Converter;
+use crate::ConversionError;
+use crate::LabelGetter;
+
+/// Forwards every event to all the converters, so a single capture
+/// can be converted to multiple formats.
+pub struct CompositeConverter {
+    converters: std::vec::Vec<Box<dyn Converter>>,
+}
+
+impl CompositeConverter {
+    pub fn new(converters: std::vec::Vec<Box<dyn Converter>>) -> CompositeConverter {
+        CompositeConverter { converters }
+    }
+}
+
+impl Converter for CompositeConverter {
+    fn process_event(
+        &mut self,
+        event: &hawktracer_parser::Event,
+        reg: &hawktracer_parser::EventKlassRegistry,
+    ) -> Result<(), ConversionError> {
+        // An error in one converter doesn't stop the event from reaching the other ones.
+        let mut errors = vec![];
+        for converter in &mut self.converters {
+            if let Err(err) = converter.process_event(event, reg) {
+                errors.push(err);
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(ConversionError::Multiple(errors)),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        let mut errors: std::vec::Vec<ConversionError> = self
+            .converters
+            .iter_mut()
+            .filter_map(|converter| converter.finish().err())
+            .collect();
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(ConversionError::Multiple(errors)),
+        }
+    }
+
+    /// Label getter of the first converter; all of them see the same events,
+    /// so their label state is the same.
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        self.converters
+            .iter()
+            .find_map(|converter| converter.get_label_getter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingConverter {
+        count: std::rc::Rc<std::cell::Cell<usize>>,
+        fail: bool,
+    }
+
+    impl Converter for CountingConverter {
+        fn process_event(
+            &mut self,
+            _event: &hawktracer_parser::Event,
+            _reg: &hawktracer_parser::EventKlassRegistry,
+        ) -> Result<(), ConversionError> {
+            self.count.set(self.count.get() + 1);
+            if self.fail {
+                Err(ConversionError::MissingField("duration".to_owned()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn process_event_should_forward_event_to_all_converters() {
+        let first = std::rc::Rc::new(std::cell::Cell::new(0));
+        let second = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut converter = CompositeConverter::new(vec![
+            Box::new(CountingConverter {
+                count: first.clone(),
+                fail: true,
+            }),
+            Box::new(CountingConverter {
+                count: second.clone(),
+                fail: false,
+            }),
+        ]);
+
+        let result = converter.process_event(
+            &hawktracer_parser::Event::new(1, std::collections::HashMap::new()),
+            &hawktracer_parser::EventKlassRegistry::new(),
+        );
+
+        assert_eq!(result.unwrap_err().to_string(), "missing field 'duration'");
+        assert_eq!(first.get(), 1);
+        assert_eq!(second.get(), 1);
+    }
+}
@@ -1,11 +1,16 @@
 use crate::converters::Converter;
+use crate::ConversionError;
 use crate::ConverterFactory;
+use crate::ConverterMetadata;
+use crate::ConverterOptions;
 use crate::LabelGetter;
+use crate::{OptionSpec, OptionType};
 
 struct DebugConverter {
     writable: Box<dyn std::io::Write>,
     label_getter: LabelGetter,
     format_json: bool,
+    print_klass_info: bool,
 }
 
 impl Converter for DebugConverter {
@@ -13,10 +18,26 @@ impl Converter for DebugConverter {
         &mut self,
         event: &hawktracer_parser::Event,
         reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), ConversionError> {
+        if !self.print_klass_info
+            && hawktracer_parser::CoreEventKlassId::is_core_klass(event.get_klass_id())
+        {
+            // Label getter still has to see the events, e.g. to learn mapping event klass.
+            self.label_getter.get_label(event, reg);
+            return Ok(());
+        }
         self.format_event(event, reg)?;
         Ok(())
     }
+
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        self.writable.flush()?;
+        Ok(())
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        Some(&self.label_getter)
+    }
 }
 
 impl DebugConverter {
@@ -24,125 +45,128 @@ impl DebugConverter {
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
         format_json: bool,
+        print_klass_info: bool,
     ) -> DebugConverter {
         DebugConverter {
             writable,
             label_getter,
             format_json,
+            print_klass_info,
         }
     }
 
-    fn write_value_pair(
+    // Label is resolved once per event, so unresolved identifiers are not over-counted.
+    fn format_event(
         &mut self,
-        key: &str,
-        value: &hawktracer_parser::Value,
-        map: Option<String>,
-    ) -> std::io::Result<()> {
-        self.writable.write_fmt(format_args!("    \"{}\": ", key))?;
-
-        self.writable
-            .write_fmt(format_args!("{{\n        \"value\": {},\n", value))?;
-        let debug_value: String = (format!("{:?}", value)).replace("\"", "\\\"");
-        if let Some(mapping) = map {
-            self.writable
-                .write_fmt(format_args!("        \"maps_to\": \"{}\",\n", mapping))?;
-        }
-        self.writable.write_fmt(format_args!(
-            "        \"debug_value\": \"{}\"\n    }}",
-            debug_value
-        ))
-    }
-
-    fn get_klass_name(
-        &self,
         event: &hawktracer_parser::Event,
         reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> String {
-        match reg.get_klass_by_id(event.get_klass_id()) {
-            Some(c) => c.get_name().to_string(),
-            _ => format!("<unknown type, id: {}>", event.get_klass_id()),
+    ) -> Result<(), ConversionError> {
+        let mapping = match self.label_getter.get_label(event, reg) {
+            Some((field, label)) => match event.get_raw_value(field) {
+                Some(hawktracer_parser::Value::Str(_)) => None,
+                _ => Some((field.as_str(), label.as_str())),
+            },
+            _ => None,
+        };
+        if self.format_json {
+            write_event_json(&mut self.writable, event, reg, mapping)?;
+        } else {
+            write_event_human(&mut self.writable, event, reg, mapping)?;
         }
+        Ok(())
     }
+}
 
-    fn get_mapping(
-        &mut self,
-        event: &hawktracer_parser::Event,
-        label_field: &String,
-        value: &hawktracer_parser::Value,
-    ) -> Option<String> {
-        return match self.label_getter.get_label(event) {
-            Some((field, label)) if field == label_field => {
-                match value {
-                    hawktracer_parser::Value::Str(_) => None,
-                    _ => Some(label.clone())
-                }
-            }
-            _ => None,
-        };
+fn write_klass_name(
+    writable: &mut dyn std::io::Write,
+    event: &hawktracer_parser::Event,
+    reg: &hawktracer_parser::EventKlassRegistry,
+) -> std::io::Result<()> {
+    match reg.get_klass_by_id(event.get_klass_id()) {
+        Some(klass) => writable.write_all(klass.get_name().as_bytes()),
+        _ => write!(writable, "<unknown type, id: {}>", event.get_klass_id()),
     }
+}
 
-    fn format_event_json(
-        &mut self,
-        event: &hawktracer_parser::Event,
-        reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.writable.write_all(b"{\n")?;
-        let klass_name = self.get_klass_name(event, reg);
-        self.writable.write_fmt(format_args!(
-            "    \"meta_klass_name\": \"{}\",\n",
-            &klass_name
-        ))?;
-
-        let mut first = true;
-        for value in event.get_all_values() {
-            if first {
-                first = false;
-            } else {
-                self.writable.write_all(b",\n")?;
-            }
-            let mapping = self.get_mapping(event, value.0, &value.1);
-            self.write_value_pair(value.0, &value.1, mapping)?;
-        }
-        self.writable.write_all(b"\n},\n")?;
-        Ok(())
+fn get_field_mapping<'a>(mapping: Option<(&str, &'a str)>, field: &str) -> Option<&'a str> {
+    match mapping {
+        Some((label_field, label)) if label_field == field => Some(label),
+        _ => None,
     }
+}
 
-    fn format_event_human(
-        &mut self,
-        event: &hawktracer_parser::Event,
-        reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let klass_name = self.get_klass_name(event, reg);
-
-        self.writable
-            .write_fmt(format_args!("{} {{\n", klass_name))?;
-        for value in event.get_all_values() {
-            self.writable
-                .write_fmt(format_args!("    \"{}\": {:?}", value.0, value.1))?;
-            match self.get_mapping(event, value.0, &value.1) {
-                Some(label) => {
-                    self.writable
-                        .write_fmt(format_args!(" <maps to {:?}>", label))?;
-                }
-                _ => {}
-            };
-            self.writable.write_all(b"\n")?;
-        }
-        self.writable.write_all(b"}\n")?;
-        Ok(())
+fn write_value_pair(
+    writable: &mut dyn std::io::Write,
+    key: &str,
+    value: &hawktracer_parser::Value,
+    map: Option<&str>,
+) -> std::io::Result<()> {
+    write!(writable, "    \"{}\": ", key)?;
+
+    write!(writable, "{{\n        \"value\": {},\n", value)?;
+    let debug_value: String = (format!("{:?}", value)).replace("\"", "\\\"");
+    if let Some(mapping) = map {
+        writeln!(writable, "        \"maps_to\": \"{}\",", mapping)?;
     }
+    write!(
+        writable,
+        "        \"debug_value\": \"{}\"\n    }}",
+        debug_value
+    )
+}
 
-    fn format_event(
-        &mut self,
-        event: &hawktracer_parser::Event,
-        reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if self.format_json {
-            self.format_event_json(event, reg)
+fn write_event_json(
+    writable: &mut dyn std::io::Write,
+    event: &hawktracer_parser::Event,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    mapping: Option<(&str, &str)>,
+) -> std::io::Result<()> {
+    writable.write_all(b"{\n    \"meta_klass_name\": \"")?;
+    write_klass_name(writable, event, reg)?;
+    writable.write_all(b"\",\n")?;
+
+    let mut first = true;
+    for value in event.get_all_values() {
+        if first {
+            first = false;
         } else {
-            self.format_event_human(event, reg)
+            writable.write_all(b",\n")?;
         }
+        write_value_pair(
+            writable,
+            value.0,
+            value.1,
+            get_field_mapping(mapping, value.0),
+        )?;
     }
+    writable.write_all(b"\n},\n")
+}
+
+fn write_event_human(
+    writable: &mut dyn std::io::Write,
+    event: &hawktracer_parser::Event,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    mapping: Option<(&str, &str)>,
+) -> std::io::Result<()> {
+    write_klass_name(writable, event, reg)?;
+    writable.write_all(b" {\n")?;
+    for value in event.get_all_values() {
+        write!(writable, "    \"{}\": {:?}", value.0, value.1)?;
+        if let Some(label) = get_field_mapping(mapping, value.0) {
+            write!(writable, " <maps to {:?}>", label)?;
+        }
+        writable.write_all(b"\n")?;
+    }
+    writable.write_all(b"}\n")
+}
+
+fn get_debug_options() -> std::vec::Vec<OptionSpec> {
+    vec![OptionSpec::new(
+        "klass-info",
+        OptionType::Bool,
+        "true",
+        "Print core events describing event klasses and their fields",
+    )]
 }
 
 pub struct DebugConverterFactory {}
@@ -152,13 +176,27 @@ impl ConverterFactory for DebugConverterFactory {
         &self,
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        options: &ConverterOptions,
     ) -> Box<dyn Converter> {
-        Box::new(DebugConverter::new(writable, label_getter, false))
+        Box::new(DebugConverter::new(
+            writable,
+            label_getter,
+            false,
+            options.get_bool("klass-info").unwrap_or(true),
+        ))
     }
 
     fn get_name(&self) -> &str {
         "debug"
     }
+
+    fn get_metadata(&self) -> ConverterMetadata {
+        ConverterMetadata::new("All events, as text", "txt", "text/plain", true)
+    }
+
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        get_debug_options()
+    }
 }
 
 pub struct JSONDebugConverterFactory {}
@@ -168,11 +206,25 @@ impl ConverterFactory for JSONDebugConverterFactory {
         &self,
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        options: &ConverterOptions,
     ) -> Box<dyn Converter> {
-        Box::new(DebugConverter::new(writable, label_getter, true))
+        Box::new(DebugConverter::new(
+            writable,
+            label_getter,
+            true,
+            options.get_bool("klass-info").unwrap_or(true),
+        ))
     }
 
     fn get_name(&self) -> &str {
         "json_debug"
     }
+
+    fn get_metadata(&self) -> ConverterMetadata {
+        ConverterMetadata::new("All events, as JSON", "json", "application/json", true)
+    }
+
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        get_debug_options()
+    }
 }
@@ -0,0 +1,43 @@
+use crate::converters::Converter;
+use crate::ConversionError;
+use crate::EventFilter;
+use crate::LabelGetter;
+use crate::TimeTransform;
+
+/// Passes to the converter only events accepted by the filter.
+pub struct FilteredConverter {
+    filter: EventFilter,
+    converter: Box<dyn Converter>,
+}
+
+impl FilteredConverter {
+    pub fn new(filter: EventFilter, converter: Box<dyn Converter>) -> FilteredConverter {
+        FilteredConverter { filter, converter }
+    }
+}
+
+impl Converter for FilteredConverter {
+    fn process_event(
+        &mut self,
+        event: &hawktracer_parser::Event,
+        reg: &hawktracer_parser::EventKlassRegistry,
+    ) -> Result<(), ConversionError> {
+        if self.filter.is_accepted(event, reg) {
+            self.converter.process_event(event, reg)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        self.converter.finish()
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        self.converter.get_label_getter()
+    }
+
+    fn get_time_transform(&self) -> Option<&TimeTransform> {
+        self.converter.get_time_transform()
+    }
+}
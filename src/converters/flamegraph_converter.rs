@@ -1,34 +1,72 @@
 use crate::converters::Converter;
+use crate::ConversionError;
 use crate::ConverterFactory;
+use crate::ConverterMetadata;
+use crate::ConverterOptions;
 use crate::LabelGetter;
+use crate::{OptionSpec, OptionType};
 use std::cell::RefCell;
 use std::rc::Rc;
 
 type StackItemPtr = Rc<RefCell<StackItem>>;
 
+/// Index of a label in the `LabelTable`.
+type LabelIndex = usize;
+
+/// Every distinct label is stored once; events and stack items refer to it by index.
+#[derive(Default)]
+struct LabelTable {
+    labels: std::vec::Vec<String>,
+    indices: std::collections::HashMap<String, LabelIndex>,
+}
+
+impl LabelTable {
+    pub fn get_index(&mut self, label: &str) -> LabelIndex {
+        if let Some(index) = self.indices.get(label) {
+            return *index;
+        }
+        let index = self.labels.len();
+        self.labels.push(label.to_owned());
+        self.indices.insert(label.to_owned(), index);
+        index
+    }
+
+    pub fn get_label(&self, index: LabelIndex) -> &str {
+        &self.labels[index]
+    }
+}
+
 struct StackItem {
-    label: String,
+    label: LabelIndex,
     parent: Option<StackItemPtr>,
     duration: u64,
     last_start: u64,
     last_stop: u64,
-    children: std::collections::HashMap<String, StackItemPtr>,
+    children: std::collections::HashMap<LabelIndex, StackItemPtr>,
 }
 
 impl StackItem {
-    pub fn new(label: String, parent: Option<StackItemPtr>, start: u64, stop: u64) -> StackItem {
+    // Roots are never written, so they have no label.
+    const ROOT_LABEL: LabelIndex = LabelIndex::MAX;
+
+    pub fn new(
+        label: LabelIndex,
+        parent: Option<StackItemPtr>,
+        start: u64,
+        stop: u64,
+    ) -> StackItem {
         StackItem {
             label,
             parent,
             duration: 0,
             last_start: start,
             last_stop: stop,
-            children: std::collections::HashMap::<String, StackItemPtr>::new(),
+            children: std::collections::HashMap::<LabelIndex, StackItemPtr>::new(),
         }
     }
 
     pub fn new_root() -> StackItem {
-        StackItem::new(String::new(), None, 0, std::u64::MAX)
+        StackItem::new(StackItem::ROOT_LABEL, None, 0, u64::MAX)
     }
 
     pub fn update_last_range(&mut self, start_ts: u64, stop_ts: u64) {
@@ -39,7 +77,7 @@ impl StackItem {
 }
 
 struct EventItem {
-    label: String,
+    label: LabelIndex,
     thread_id: u32,
     start_ts: u64,
     stop_ts: u64,
@@ -66,7 +104,7 @@ impl ThreadStack {
 
     pub fn add_item(&mut self, item: &EventItem) {
         while !self.is_parent_of(&self.current_item, item) {
-            let parent = Rc::clone(&self.current_item.borrow().parent.as_ref().unwrap());
+            let parent = Rc::clone(self.current_item.borrow().parent.as_ref().unwrap());
             self.current_item = parent;
         }
 
@@ -78,9 +116,9 @@ impl ThreadStack {
         {
             let current_item_cpy = Rc::clone(&self.current_item);
             self.current_item.borrow_mut().children.insert(
-                item.label.clone(),
+                item.label,
                 Rc::new(RefCell::new(StackItem::new(
-                    item.label.clone(),
+                    item.label,
                     Some(current_item_cpy),
                     item.start_ts,
                     item.stop_ts,
@@ -100,19 +138,26 @@ struct FlamegraphConverter {
     writable: Box<dyn std::io::Write>,
     stacks: std::collections::HashMap<u32, ThreadStack>,
     items: std::vec::Vec<EventItem>,
+    labels: LabelTable,
     label_getter: LabelGetter,
+    split_threads: bool,
+    is_finished: bool,
 }
 
 impl FlamegraphConverter {
     pub fn new(
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        split_threads: bool,
     ) -> FlamegraphConverter {
         FlamegraphConverter {
             writable,
             label_getter,
             items: vec![],
+            labels: LabelTable::default(),
             stacks: std::collections::HashMap::<u32, ThreadStack>::new(),
+            split_threads,
+            is_finished: false,
         }
     }
 
@@ -127,11 +172,26 @@ impl FlamegraphConverter {
         }
 
         let super_root = Rc::new(RefCell::new(StackItem::new_root()));
-        for stack in self.stacks.values() {
-            self.merge_stacks(&super_root, &stack.root_item);
+        for (thread_id, stack) in &self.stacks {
+            if self.split_threads {
+                let label = self.labels.get_index(&format!("thread {}", thread_id));
+                let thread_root = Rc::new(RefCell::new(StackItem::new(
+                    label,
+                    Some(Rc::clone(&super_root)),
+                    0,
+                    u64::MAX,
+                )));
+                for child in stack.root_item.borrow().children.values() {
+                    thread_root.borrow_mut().duration += child.borrow().duration;
+                }
+                self.merge_stacks(&thread_root, &stack.root_item);
+                super_root.borrow_mut().children.insert(label, thread_root);
+            } else {
+                self.merge_stacks(&super_root, &stack.root_item);
+            }
         }
 
-        HTMLFlameGraphWritter::new(&mut self.writable).write_flamegraph(&super_root)
+        HTMLFlameGraphWritter::new(&mut self.writable, &self.labels).write_flamegraph(&super_root)
     }
 
     fn merge_stacks(&self, super_stack: &StackItemPtr, stack: &StackItemPtr) {
@@ -144,15 +204,12 @@ impl FlamegraphConverter {
                 super_stack.borrow().children[&child.borrow().label]
                     .borrow_mut()
                     .duration += child.borrow().duration;
-                self.merge_stacks(
-                    &super_stack.borrow().children[&child.borrow().label],
-                    &child,
-                );
+                self.merge_stacks(&super_stack.borrow().children[&child.borrow().label], child);
             } else {
                 super_stack
                     .borrow_mut()
                     .children
-                    .insert(child.borrow().label.clone(), Rc::clone(child));
+                    .insert(child.borrow().label, Rc::clone(child));
             }
         }
     }
@@ -162,34 +219,67 @@ impl Converter for FlamegraphConverter {
     fn process_event(
         &mut self,
         event: &hawktracer_parser::Event,
-        _reg: &hawktracer_parser::EventKlassRegistry,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let timestamp = event.get_value_u64("timestamp");
-        let duration = event.get_value_u64("duration");
-        let thread_id = event.get_value_u32("thread_id");
-        let label_mapping = self.label_getter.get_label(event);
-
-        if timestamp.is_err() || duration.is_err() || label_mapping.is_none() || thread_id.is_err()
-        {
+        reg: &hawktracer_parser::EventKlassRegistry,
+    ) -> Result<(), ConversionError> {
+        // Only events with duration are spans.
+        if event.get_raw_value("duration").is_none() {
+            self.label_getter.get_label(event, reg);
             return Ok(());
         }
 
+        let get_value_u64 = |name| {
+            event
+                .get_value_u64(name)
+                .map_err(|err| ConversionError::from_value_error(name, err))
+        };
+        let timestamp = get_value_u64("timestamp")?;
+        let duration = get_value_u64("duration")?;
+        let thread_id = event
+            .get_value_u32("thread_id")
+            .map_err(|err| ConversionError::from_value_error("thread_id", err))?;
+        let label = self.labels.get_index(
+            self.label_getter
+                .get_label(event, reg)
+                .ok_or(ConversionError::UnresolvedLabel)?
+                .1,
+        );
+        let stop_ts = timestamp.checked_add(duration).ok_or_else(|| {
+            ConversionError::MalformedSpan(format!(
+                "duration {} starting at {} exceeds the time range",
+                duration, timestamp
+            ))
+        })?;
+
         let item = EventItem {
-            label: label_mapping.unwrap().1.clone(),
-            thread_id: thread_id.unwrap(),
-            start_ts: *timestamp.as_ref().unwrap(),
-            stop_ts: timestamp.unwrap() + duration.unwrap(),
+            label,
+            thread_id,
+            start_ts: timestamp,
+            stop_ts,
         };
 
         self.items.push(item);
 
         Ok(())
     }
+
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        self.is_finished = true;
+        self.generate_flamegraph()?;
+        self.writable.flush()?;
+        Ok(())
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        Some(&self.label_getter)
+    }
 }
 
 impl Drop for FlamegraphConverter {
     fn drop(&mut self) {
-        self.generate_flamegraph().unwrap();
+        if !self.is_finished {
+            // Errors can't be reported from drop.
+            let _ = self.generate_flamegraph();
+        }
     }
 }
 
@@ -200,27 +290,54 @@ impl ConverterFactory for FlamegraphConverterFactory {
         &self,
         writable: Box<dyn std::io::Write>,
         label_getter: LabelGetter,
+        options: &ConverterOptions,
     ) -> Box<dyn Converter> {
-        Box::new(FlamegraphConverter::new(writable, label_getter))
+        Box::new(FlamegraphConverter::new(
+            writable,
+            label_getter,
+            options.get_str("mode") == Some("threads"),
+        ))
     }
 
     fn get_name(&self) -> &str {
         "flamegraph"
     }
+
+    fn get_metadata(&self) -> ConverterMetadata {
+        ConverterMetadata::new(
+            "Flame graph of merged call stacks, as an HTML page",
+            "html",
+            "text/html",
+            false,
+        )
+    }
+
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        vec![OptionSpec::new(
+            "mode",
+            OptionType::Choice(vec!["merged".to_owned(), "threads".to_owned()]),
+            "merged",
+            "Merge stacks of all threads, or show every thread as a separate root",
+        )]
+    }
 }
 
 struct HTMLFlameGraphWritter<'a> {
     writable: &'a mut dyn std::io::Write,
+    labels: &'a LabelTable,
 }
 
 impl<'a> HTMLFlameGraphWritter<'a> {
-    pub fn new(writable: &'a mut dyn std::io::Write) -> HTMLFlameGraphWritter<'a> {
-        HTMLFlameGraphWritter { writable }
+    pub fn new(
+        writable: &'a mut dyn std::io::Write,
+        labels: &'a LabelTable,
+    ) -> HTMLFlameGraphWritter<'a> {
+        HTMLFlameGraphWritter { writable, labels }
     }
 
     pub fn write_flamegraph(&mut self, root_item: &StackItemPtr) -> std::io::Result<()> {
         self.write_header()?;
-        self.write_stack_item(&root_item)?;
+        self.write_stack_item(root_item)?;
         self.write_footer()
     }
 
@@ -232,7 +349,7 @@ impl<'a> HTMLFlameGraphWritter<'a> {
         if !self.is_root(item) {
             self.writable.write_fmt(format_args!(
                 "{{ name: \"{}\", value: {}, children: [",
-                item.borrow().label,
+                self.labels.get_label(item.borrow().label),
                 item.borrow().duration
             ))?;
         }
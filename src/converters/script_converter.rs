@@ -0,0 +1,390 @@
+use crate::converters::Converter;
+use crate::ConversionError;
+use crate::ConverterFactory;
+use crate::ConverterMetadata;
+use crate::ConverterOptions;
+use crate::LabelGetter;
+use crate::{OptionError, OptionSpec, OptionType};
+use hawktracer_parser::{Event, EventKlassRegistry, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type SharedWritable = Rc<RefCell<Box<dyn std::io::Write>>>;
+
+fn value_to_dynamic(value: &Value) -> rhai::Dynamic {
+    match value {
+        Value::U8(v) => rhai::Dynamic::from_int(rhai::INT::from(*v)),
+        Value::I8(v) => rhai::Dynamic::from_int(rhai::INT::from(*v)),
+        Value::U16(v) => rhai::Dynamic::from_int(rhai::INT::from(*v)),
+        Value::I16(v) => rhai::Dynamic::from_int(rhai::INT::from(*v)),
+        Value::U32(v) => rhai::Dynamic::from_int(rhai::INT::from(*v)),
+        Value::I32(v) => rhai::Dynamic::from_int(rhai::INT::from(*v)),
+        // Script integers are signed 64-bit, values above i64::MAX wrap around.
+        Value::U64(v) => rhai::Dynamic::from_int(*v as rhai::INT),
+        Value::I64(v) => rhai::Dynamic::from_int(*v),
+        Value::Str(v) => v.clone().into(),
+        Value::Struct(event) => rhai::Dynamic::from_map(fields_to_map(event)),
+    }
+}
+
+fn fields_to_map(event: &Event) -> rhai::Map {
+    event
+        .get_all_values()
+        .iter()
+        .map(|(name, value)| (name.as_str().into(), value_to_dynamic(value)))
+        .collect()
+}
+
+fn optional_string(value: Option<&String>) -> rhai::Dynamic {
+    value.map_or(rhai::Dynamic::UNIT, |value| value.clone().into())
+}
+
+fn create_engine(writable: &SharedWritable) -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    // Standard output may be used for the converted data.
+    engine.on_print(|text| eprintln!("{}", text));
+
+    let write_target = Rc::clone(writable);
+    engine.register_fn(
+        "write",
+        move |value: rhai::Dynamic| -> Result<(), Box<rhai::EvalAltResult>> {
+            write!(write_target.borrow_mut(), "{}", value).map_err(|err| err.to_string().into())
+        },
+    );
+    let writeln_target = Rc::clone(writable);
+    engine.register_fn(
+        "writeln",
+        move |value: rhai::Dynamic| -> Result<(), Box<rhai::EvalAltResult>> {
+            writeln!(writeln_target.borrow_mut(), "{}", value).map_err(|err| err.to_string().into())
+        },
+    );
+    engine
+}
+
+fn compile_script(engine: &rhai::Engine, path: &str) -> Result<rhai::AST, String> {
+    if path.is_empty() {
+        return Err("script file is not set".to_owned());
+    }
+    let ast = engine
+        .compile_file(path.into())
+        .map_err(|err| err.to_string())?;
+    if !ast
+        .iter_functions()
+        .any(|function| function.name == "on_event" && function.params.len() == 1)
+    {
+        return Err(format!("{}: function on_event(event) is not defined", path));
+    }
+    Ok(ast)
+}
+
+// Compiles the script, and runs its top-level statements and `on_start()`.
+fn load_script(
+    engine: &rhai::Engine,
+    path: &str,
+    state: &mut rhai::Dynamic,
+) -> Result<rhai::AST, String> {
+    let ast = compile_script(engine, path)?;
+    engine
+        .run_ast_with_scope(&mut rhai::Scope::new(), &ast)
+        .map_err(|err| err.to_string())?;
+    call_script_fn(engine, &ast, state, "on_start", ())?;
+    Ok(ast)
+}
+
+/// Runs `on_event(event)` of a user script for every event, and `on_finish()`
+/// once the conversion is done; state kept between calls is stored in `this`.
+struct ScriptConverter {
+    engine: rhai::Engine,
+    // Error if the script couldn't be loaded.
+    ast: Result<rhai::AST, String>,
+    state: rhai::Dynamic,
+    writable: SharedWritable,
+    label_getter: LabelGetter,
+    is_finished: bool,
+}
+
+impl ScriptConverter {
+    pub fn new(
+        writable: Box<dyn std::io::Write>,
+        label_getter: LabelGetter,
+        path: &str,
+    ) -> ScriptConverter {
+        let writable = Rc::new(RefCell::new(writable));
+        let engine = create_engine(&writable);
+        let mut state = rhai::Dynamic::from_map(rhai::Map::new());
+        let ast = load_script(&engine, path, &mut state);
+
+        ScriptConverter {
+            engine,
+            ast,
+            state,
+            writable,
+            label_getter,
+            is_finished: false,
+        }
+    }
+
+    fn make_event_map(&mut self, event: &Event, reg: &EventKlassRegistry) -> rhai::Map {
+        let mut map = rhai::Map::new();
+        map.insert(
+            "klass_id".into(),
+            rhai::Dynamic::from_int(rhai::INT::from(event.get_klass_id())),
+        );
+        map.insert(
+            "klass_name".into(),
+            optional_string(
+                reg.get_klass_by_id(event.get_klass_id())
+                    .map(|klass| klass.get_name()),
+            ),
+        );
+        map.insert(
+            "category".into(),
+            optional_string(self.label_getter.get_category(event, reg)),
+        );
+        map.insert(
+            "label".into(),
+            optional_string(
+                self.label_getter
+                    .get_label(event, reg)
+                    .map(|(_, label)| label),
+            ),
+        );
+        map.insert(
+            "fields".into(),
+            rhai::Dynamic::from_map(fields_to_map(event)),
+        );
+        map
+    }
+}
+
+// Calls a script function if it's defined.
+fn call_script_fn(
+    engine: &rhai::Engine,
+    ast: &rhai::AST,
+    state: &mut rhai::Dynamic,
+    name: &str,
+    args: impl rhai::FuncArgs,
+) -> Result<(), String> {
+    let mut arg_values = vec![];
+    args.parse(&mut arg_values);
+    if !ast
+        .iter_functions()
+        .any(|function| function.name == name && function.params.len() == arg_values.len())
+    {
+        return Ok(());
+    }
+
+    let options = rhai::CallFnOptions::new()
+        .eval_ast(false)
+        .bind_this_ptr(state);
+    engine
+        .call_fn_with_options::<rhai::Dynamic>(
+            options,
+            &mut rhai::Scope::new(),
+            ast,
+            name,
+            arg_values,
+        )
+        .map(|_| ())
+        .map_err(|err| format!("{}: {}", name, err))
+}
+
+impl Converter for ScriptConverter {
+    fn process_event(
+        &mut self,
+        event: &Event,
+        reg: &EventKlassRegistry,
+    ) -> Result<(), ConversionError> {
+        let event_map = self.make_event_map(event, reg);
+        let ast = self
+            .ast
+            .as_ref()
+            .map_err(|err| ConversionError::Script(err.clone()))?;
+        call_script_fn(
+            &self.engine,
+            ast,
+            &mut self.state,
+            "on_event",
+            (rhai::Dynamic::from_map(event_map),),
+        )
+        .map_err(ConversionError::Script)
+    }
+
+    /// Runs `on_finish()` of the script, and flushes the output.
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        self.is_finished = true;
+        if let Ok(ast) = &self.ast {
+            call_script_fn(&self.engine, ast, &mut self.state, "on_finish", ())
+                .map_err(ConversionError::Script)?;
+        }
+        self.writable.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        Some(&self.label_getter)
+    }
+}
+
+impl Drop for ScriptConverter {
+    fn drop(&mut self) {
+        if !self.is_finished {
+            if let Err(err) = self.finish() {
+                eprintln!("Unable to finish the script: {}", err);
+            }
+        }
+    }
+}
+
+pub struct ScriptConverterFactory {}
+
+impl ConverterFactory for ScriptConverterFactory {
+    fn construct(
+        &self,
+        writable: Box<dyn std::io::Write>,
+        label_getter: LabelGetter,
+        options: &ConverterOptions,
+    ) -> Box<dyn Converter> {
+        Box::new(ScriptConverter::new(
+            writable,
+            label_getter,
+            options.get_str("path").unwrap_or_default(),
+        ))
+    }
+
+    fn get_name(&self) -> &str {
+        "script"
+    }
+
+    fn get_metadata(&self) -> ConverterMetadata {
+        ConverterMetadata::new("Output of a Rhai script", "txt", "text/plain", true)
+    }
+
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        vec![OptionSpec::new(
+            "path",
+            OptionType::String,
+            "",
+            "Rhai script defining on_event(event), and optionally on_start() and on_finish()",
+        )]
+    }
+
+    /// Runs the start of the script (with its output discarded), so scripts
+    /// failing before the first event are reported before the conversion.
+    fn check_options(&self, options: &ConverterOptions) -> Result<(), OptionError> {
+        let writable: SharedWritable = Rc::new(RefCell::new(Box::new(std::io::sink())));
+        load_script(
+            &create_engine(&writable),
+            options.get_str("path").unwrap_or_default(),
+            &mut rhai::Dynamic::from_map(rhai::Map::new()),
+        )
+        .map(|_| ())
+        .map_err(|reason| OptionError {
+            option: "path".to_owned(),
+            reason,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LabelMap;
+
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<std::vec::Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_script(name: &str, source: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, source).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn script_should_process_events_and_keep_state() {
+        let path = write_script(
+            "hawktracer-converter-test-script.rhai",
+            r#"
+                fn on_start() { this.total = 0; }
+                fn on_event(event) {
+                    if event.label != () {
+                        this.total += event.fields.duration;
+                        writeln(`${event.label} ${event.fields.duration}`);
+                    }
+                }
+                fn on_finish() { write(`total ${this.total}`); }
+            "#,
+        );
+
+        let buffer = SharedBuffer(Default::default());
+        let mut label_map = LabelMap::new();
+        label_map.add_mapping(7, "seven");
+        let label_getter = LabelGetter::new(label_map, vec!["name".to_owned()]);
+        let mut converter = ScriptConverter::new(Box::new(buffer.clone()), label_getter, &path);
+
+        let reg = EventKlassRegistry::new();
+        for duration in &[3u64, 4u64] {
+            let mut values = std::collections::HashMap::new();
+            values.insert("name".to_owned(), Value::U64(7));
+            values.insert("duration".to_owned(), Value::U64(*duration));
+            converter
+                .process_event(&Event::new(99, values), &reg)
+                .unwrap();
+        }
+        drop(converter);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&buffer.0.borrow()).unwrap(),
+            "seven 3\nseven 4\ntotal 7"
+        );
+    }
+
+    #[test]
+    fn check_options_should_fail_for_invalid_scripts() {
+        let factory = ScriptConverterFactory {};
+        let schema = factory.get_options();
+
+        let options = ConverterOptions::defaults(&schema).unwrap();
+        assert!(factory.check_options(&options).is_err());
+
+        let path = write_script(
+            "hawktracer-converter-test-no-callback.rhai",
+            "fn on_finish() {}",
+        );
+        let options = ConverterOptions::parse(&schema, &[("path", &path)]).unwrap();
+        assert!(factory.check_options(&options).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn check_options_should_fail_for_scripts_failing_on_start() {
+        let factory = ScriptConverterFactory {};
+        let schema = factory.get_options();
+
+        let path = write_script(
+            "hawktracer-converter-test-failing-start.rhai",
+            "fn on_start() { throw \"no start\"; } fn on_event(event) {}",
+        );
+        let options = ConverterOptions::parse(&schema, &[("path", &path)]).unwrap();
+        assert!(factory.check_options(&options).is_err());
+
+        std::fs::write(&path, "let x = 1 / 0; fn on_event(event) {}").unwrap();
+        assert!(factory.check_options(&options).is_err());
+
+        std::fs::write(&path, "fn on_start() { this.n = 0; } fn on_event(event) {}").unwrap();
+        assert!(factory.check_options(&options).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
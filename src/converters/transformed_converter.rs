@@ -0,0 +1,44 @@
+use crate::converters::Converter;
+use crate::ConversionError;
+use crate::LabelGetter;
+use crate::TimeTransform;
+
+/// Passes events to the converter with time rewritten by the transform.
+pub struct TransformedConverter {
+    transform: TimeTransform,
+    converter: Box<dyn Converter>,
+}
+
+impl TransformedConverter {
+    pub fn new(transform: TimeTransform, converter: Box<dyn Converter>) -> TransformedConverter {
+        TransformedConverter {
+            transform,
+            converter,
+        }
+    }
+}
+
+impl Converter for TransformedConverter {
+    fn process_event(
+        &mut self,
+        event: &hawktracer_parser::Event,
+        reg: &hawktracer_parser::EventKlassRegistry,
+    ) -> Result<(), ConversionError> {
+        match self.transform.transform(event, reg) {
+            Some(event) => self.converter.process_event(&event, reg),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        self.converter.finish()
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        self.converter.get_label_getter()
+    }
+
+    fn get_time_transform(&self) -> Option<&TimeTransform> {
+        Some(&self.transform)
+    }
+}
@@ -0,0 +1,133 @@
+use hawktracer_parser::Event;
+
+/// FIFO buffer delaying events whose label can not be resolved yet (e.g. because
+/// the mapping event hasn't arrived). An event leaves the buffer once it's resolved,
+/// it has waited longer than the timeout, or the buffer is full; events always
+/// leave in the order they were added.
+pub struct DeferredEvents {
+    events: std::collections::VecDeque<(std::time::Instant, Event)>,
+    capacity: usize,
+    timeout: std::time::Duration,
+}
+
+impl DeferredEvents {
+    pub fn new(capacity: usize, timeout: std::time::Duration) -> DeferredEvents {
+        DeferredEvents {
+            events: std::collections::VecDeque::new(),
+            capacity,
+            timeout,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn push(&mut self, event: Event, now: std::time::Instant) {
+        self.events.push_back((now, event));
+    }
+
+    /// Time the oldest event times out, even if no other event arrives (e.g. on
+    /// a quiet stream), so the caller knows when to check the buffer again.
+    pub fn get_expiry(&self) -> Option<std::time::Instant> {
+        self.events
+            .front()
+            .map(|(deferred_at, _)| *deferred_at + self.timeout)
+    }
+
+    /// Removes the oldest event if it's ready to be processed.
+    pub fn pop_ready<F: Fn(&Event) -> bool>(
+        &mut self,
+        now: std::time::Instant,
+        is_resolved: F,
+    ) -> Option<Event> {
+        let (deferred_at, event) = self.events.front()?;
+        if self.events.len() > self.capacity
+            || now.duration_since(*deferred_at) >= self.timeout
+            || is_resolved(event)
+        {
+            self.events.pop_front().map(|(_, event)| event)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the oldest event, regardless of whether it's ready.
+    pub fn pop(&mut self) -> Option<Event> {
+        self.events.pop_front().map(|(_, event)| event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_event(id: u64) -> Event {
+        let mut values = std::collections::HashMap::new();
+        values.insert("id".to_owned(), hawktracer_parser::Value::U64(id));
+        Event::new(1, values)
+    }
+
+    fn event_id(event: Event) -> u64 {
+        event.get_value_u64("id").unwrap()
+    }
+
+    #[test]
+    fn pop_ready_should_keep_order() {
+        let now = std::time::Instant::now();
+        let mut deferred = DeferredEvents::new(10, std::time::Duration::from_secs(1));
+        deferred.push(make_event(1), now);
+        deferred.push(make_event(2), now);
+
+        // Second event is resolved, but it must wait for the first one.
+        let is_resolved = |event: &Event| event.get_value_u64("id").ok() == Some(2);
+        assert!(deferred.pop_ready(now, is_resolved).is_none());
+
+        let is_resolved = |_: &Event| true;
+        assert_eq!(event_id(deferred.pop_ready(now, is_resolved).unwrap()), 1);
+        assert_eq!(event_id(deferred.pop_ready(now, is_resolved).unwrap()), 2);
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn pop_ready_should_release_events_after_timeout() {
+        let now = std::time::Instant::now();
+        let mut deferred = DeferredEvents::new(10, std::time::Duration::from_millis(100));
+        deferred.push(make_event(1), now);
+
+        assert!(deferred.pop_ready(now, |_| false).is_none());
+        let later = now + std::time::Duration::from_millis(100);
+        assert_eq!(event_id(deferred.pop_ready(later, |_| false).unwrap()), 1);
+    }
+
+    #[test]
+    fn get_expiry_should_return_timeout_of_oldest_event() {
+        let now = std::time::Instant::now();
+        let timeout = std::time::Duration::from_millis(100);
+        let mut deferred = DeferredEvents::new(10, timeout);
+        assert!(deferred.get_expiry().is_none());
+
+        deferred.push(make_event(1), now);
+        deferred.push(make_event(2), now + timeout);
+
+        assert_eq!(deferred.get_expiry(), Some(now + timeout));
+        deferred.pop();
+        assert_eq!(deferred.get_expiry(), Some(now + timeout * 2));
+    }
+
+    #[test]
+    fn pop_ready_should_release_events_if_buffer_is_full() {
+        let now = std::time::Instant::now();
+        let mut deferred = DeferredEvents::new(1, std::time::Duration::from_secs(1));
+        deferred.push(make_event(1), now);
+        deferred.push(make_event(2), now);
+
+        assert_eq!(event_id(deferred.pop_ready(now, |_| false).unwrap()), 1);
+        assert!(deferred.pop_ready(now, |_| false).is_none());
+        assert_eq!(deferred.len(), 1);
+    }
+}
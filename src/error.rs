@@ -0,0 +1,318 @@
+use hawktracer_parser::event::ValueError;
+use hawktracer_parser::{Event, EventKlassRegistry};
+
+/// Kind of a conversion error, used for grouping errors in reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorKind {
+    MissingField,
+    WrongType,
+    UnresolvedLabel,
+    MalformedSpan,
+    Io,
+    Plugin,
+    Script,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ErrorKind::MissingField => "missing field",
+            ErrorKind::WrongType => "wrong type",
+            ErrorKind::UnresolvedLabel => "unresolved label",
+            ErrorKind::MalformedSpan => "malformed span",
+            ErrorKind::Io => "I/O error",
+            ErrorKind::Plugin => "plugin error",
+            ErrorKind::Script => "script error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Error of processing a single event by a converter.
+#[derive(Debug)]
+pub enum ConversionError {
+    /// The event doesn't have a field required by the converter.
+    MissingField(String),
+    /// The field has a different type than the converter expects.
+    WrongType(String),
+    /// The label of the event can't be resolved, e.g. none of the label fields is present.
+    UnresolvedLabel,
+    /// Timestamp and duration of the event don't describe a valid span.
+    MalformedSpan(String),
+    Io(std::io::Error),
+    Plugin(String),
+    Script(String),
+    /// Errors of multiple converters processing the same event; never empty.
+    Multiple(std::vec::Vec<ConversionError>),
+}
+
+impl ConversionError {
+    /// Converts an error of reading the event field.
+    pub fn from_value_error(field: &str, error: ValueError) -> ConversionError {
+        match error.kind() {
+            hawktracer_parser::event::ErrorKind::NotFound => {
+                ConversionError::MissingField(field.to_owned())
+            }
+            hawktracer_parser::event::ErrorKind::InvalidType => {
+                ConversionError::WrongType(field.to_owned())
+            }
+        }
+    }
+
+    /// Kind of the error; for `Multiple`, it's the kind of the first error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ConversionError::MissingField(_) => ErrorKind::MissingField,
+            ConversionError::WrongType(_) => ErrorKind::WrongType,
+            ConversionError::UnresolvedLabel => ErrorKind::UnresolvedLabel,
+            ConversionError::MalformedSpan(_) => ErrorKind::MalformedSpan,
+            ConversionError::Io(_) => ErrorKind::Io,
+            ConversionError::Plugin(_) => ErrorKind::Plugin,
+            ConversionError::Script(_) => ErrorKind::Script,
+            ConversionError::Multiple(errors) => errors[0].kind(),
+        }
+    }
+
+    /// All the errors, with `Multiple` errors expanded.
+    pub fn flatten(&self) -> std::vec::Vec<&ConversionError> {
+        match self {
+            ConversionError::Multiple(errors) => {
+                errors.iter().flat_map(|error| error.flatten()).collect()
+            }
+            _ => vec![self],
+        }
+    }
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConversionError::MissingField(field) => write!(f, "missing field '{}'", field),
+            ConversionError::WrongType(field) => write!(f, "field '{}' has wrong type", field),
+            ConversionError::UnresolvedLabel => write!(f, "label can't be resolved"),
+            ConversionError::MalformedSpan(reason) => write!(f, "malformed span: {}", reason),
+            ConversionError::Io(err) => write!(f, "I/O error: {}", err),
+            ConversionError::Plugin(message) => write!(f, "plugin error: {}", message),
+            ConversionError::Script(message) => write!(f, "script error: {}", message),
+            ConversionError::Multiple(errors) => {
+                let messages: std::vec::Vec<String> =
+                    errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "{}", messages.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl From<std::io::Error> for ConversionError {
+    fn from(err: std::io::Error) -> ConversionError {
+        ConversionError::Io(err)
+    }
+}
+
+/// Errors of a kind, reported for events of a single klass.
+pub struct KlassErrors {
+    pub count: u64,
+    /// The first offending event, and its error.
+    pub sample: String,
+}
+
+/// Counts conversion errors per kind and per event klass.
+#[derive(Default)]
+pub struct ErrorStats {
+    event_count: u64,
+    failed_event_count: u64,
+    kinds: std::collections::BTreeMap<ErrorKind, u64>,
+    klasses: std::collections::BTreeMap<(ErrorKind, String), KlassErrors>,
+    finish_errors: std::vec::Vec<String>,
+}
+
+fn describe_event(event: &Event, klass_name: &str) -> String {
+    let mut fields: std::vec::Vec<String> = event
+        .get_all_values()
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect();
+    fields.sort();
+    format!("{} {{ {} }}", klass_name, fields.join(", "))
+}
+
+impl ErrorStats {
+    pub fn new() -> ErrorStats {
+        ErrorStats::default()
+    }
+
+    /// Records the result of processing the event.
+    pub fn record(
+        &mut self,
+        event: &Event,
+        reg: &EventKlassRegistry,
+        result: &Result<(), ConversionError>,
+    ) {
+        self.event_count += 1;
+        let error = match result {
+            Ok(()) => return,
+            Err(error) => error,
+        };
+
+        self.failed_event_count += 1;
+        let klass_name = reg.get_klass_by_id(event.get_klass_id()).map_or_else(
+            || format!("<klass {}>", event.get_klass_id()),
+            |klass| klass.get_name().clone(),
+        );
+        for error in error.flatten() {
+            *self.kinds.entry(error.kind()).or_insert(0) += 1;
+            self.klasses
+                .entry((error.kind(), klass_name.clone()))
+                .or_insert_with(|| KlassErrors {
+                    count: 0,
+                    sample: format!("{}: {}", describe_event(event, &klass_name), error),
+                })
+                .count += 1;
+        }
+    }
+
+    /// Records the result of finishing the converter (see `Converter::finish`);
+    /// errors are counted by kind, but not as failed events.
+    pub fn record_finish(&mut self, result: &Result<(), ConversionError>) {
+        if let Err(error) = result {
+            for error in error.flatten() {
+                *self.kinds.entry(error.kind()).or_insert(0) += 1;
+                self.finish_errors.push(error.to_string());
+            }
+        }
+    }
+
+    /// Errors of writing the rest of the output, once all the events are processed;
+    /// the output is likely incomplete.
+    pub fn get_finish_errors(&self) -> &[String] {
+        &self.finish_errors
+    }
+
+    /// Number of all the processed events.
+    pub fn get_event_count(&self) -> u64 {
+        self.event_count
+    }
+
+    /// Number of events with at least one error.
+    pub fn get_failed_event_count(&self) -> u64 {
+        self.failed_event_count
+    }
+
+    pub fn get_error_count(&self, kind: ErrorKind) -> u64 {
+        self.kinds.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// Errors of the kind, per klass name.
+    pub fn get_klass_errors(&self, kind: ErrorKind) -> std::vec::Vec<(&str, &KlassErrors)> {
+        self.klasses
+            .iter()
+            .filter(|((error_kind, _), _)| *error_kind == kind)
+            .map(|((_, klass_name), errors)| (klass_name.as_str(), errors))
+            .collect()
+    }
+
+    pub fn write_summary(&self, writable: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(
+            writable,
+            "Processed {} events, {} failed",
+            self.event_count, self.failed_event_count
+        )?;
+        for (kind, count) in &self.kinds {
+            writeln!(writable, "  {}: {}", kind, count)?;
+            for (klass_name, errors) in self.get_klass_errors(*kind) {
+                writeln!(writable, "    {}: {}", klass_name, errors.count)?;
+                writeln!(writable, "      e.g. {}", errors.sample)?;
+            }
+        }
+        for error in &self.finish_errors {
+            writeln!(writable, "  failed to finish the output: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hawktracer_parser::Value;
+
+    fn make_event(klass_id: u32) -> Event {
+        let mut values = std::collections::HashMap::new();
+        values.insert("timestamp".to_owned(), Value::U64(10));
+        values.insert("name".to_owned(), Value::Str("load".to_owned()));
+        Event::new(klass_id, values)
+    }
+
+    #[test]
+    fn stats_should_count_errors_per_kind_and_klass() {
+        let reg = EventKlassRegistry::new();
+        let mut stats = ErrorStats::new();
+        stats.record(&make_event(99), &reg, &Ok(()));
+        stats.record(
+            &make_event(99),
+            &reg,
+            &Err(ConversionError::MissingField("duration".to_owned())),
+        );
+        stats.record(
+            &make_event(98),
+            &reg,
+            &Err(ConversionError::Multiple(vec![
+                ConversionError::MissingField("duration".to_owned()),
+                ConversionError::UnresolvedLabel,
+            ])),
+        );
+
+        assert_eq!(stats.get_event_count(), 3);
+        assert_eq!(stats.get_failed_event_count(), 2);
+        assert_eq!(stats.get_error_count(ErrorKind::MissingField), 2);
+        assert_eq!(stats.get_error_count(ErrorKind::UnresolvedLabel), 1);
+        assert_eq!(stats.get_error_count(ErrorKind::Io), 0);
+
+        let klass_errors = stats.get_klass_errors(ErrorKind::MissingField);
+        assert_eq!(klass_errors.len(), 2);
+        assert_eq!(klass_errors[1].0, "<klass 99>");
+        assert_eq!(klass_errors[1].1.count, 1);
+        assert_eq!(
+            klass_errors[1].1.sample,
+            "<klass 99> { name: \"load\", timestamp: 10 }: missing field 'duration'"
+        );
+    }
+
+    #[test]
+    fn stats_should_record_finish_errors() {
+        let mut stats = ErrorStats::new();
+        stats.record_finish(&Ok(()));
+        stats.record_finish(&Err(ConversionError::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "disk full",
+        ))));
+
+        assert_eq!(stats.get_failed_event_count(), 0);
+        assert_eq!(stats.get_error_count(ErrorKind::Io), 1);
+        assert_eq!(stats.get_finish_errors(), ["I/O error: disk full"]);
+
+        let mut summary = vec![];
+        stats.write_summary(&mut summary).unwrap();
+        assert!(String::from_utf8(summary)
+            .unwrap()
+            .contains("failed to finish the output: I/O error: disk full"));
+    }
+
+    #[test]
+    fn value_errors_should_be_converted_to_error_kinds() {
+        let event = make_event(99);
+        let missing = event.get_value_u64("duration").unwrap_err();
+        let wrong_type = event.get_value_u32("timestamp").unwrap_err();
+
+        assert_eq!(
+            ConversionError::from_value_error("duration", missing).kind(),
+            ErrorKind::MissingField
+        );
+        assert_eq!(
+            ConversionError::from_value_error("timestamp", wrong_type).kind(),
+            ErrorKind::WrongType
+        );
+    }
+}
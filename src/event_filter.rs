@@ -0,0 +1,312 @@
+mod expression;
+pub use self::expression::parse_duration;
+pub use self::expression::ExpressionError;
+pub use self::expression::FilterExpression;
+
+pub(crate) use self::expression::{integer_value, EvaluationContext};
+use crate::LabelGetter;
+use hawktracer_parser::{CoreEventKlassId, Event, EventKlassRegistry, Value};
+
+/// Decides which events reach converters. Core and mapping events are always
+/// accepted, as converters need them for interpreting other events.
+pub struct EventFilter {
+    label_getter: LabelGetter,
+    threads: std::vec::Vec<String>,
+    thread_names: std::collections::HashMap<i128, String>,
+    klasses: std::vec::Vec<String>,
+    included_labels: std::vec::Vec<regex::Regex>,
+    excluded_labels: std::vec::Vec<regex::Regex>,
+    time_window: (Option<u64>, Option<u64>),
+    first_timestamp: Option<u64>,
+    duration_range: (Option<u64>, Option<u64>),
+    expression: Option<FilterExpression>,
+}
+
+impl Default for EventFilter {
+    /// Filter whose label getter doesn't resolve any labels; it should be
+    /// replaced with `set_label_getter` if labels are matched.
+    fn default() -> EventFilter {
+        EventFilter::new(LabelGetter::new(crate::LabelMap::new(), vec![]))
+    }
+}
+
+impl EventFilter {
+    /// The label getter is used for label patterns and expressions only;
+    /// it should be configured the same way as the converters' ones.
+    pub fn new(label_getter: LabelGetter) -> EventFilter {
+        EventFilter {
+            label_getter,
+            threads: vec![],
+            thread_names: std::collections::HashMap::new(),
+            klasses: vec![],
+            included_labels: vec![],
+            excluded_labels: vec![],
+            time_window: (None, None),
+            first_timestamp: None,
+            duration_range: (None, None),
+            expression: None,
+        }
+    }
+
+    /// Replaces the label getter, e.g. with a copy of the converters' one once
+    /// mappings are prescanned.
+    pub fn set_label_getter(&mut self, label_getter: LabelGetter) {
+        self.label_getter = label_getter;
+    }
+
+    /// Accepts events of the thread with the given id or name. Thread names are
+    /// learned from events with `thread_id` and `thread_name` fields.
+    pub fn add_thread(&mut self, thread: &str) {
+        self.threads.push(thread.to_owned());
+    }
+
+    pub fn add_klass(&mut self, klass_name: &str) {
+        self.klasses.push(klass_name.to_owned());
+    }
+
+    /// Accepts events whose label matches the pattern.
+    pub fn add_label_pattern(&mut self, pattern: &str) -> Result<(), regex::Error> {
+        self.included_labels.push(regex::Regex::new(pattern)?);
+        Ok(())
+    }
+
+    /// Rejects events whose label matches the pattern.
+    pub fn add_excluded_label_pattern(&mut self, pattern: &str) -> Result<(), regex::Error> {
+        self.excluded_labels.push(regex::Regex::new(pattern)?);
+        Ok(())
+    }
+
+    /// Accepts events starting in the window; times are in nanoseconds,
+    /// relative to the timestamp of the first event.
+    pub fn set_time_window(&mut self, start: Option<u64>, end: Option<u64>) {
+        self.time_window = (start, end);
+    }
+
+    /// Accepts events whose duration (in nanoseconds) is within the range.
+    pub fn set_duration_range(&mut self, min: Option<u64>, max: Option<u64>) {
+        self.duration_range = (min, max);
+    }
+
+    pub fn set_expression(&mut self, expression: FilterExpression) {
+        self.expression = Some(expression);
+    }
+
+    fn get_integer(event: &Event, name: &str) -> Option<i128> {
+        event.get_raw_value(name).and_then(integer_value)
+    }
+
+    fn is_thread_accepted(&self, event: &Event) -> bool {
+        let thread_id = match EventFilter::get_integer(event, "thread_id") {
+            Some(thread_id) => thread_id,
+            None => return false,
+        };
+        let thread_name = self.thread_names.get(&thread_id);
+        self.threads.iter().any(|thread| {
+            thread.parse::<i128>().ok() == Some(thread_id) || thread_name == Some(thread)
+        })
+    }
+
+    fn is_in_range(value: Option<u64>, range: (Option<u64>, Option<u64>)) -> bool {
+        match value {
+            Some(value) => {
+                range.0.map_or(true, |min| value >= min) && range.1.map_or(true, |max| value <= max)
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_accepted(&mut self, event: &Event, reg: &EventKlassRegistry) -> bool {
+        if let (Some(thread_id), Some(Value::Str(name))) = (
+            EventFilter::get_integer(event, "thread_id"),
+            event.get_raw_value("thread_name"),
+        ) {
+            self.thread_names.insert(thread_id, name.clone());
+        }
+
+        if CoreEventKlassId::is_core_klass(event.get_klass_id())
+            || self.label_getter.is_mapping_event(event)
+        {
+            // Mappings are needed for resolving labels of the following events.
+            self.label_getter.get_label(event, reg);
+            return true;
+        }
+
+        // Events not bound to time (e.g. HT_SystemInfoEvent) have zero timestamps;
+        // they don't define the beginning of the trace and pass the time window.
+        let timestamp = event.get_value_u64("timestamp").ok();
+        if self.first_timestamp.is_none() && timestamp.is_some_and(|timestamp| timestamp > 0) {
+            self.first_timestamp = timestamp;
+        }
+
+        if !self.threads.is_empty() && !self.is_thread_accepted(event) {
+            return false;
+        }
+
+        let klass_name = reg
+            .get_klass_by_id(event.get_klass_id())
+            .map(|klass| klass.get_name().as_str());
+        if !self.klasses.is_empty()
+            && !self
+                .klasses
+                .iter()
+                .any(|klass| Some(klass.as_str()) == klass_name)
+        {
+            return false;
+        }
+
+        if self.duration_range != (None, None)
+            && !EventFilter::is_in_range(event.get_value_u64("duration").ok(), self.duration_range)
+        {
+            return false;
+        }
+
+        if self.time_window != (None, None) && timestamp != Some(0) {
+            let relative_time = timestamp
+                .zip(self.first_timestamp)
+                .map(|(timestamp, first)| timestamp.saturating_sub(first));
+            if !EventFilter::is_in_range(relative_time, self.time_window) {
+                return false;
+            }
+        }
+
+        if self.included_labels.is_empty()
+            && self.excluded_labels.is_empty()
+            && self.expression.is_none()
+        {
+            return true;
+        }
+
+        let category = self.label_getter.get_category(event, reg).cloned();
+        let label = self
+            .label_getter
+            .get_label(event, reg)
+            .map(|(_, label)| label.clone());
+
+        if !self.included_labels.is_empty()
+            && !label.as_ref().is_some_and(|label| {
+                self.included_labels
+                    .iter()
+                    .any(|pattern| pattern.is_match(label))
+            })
+        {
+            return false;
+        }
+        if let Some(label) = &label {
+            if self
+                .excluded_labels
+                .iter()
+                .any(|pattern| pattern.is_match(label))
+            {
+                return false;
+            }
+        }
+
+        self.expression.as_ref().map_or(true, |expression| {
+            expression.evaluate(&EvaluationContext {
+                event,
+                label: label.as_deref(),
+                category: category.as_deref(),
+                klass_name,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LabelMap;
+
+    fn make_event(thread_id: u32, timestamp: u64, duration: u64, name: u64) -> Event {
+        let mut values = std::collections::HashMap::new();
+        values.insert("thread_id".to_owned(), Value::U32(thread_id));
+        values.insert("timestamp".to_owned(), Value::U64(timestamp));
+        values.insert("duration".to_owned(), Value::U64(duration));
+        values.insert("name".to_owned(), Value::U64(name));
+        Event::new(99, values)
+    }
+
+    fn make_filter() -> EventFilter {
+        let mut label_map = LabelMap::new();
+        label_map.add_mapping(1, "IO_read");
+        label_map.add_mapping(2, "compute");
+        EventFilter::new(LabelGetter::new(label_map, vec!["name".to_owned()]))
+    }
+
+    #[test]
+    fn filter_without_rules_should_accept_all_events() {
+        let reg = EventKlassRegistry::new();
+        let mut filter = make_filter();
+
+        assert!(filter.is_accepted(&make_event(1, 0, 10, 1), &reg));
+        assert!(filter.is_accepted(&Event::new(99, std::collections::HashMap::new()), &reg));
+    }
+
+    #[test]
+    fn filter_should_check_threads_time_and_duration() {
+        let reg = EventKlassRegistry::new();
+        let mut filter = make_filter();
+        filter.add_thread("2");
+        filter.add_thread("worker");
+        filter.set_time_window(Some(100), Some(1000));
+        filter.set_duration_range(Some(10), None);
+
+        let mut name_values = std::collections::HashMap::new();
+        name_values.insert("thread_id".to_owned(), Value::U32(3));
+        name_values.insert("thread_name".to_owned(), Value::Str("worker".to_owned()));
+        filter.is_accepted(&Event::new(98, name_values), &reg);
+
+        // The first event defines the beginning of the time window.
+        assert!(!filter.is_accepted(&make_event(2, 5000, 10, 1), &reg));
+        assert!(filter.is_accepted(&make_event(2, 5100, 10, 1), &reg));
+        assert!(filter.is_accepted(&make_event(3, 5100, 10, 1), &reg));
+        assert!(!filter.is_accepted(&make_event(4, 5100, 10, 1), &reg));
+        assert!(!filter.is_accepted(&make_event(2, 5100, 9, 1), &reg));
+        assert!(!filter.is_accepted(&make_event(2, 6001, 10, 1), &reg));
+    }
+
+    #[test]
+    fn filter_should_measure_time_window_from_first_timed_event() {
+        let reg = EventKlassRegistry::new();
+        let mut filter = make_filter();
+        filter.set_time_window(None, Some(1000));
+
+        let mut system_info_values = std::collections::HashMap::new();
+        system_info_values.insert("timestamp".to_owned(), Value::U64(0));
+        assert!(filter.is_accepted(&Event::new(98, system_info_values), &reg));
+
+        assert!(filter.is_accepted(&make_event(1, 5000, 10, 1), &reg));
+        assert!(filter.is_accepted(&make_event(1, 6000, 10, 1), &reg));
+        assert!(!filter.is_accepted(&make_event(1, 6001, 10, 1), &reg));
+    }
+
+    #[test]
+    fn filter_should_check_labels_and_expression() {
+        let reg = EventKlassRegistry::new();
+        let mut filter = make_filter();
+        filter.add_label_pattern("^IO|^comp").unwrap();
+        filter.add_excluded_label_pattern("ute$").unwrap();
+        filter.set_expression(FilterExpression::parse("duration >= 1us").unwrap());
+
+        assert!(filter.is_accepted(&make_event(1, 0, 1000, 1), &reg));
+        assert!(!filter.is_accepted(&make_event(1, 0, 999, 1), &reg));
+        assert!(!filter.is_accepted(&make_event(1, 0, 1000, 2), &reg));
+        assert!(!filter.is_accepted(&make_event(1, 0, 1000, 3), &reg));
+    }
+
+    #[test]
+    fn filter_should_accept_core_events() {
+        let reg = EventKlassRegistry::new();
+        let mut filter = make_filter();
+        filter.add_klass("MyEvent");
+
+        assert!(filter.is_accepted(
+            &Event::new(
+                CoreEventKlassId::KlassInfo as u32,
+                std::collections::HashMap::new()
+            ),
+            &reg
+        ));
+        assert!(!filter.is_accepted(&make_event(1, 0, 10, 1), &reg));
+    }
+}
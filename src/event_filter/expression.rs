@@ -0,0 +1,487 @@
+use hawktracer_parser::{Event, Value};
+use std::convert::TryFrom;
+
+#[derive(Debug, PartialEq)]
+pub struct ExpressionError {
+    /// Byte offset in the expression.
+    pub position: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "at position {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// Converts a number with an optional time unit (ns, us, ms, s) to nanoseconds.
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let unit_pos = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_pos);
+    apply_time_unit(number, unit)
+}
+
+fn apply_time_unit(number: &str, unit: &str) -> Result<u64, String> {
+    let multiplier: u128 = match unit {
+        "" | "ns" => 1,
+        "us" => 1_000,
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        _ => return Err(format!("unknown time unit '{}'", unit)),
+    };
+
+    let (integer, fraction) = match number.find('.') {
+        Some(dot_pos) => (&number[..dot_pos], &number[dot_pos + 1..]),
+        None => (number, ""),
+    };
+    if integer.is_empty()
+        || !integer.bytes().all(|c| c.is_ascii_digit())
+        || !fraction.bytes().all(|c| c.is_ascii_digit())
+    {
+        return Err(format!("invalid number '{}'", number));
+    }
+
+    let too_large = || format!("number '{}{}' is too large", number, unit);
+    let mut value = integer
+        .parse::<u128>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .ok_or_else(too_large)?;
+    let mut scale = multiplier;
+    for digit in fraction.bytes() {
+        scale /= 10;
+        value += u128::from(digit - b'0') * scale;
+    }
+    if scale == 0 && fraction.bytes().any(|c| c != b'0') {
+        return Err(format!("'{}{}' is more precise than 1ns", number, unit));
+    }
+    u64::try_from(value).map_err(|_| too_large())
+}
+
+pub(crate) fn integer_value(value: &Value) -> Option<i128> {
+    match value {
+        Value::U8(v) => Some(i128::from(*v)),
+        Value::I8(v) => Some(i128::from(*v)),
+        Value::U16(v) => Some(i128::from(*v)),
+        Value::I16(v) => Some(i128::from(*v)),
+        Value::U32(v) => Some(i128::from(*v)),
+        Value::I32(v) => Some(i128::from(*v)),
+        Value::U64(v) => Some(i128::from(*v)),
+        Value::I64(v) => Some(i128::from(*v)),
+        Value::Str(_) | Value::Struct(_) => None,
+    }
+}
+
+/// Event data available to an expression.
+pub(crate) struct EvaluationContext<'a> {
+    pub event: &'a Event,
+    pub label: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub klass_name: Option<&'a str>,
+}
+
+enum EvaluatedValue<'a> {
+    Number(i128),
+    Text(std::borrow::Cow<'a, str>),
+}
+
+impl<'a> EvaluationContext<'a> {
+    fn get(&self, name: &str) -> Option<EvaluatedValue<'a>> {
+        let text = |value: Option<&'a str>| value.map(|v| EvaluatedValue::Text(v.into()));
+        match name {
+            "label" => text(self.label),
+            "category" => text(self.category),
+            "klass" => text(self.klass_name),
+            "klass_id" => Some(EvaluatedValue::Number(i128::from(
+                self.event.get_klass_id(),
+            ))),
+            _ => match self.event.get_raw_value(name)? {
+                Value::Str(value) => Some(EvaluatedValue::Text(value.as_str().into())),
+                value => integer_value(value).map(EvaluatedValue::Number),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Field(String),
+    Number(i128),
+    Text(String),
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    Match(Operand, regex::Regex, bool),
+    // True if the value exists and is not zero or an empty string.
+    Truthy(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(i128),
+    Text(String),
+    Operator(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "&&", "||", "==", "!=", "<=", ">=", "=~", "!~", "<", ">", "!", "(", ")", "-",
+];
+
+fn tokenize(expression: &str) -> Result<std::vec::Vec<(usize, Token)>, ExpressionError> {
+    let mut tokens = vec![];
+    let bytes = expression.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        if c.is_ascii_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() {
+            while pos < bytes.len() && (bytes[pos].is_ascii_digit() || bytes[pos] == b'.') {
+                pos += 1;
+            }
+            let number_end = pos;
+            while pos < bytes.len() && bytes[pos].is_ascii_alphabetic() {
+                pos += 1;
+            }
+            let (number, unit) = (&expression[start..number_end], &expression[number_end..pos]);
+            if unit.is_empty() && number.contains('.') {
+                return Err(ExpressionError {
+                    position: start,
+                    message: "fractional numbers require a time unit".to_owned(),
+                });
+            }
+            let value = apply_time_unit(number, unit).map_err(|message| ExpressionError {
+                position: start,
+                message,
+            })?;
+            tokens.push((start, Token::Number(i128::from(value))));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while pos < bytes.len()
+                && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_' || bytes[pos] == b'.')
+            {
+                pos += 1;
+            }
+            tokens.push((start, Token::Identifier(expression[start..pos].to_owned())));
+        } else if c == b'"' {
+            let mut text = String::new();
+            let mut chars = expression[pos + 1..].char_indices();
+            loop {
+                let next = match chars.next() {
+                    Some((_, '\\')) => chars.next().map(|(offset, c)| (offset, c, true)),
+                    next => next.map(|(offset, c)| (offset, c, false)),
+                };
+                match next {
+                    Some((offset, '"', false)) => {
+                        pos += offset + 2;
+                        break;
+                    }
+                    Some((_, c, true)) if c != '"' && c != '\\' => {
+                        // Other escape sequences are kept for regular expressions, e.g. "\d".
+                        text.push('\\');
+                        text.push(c);
+                    }
+                    Some((_, c, _)) => text.push(c),
+                    None => {
+                        return Err(ExpressionError {
+                            position: start,
+                            message: "unterminated string".to_owned(),
+                        })
+                    }
+                }
+            }
+            tokens.push((start, Token::Text(text)));
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| expression[pos..].starts_with(**operator))
+                .ok_or_else(|| ExpressionError {
+                    position: start,
+                    message: format!(
+                        "unexpected character '{}'",
+                        expression[pos..].chars().next().unwrap()
+                    ),
+                })?;
+            pos += operator.len();
+            tokens.push((start, Token::Operator(operator)));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: std::vec::Vec<(usize, Token)>,
+    pos: usize,
+    length: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.length, |(position, _)| *position)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ExpressionError> {
+        Err(ExpressionError {
+            position: self.position(),
+            message: message.to_owned(),
+        })
+    }
+
+    fn accept(&mut self, operator: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Operator(op)) if *op == operator) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ExpressionError> {
+        let mut expr = self.parse_and()?;
+        while self.accept("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExpressionError> {
+        let mut expr = self.parse_unary()?;
+        while self.accept("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExpressionError> {
+        if self.accept("!") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.accept("(") {
+            let expr = self.parse_or()?;
+            if !self.accept(")") {
+                return self.error("expected ')'");
+            }
+            return Ok(expr);
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, ExpressionError> {
+        let negative = self.accept("-");
+        let operand = match self.peek().cloned() {
+            Some(Token::Number(value)) => Operand::Number(if negative { -value } else { value }),
+            _ if negative => return self.error("expected a number"),
+            Some(Token::Identifier(name)) => Operand::Field(name),
+            Some(Token::Text(text)) => Operand::Text(text),
+            _ => return self.error("expected a field name, a number or a string"),
+        };
+        self.pos += 1;
+        Ok(operand)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ExpressionError> {
+        let left = self.parse_operand()?;
+        let operator = match self.peek() {
+            Some(Token::Operator(operator)) => *operator,
+            _ => return Ok(Expr::Truthy(left)),
+        };
+
+        let compare_op = match operator {
+            "==" => CompareOp::Equal,
+            "!=" => CompareOp::NotEqual,
+            "<" => CompareOp::Less,
+            "<=" => CompareOp::LessOrEqual,
+            ">" => CompareOp::Greater,
+            ">=" => CompareOp::GreaterOrEqual,
+            "=~" | "!~" => {
+                self.pos += 1;
+                let position = self.position();
+                let pattern = match self.parse_operand()? {
+                    Operand::Text(pattern) => pattern,
+                    _ => {
+                        return Err(ExpressionError {
+                            position,
+                            message: "expected a regular expression string".to_owned(),
+                        })
+                    }
+                };
+                let regex = regex::Regex::new(&pattern).map_err(|err| ExpressionError {
+                    position,
+                    message: err.to_string(),
+                })?;
+                return Ok(Expr::Match(left, regex, operator == "!~"));
+            }
+            _ => return Ok(Expr::Truthy(left)),
+        };
+        self.pos += 1;
+        let right = self.parse_operand()?;
+        Ok(Expr::Compare(left, compare_op, right))
+    }
+}
+
+/// Boolean expression over event fields, e.g. `duration > 1ms && label =~ "^IO"`.
+#[derive(Debug)]
+pub struct FilterExpression {
+    expr: Expr,
+}
+
+impl FilterExpression {
+    pub fn parse(expression: &str) -> Result<FilterExpression, ExpressionError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            pos: 0,
+            length: expression.len(),
+        };
+        let expr = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return parser.error("unexpected token");
+        }
+        Ok(FilterExpression { expr })
+    }
+
+    pub(crate) fn evaluate(&self, context: &EvaluationContext) -> bool {
+        evaluate_expr(&self.expr, context)
+    }
+}
+
+fn evaluate_operand<'a>(
+    operand: &'a Operand,
+    context: &EvaluationContext<'a>,
+) -> Option<EvaluatedValue<'a>> {
+    match operand {
+        Operand::Field(name) => context.get(name),
+        Operand::Number(value) => Some(EvaluatedValue::Number(*value)),
+        Operand::Text(text) => Some(EvaluatedValue::Text(text.as_str().into())),
+    }
+}
+
+fn evaluate_expr(expr: &Expr, context: &EvaluationContext) -> bool {
+    match expr {
+        Expr::And(left, right) => evaluate_expr(left, context) && evaluate_expr(right, context),
+        Expr::Or(left, right) => evaluate_expr(left, context) || evaluate_expr(right, context),
+        Expr::Not(expr) => !evaluate_expr(expr, context),
+        Expr::Truthy(operand) => match evaluate_operand(operand, context) {
+            Some(EvaluatedValue::Number(value)) => value != 0,
+            Some(EvaluatedValue::Text(text)) => !text.is_empty(),
+            None => false,
+        },
+        Expr::Match(operand, regex, negate) => match evaluate_operand(operand, context) {
+            Some(EvaluatedValue::Text(text)) => regex.is_match(&text) != *negate,
+            Some(EvaluatedValue::Number(value)) => regex.is_match(&value.to_string()) != *negate,
+            None => false,
+        },
+        // Missing values, and values of different types are never equal, nor ordered.
+        Expr::Compare(left, op, right) => {
+            let ordering = match (
+                evaluate_operand(left, context),
+                evaluate_operand(right, context),
+            ) {
+                (Some(EvaluatedValue::Number(l)), Some(EvaluatedValue::Number(r))) => l.cmp(&r),
+                (Some(EvaluatedValue::Text(l)), Some(EvaluatedValue::Text(r))) => l.cmp(&r),
+                _ => return false,
+            };
+            match op {
+                CompareOp::Equal => ordering == std::cmp::Ordering::Equal,
+                CompareOp::NotEqual => ordering != std::cmp::Ordering::Equal,
+                CompareOp::Less => ordering == std::cmp::Ordering::Less,
+                CompareOp::LessOrEqual => ordering != std::cmp::Ordering::Greater,
+                CompareOp::Greater => ordering == std::cmp::Ordering::Greater,
+                CompareOp::GreaterOrEqual => ordering != std::cmp::Ordering::Less,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_event() -> Event {
+        let mut values = std::collections::HashMap::new();
+        values.insert("duration".to_owned(), Value::U64(2_500_000));
+        values.insert("thread_id".to_owned(), Value::U32(3));
+        values.insert("offset".to_owned(), Value::I32(-4));
+        values.insert("path".to_owned(), Value::Str("/tmp/a".to_owned()));
+        Event::new(99, values)
+    }
+
+    fn evaluate(expression: &str) -> bool {
+        let event = make_event();
+        FilterExpression::parse(expression)
+            .unwrap()
+            .evaluate(&EvaluationContext {
+                event: &event,
+                label: Some("IO_read"),
+                category: None,
+                klass_name: Some("MyEvent"),
+            })
+    }
+
+    #[test]
+    fn parse_duration_should_support_time_units() {
+        assert_eq!(parse_duration("15"), Ok(15));
+        assert_eq!(parse_duration("2us"), Ok(2_000));
+        assert_eq!(parse_duration("1.5ms"), Ok(1_500_000));
+        assert_eq!(parse_duration("3s"), Ok(3_000_000_000));
+        assert!(parse_duration("1.5").is_err());
+        assert!(parse_duration("1.5ns").is_err());
+        assert!(parse_duration("3h").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
+    #[test]
+    fn expression_should_compare_fields() {
+        assert!(evaluate(r#"duration > 1ms && label =~ "^IO""#));
+        assert!(!evaluate(r#"duration > 1ms && label !~ "^IO""#));
+        assert!(evaluate("duration <= 2.5ms && thread_id == 3"));
+        assert!(evaluate("offset < -3 || missing > 1"));
+        assert!(evaluate(r#"!(klass == "Other") && path == "/tmp/a""#));
+        assert!(evaluate("thread_id && !missing"));
+        // Values of different types, and missing values, don't compare.
+        assert!(!evaluate(r#"thread_id != "3""#));
+        assert!(!evaluate("category != 1"));
+        assert!(evaluate(
+            r#"path =~ "^/\w+/a$" && "say \"hi\"" == "say \"hi\"""#
+        ));
+    }
+
+    #[test]
+    fn parse_should_report_error_position() {
+        let error = FilterExpression::parse("duration > && 1").unwrap_err();
+        assert_eq!(error.position, 11);
+
+        assert!(FilterExpression::parse("duration > 1.5").is_err());
+        assert!(FilterExpression::parse(r#"label =~ "(""#).is_err());
+        assert!(FilterExpression::parse(r#"label == "abc"#).is_err());
+        assert!(FilterExpression::parse(r#"label == "abc\"#).is_err());
+        assert!(FilterExpression::parse("label == é").is_err());
+        assert!(FilterExpression::parse("(duration > 1").is_err());
+        assert!(FilterExpression::parse("duration > 1 2").is_err());
+    }
+}
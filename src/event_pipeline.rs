@@ -0,0 +1,561 @@
+use hawktracer_parser::data_provider::DataProvider;
+use hawktracer_parser::event_klass::EventKlass;
+use hawktracer_parser::event_reader::EventReader;
+use hawktracer_parser::{CoreEventKlassId, DataType, Event, EventKlassRegistry};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNK_QUEUE_SIZE: usize = 64;
+
+/// What the parser does with an event when the event queue is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackpressurePolicy {
+    /// Waits for the converter; the source is read slower.
+    Block,
+    /// Drops the event, so the source is read at full speed.
+    Drop,
+}
+
+impl std::str::FromStr for BackpressurePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<BackpressurePolicy, String> {
+        match value {
+            "block" => Ok(BackpressurePolicy::Block),
+            "drop" => Ok(BackpressurePolicy::Drop),
+            _ => Err(format!("unknown backpressure policy '{}'", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineStats {
+    pub bytes_read: u64,
+    pub parsed_events: u64,
+    pub dropped_events: u64,
+    pub queue_size: usize,
+    pub queue_depth: usize,
+    pub max_queue_depth: usize,
+    pub average_queue_depth: f64,
+    pub elapsed: std::time::Duration,
+}
+
+impl PipelineStats {
+    pub fn get_events_per_second(&self) -> f64 {
+        self.parsed_events as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn get_bytes_per_second(&self) -> f64 {
+        self.bytes_read as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn write_summary(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "Read {} bytes ({:.1} KiB/s), parsed {} events ({:.0} events/s) in {:.2}s",
+            self.bytes_read,
+            self.get_bytes_per_second() / 1024.0,
+            self.parsed_events,
+            self.get_events_per_second(),
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            writer,
+            "Event queue depth: average {:.1}, max {} of {}",
+            self.average_queue_depth, self.max_queue_depth, self.queue_size
+        )?;
+        if self.dropped_events > 0 {
+            writeln!(
+                writer,
+                "Dropped {} events, as the conversion was too slow",
+                self.dropped_events
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct SharedCounters {
+    bytes_read: AtomicU64,
+    parsed_events: AtomicU64,
+    dropped_events: AtomicU64,
+    queue_depth: AtomicUsize,
+}
+
+/// Reads and parses events on separate threads, so a slow conversion doesn't
+/// stall the source: the ingestion thread reads raw data, the parser thread
+/// turns it into (flattened) events, and the events are received from
+/// the pipeline. Stages are connected with bounded queues; data is never
+/// dropped between ingestion and parsing, as the stream couldn't be parsed
+/// any further.
+pub struct EventPipeline {
+    receiver: Receiver<Event>,
+    counters: Arc<SharedCounters>,
+    queue_size: usize,
+    // Klasses are defined by events in the stream; the parser thread has its own registry.
+    registry: EventKlassRegistry,
+    max_queue_depth: usize,
+    queue_depth_sum: u64,
+    received_events: u64,
+    started_at: std::time::Instant,
+}
+
+impl EventPipeline {
+    /// Starts reading the source. Events of core klasses, and of `kept_klasses`
+    /// (e.g. mapping klasses) are never dropped; reading stops once
+    /// `running_flag` is cleared.
+    pub fn start(
+        source: Box<dyn std::io::Read + Send>,
+        queue_size: usize,
+        policy: BackpressurePolicy,
+        kept_klasses: std::vec::Vec<String>,
+        running_flag: Arc<AtomicBool>,
+    ) -> EventPipeline {
+        let counters = Arc::new(SharedCounters::default());
+        let (chunk_sender, chunk_receiver) = std::sync::mpsc::sync_channel(CHUNK_QUEUE_SIZE);
+        let (event_sender, event_receiver) = std::sync::mpsc::sync_channel(queue_size);
+
+        let ingestion_counters = counters.clone();
+        let ingestion_running_flag = running_flag.clone();
+        std::thread::spawn(move || {
+            ingest(
+                source,
+                chunk_sender,
+                &ingestion_counters,
+                &ingestion_running_flag,
+            )
+        });
+
+        let parser_counters = counters.clone();
+        std::thread::spawn(move || {
+            let mut parser = Parser {
+                reader: EventReader::new(DataProvider::new(Box::new(ChunkReader {
+                    receiver: chunk_receiver,
+                    chunk: vec![],
+                    position: 0,
+                }))),
+                sender: event_sender,
+                policy,
+                kept_klasses,
+                counters: &parser_counters,
+            };
+            parser.run(&running_flag)
+        });
+
+        EventPipeline {
+            receiver: event_receiver,
+            counters,
+            queue_size,
+            registry: EventKlassRegistry::new(),
+            max_queue_depth: 0,
+            queue_depth_sum: 0,
+            received_events: 0,
+            started_at: std::time::Instant::now(),
+        }
+    }
+
+    /// Waits for the next event; fails on timeout, or once the source is exhausted.
+    pub fn recv_timeout(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> Result<Event, RecvTimeoutError> {
+        let event = self.receiver.recv_timeout(timeout)?;
+        self.on_event_received(&event);
+        Ok(event)
+    }
+
+    /// Returns an already parsed event, without waiting.
+    pub fn try_recv(&mut self) -> Result<Event, TryRecvError> {
+        let event = self.receiver.try_recv()?;
+        self.on_event_received(&event);
+        Ok(event)
+    }
+
+    /// Registry of klasses defined by the events received so far.
+    pub fn get_registry(&self) -> &EventKlassRegistry {
+        &self.registry
+    }
+
+    pub fn get_stats(&self) -> PipelineStats {
+        PipelineStats {
+            bytes_read: self.counters.bytes_read.load(Ordering::Relaxed),
+            parsed_events: self.counters.parsed_events.load(Ordering::Relaxed),
+            dropped_events: self.counters.dropped_events.load(Ordering::Relaxed),
+            queue_size: self.queue_size,
+            queue_depth: self
+                .counters
+                .queue_depth
+                .load(Ordering::Relaxed)
+                .min(self.queue_size),
+            max_queue_depth: self.max_queue_depth,
+            average_queue_depth: if self.received_events > 0 {
+                self.queue_depth_sum as f64 / self.received_events as f64
+            } else {
+                0.0
+            },
+            elapsed: self.started_at.elapsed(),
+        }
+    }
+
+    fn on_event_received(&mut self, event: &Event) {
+        // Depth before the event was taken from the queue; the counter includes
+        // an event the parser waits to queue.
+        let depth = self
+            .counters
+            .queue_depth
+            .fetch_sub(1, Ordering::Relaxed)
+            .min(self.queue_size);
+        self.max_queue_depth = self.max_queue_depth.max(depth);
+        self.queue_depth_sum += depth as u64;
+        self.received_events += 1;
+        update_registry(&mut self.registry, event);
+    }
+}
+
+fn ingest(
+    mut source: Box<dyn std::io::Read + Send>,
+    sender: SyncSender<std::vec::Vec<u8>>,
+    counters: &SharedCounters,
+    running_flag: &AtomicBool,
+) {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    while running_flag.load(Ordering::SeqCst) {
+        let size = match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(size) => size,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        counters
+            .bytes_read
+            .fetch_add(size as u64, Ordering::Relaxed);
+        if sender.send(buffer[..size].to_vec()).is_err() {
+            break;
+        }
+    }
+}
+
+/// Data of the ingestion thread, as a stream.
+struct ChunkReader {
+    receiver: Receiver<std::vec::Vec<u8>>,
+    chunk: std::vec::Vec<u8>,
+    position: usize,
+}
+
+impl std::io::Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let size = buf.len().min(self.chunk.len() - self.position);
+        buf[..size].copy_from_slice(&self.chunk[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+struct Parser<'a> {
+    reader: EventReader,
+    sender: SyncSender<Event>,
+    policy: BackpressurePolicy,
+    kept_klasses: std::vec::Vec<String>,
+    counters: &'a SharedCounters,
+}
+
+impl<'a> Parser<'a> {
+    fn run(&mut self, running_flag: &AtomicBool) {
+        let mut reg = EventKlassRegistry::new();
+        while running_flag.load(Ordering::SeqCst) {
+            let event = match self.reader.read_event(&mut reg) {
+                Ok(event) => event.flat_event(),
+                Err(_) => break,
+            };
+            self.counters.parsed_events.fetch_add(1, Ordering::Relaxed);
+            if !self.send(event, &reg) {
+                break;
+            }
+        }
+    }
+
+    // Returns false if the receiving side is gone.
+    fn send(&self, event: Event, reg: &EventKlassRegistry) -> bool {
+        // Counted before sending, so the receiver never sees more events than the depth.
+        self.counters.queue_depth.fetch_add(1, Ordering::Relaxed);
+        let result = if self.policy == BackpressurePolicy::Block || self.is_kept(&event, reg) {
+            self.sender.send(event).map_err(|_| false)
+        } else {
+            self.sender.try_send(event).map_err(|err| match err {
+                TrySendError::Full(_) => {
+                    self.counters.dropped_events.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                TrySendError::Disconnected(_) => false,
+            })
+        };
+        match result {
+            Ok(()) => true,
+            Err(is_connected) => {
+                self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                is_connected
+            }
+        }
+    }
+
+    fn is_kept(&self, event: &Event, reg: &EventKlassRegistry) -> bool {
+        CoreEventKlassId::is_core_klass(event.get_klass_id())
+            || reg
+                .get_klass_by_id(event.get_klass_id())
+                .is_some_and(|klass| self.kept_klasses.contains(klass.get_name()))
+    }
+}
+
+/// Defines klasses described by a (flattened) klass or field info event,
+/// the same way the parser does.
+// Mirrors the private registry updater of hawktracer-parser 0.2.3 (including
+// its data type ids and integer sizes); it has to be checked against the
+// parser's one whenever the parser is upgraded.
+fn update_registry(reg: &mut EventKlassRegistry, event: &Event) {
+    let klass_id = event.get_klass_id();
+    if klass_id != CoreEventKlassId::KlassInfo as u32
+        && klass_id != CoreEventKlassId::FieldInfo as u32
+    {
+        return;
+    }
+    let info_klass_id = match event.get_value_u32("info_klass_id") {
+        Ok(info_klass_id) if !CoreEventKlassId::is_core_klass(info_klass_id) => info_klass_id,
+        _ => return,
+    };
+
+    if klass_id == CoreEventKlassId::KlassInfo as u32 {
+        if let Ok(klass_name) = event.get_value_string("event_klass_name") {
+            reg.add_klass(EventKlass::new(info_klass_id, klass_name.clone()));
+        }
+        return;
+    }
+
+    let data_type = match event.get_value_u8("data_type") {
+        Ok(1) => DataType::Struct,
+        Ok(2) => DataType::Str,
+        Ok(6) => DataType::U64,
+        Ok(99) => match event.get_value_u64("size") {
+            Ok(1) => DataType::U8,
+            Ok(4) => DataType::U32,
+            Ok(8) => DataType::U64,
+            _ => return,
+        },
+        _ => return,
+    };
+    if let (Ok(field_name), Ok(type_name), Some(klass)) = (
+        event.get_value_string("field_name"),
+        event.get_value_string("field_type"),
+        reg.get_klass_by_id_mut(info_klass_id),
+    ) {
+        klass.add_field(field_name.clone(), type_name.clone(), data_type);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_TRACE: &str = "resources/test/test_trace.htdump";
+
+    fn start_pipeline(
+        source: Box<dyn std::io::Read + Send>,
+        queue_size: usize,
+        policy: BackpressurePolicy,
+        kept_klasses: std::vec::Vec<String>,
+    ) -> EventPipeline {
+        EventPipeline::start(
+            source,
+            queue_size,
+            policy,
+            kept_klasses,
+            Arc::new(AtomicBool::new(true)),
+        )
+    }
+
+    fn receive_all(pipeline: &mut EventPipeline) -> std::vec::Vec<Event> {
+        let mut events = vec![];
+        while let Ok(event) = pipeline.recv_timeout(std::time::Duration::from_secs(10)) {
+            events.push(event);
+        }
+        events
+    }
+
+    fn get_klass_name(pipeline: &EventPipeline, event: &Event) -> String {
+        pipeline
+            .get_registry()
+            .get_klass_by_id(event.get_klass_id())
+            .map(|klass| klass.get_name().clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn pipeline_should_deliver_all_events_with_their_klasses() {
+        let mut pipeline = start_pipeline(
+            Box::new(std::fs::File::open(TEST_TRACE).unwrap()),
+            4,
+            BackpressurePolicy::Block,
+            vec![],
+        );
+
+        let mut klass_names = vec![];
+        while let Ok(event) = pipeline.recv_timeout(std::time::Duration::from_secs(10)) {
+            klass_names.push(get_klass_name(&pipeline, &event));
+        }
+
+        assert_eq!(klass_names.len(), 53);
+        assert_eq!(
+            klass_names
+                .iter()
+                .filter(|name| *name == "HT_CallstackIntEvent")
+                .count(),
+            11
+        );
+        let field_names = |reg: &EventKlassRegistry| {
+            reg.get_klass_by_name("HT_CallstackIntEvent")
+                .unwrap()
+                .get_fields()
+                .iter()
+                .map(|field| field.get_name().clone())
+                .collect::<std::vec::Vec<_>>()
+        };
+        let mut reader = EventReader::new(DataProvider::new(Box::new(
+            std::fs::File::open(TEST_TRACE).unwrap(),
+        )));
+        let mut reg = EventKlassRegistry::new();
+        while reader.read_event(&mut reg).is_ok() {}
+        assert_eq!(field_names(pipeline.get_registry()), field_names(&reg));
+
+        let stats = pipeline.get_stats();
+        assert_eq!(stats.parsed_events, 53);
+        assert_eq!(stats.dropped_events, 0);
+        assert_eq!(stats.queue_depth, 0);
+        assert!(stats.max_queue_depth <= 4);
+        assert_eq!(
+            stats.bytes_read,
+            std::fs::metadata(TEST_TRACE).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn update_registry_should_build_the_same_registry_as_the_parser() {
+        let mut reader = EventReader::new(DataProvider::new(Box::new(
+            std::fs::File::open(TEST_TRACE).unwrap(),
+        )));
+        let mut parser_reg = EventKlassRegistry::new();
+        let mut reg = EventKlassRegistry::new();
+        let mut klass_ids = vec![];
+        while let Ok(event) = reader.read_event(&mut parser_reg) {
+            let event = event.flat_event();
+            if event.get_klass_id() == CoreEventKlassId::KlassInfo as u32 {
+                klass_ids.extend(event.get_value_u32("info_klass_id").ok());
+            }
+            update_registry(&mut reg, &event);
+        }
+
+        let describe = |reg: &EventKlassRegistry, klass_id: u32| {
+            reg.get_klass_by_id(klass_id).map(|klass| {
+                let fields = klass
+                    .get_fields()
+                    .iter()
+                    .map(|field| {
+                        (
+                            field.get_name().clone(),
+                            field.get_type_name().clone(),
+                            *field.get_data_type(),
+                        )
+                    })
+                    .collect::<std::vec::Vec<_>>();
+                (klass.get_name().clone(), fields)
+            })
+        };
+        assert!(klass_ids.len() > 3);
+        for klass_id in klass_ids {
+            assert!(describe(&parser_reg, klass_id).is_some());
+            assert_eq!(describe(&reg, klass_id), describe(&parser_reg, klass_id));
+        }
+    }
+
+    #[test]
+    fn drop_policy_should_keep_core_and_kept_klass_events() {
+        let counters = SharedCounters::default();
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        let mut parser = Parser {
+            reader: EventReader::new(DataProvider::new(Box::new(
+                std::fs::File::open(TEST_TRACE).unwrap(),
+            ))),
+            sender,
+            policy: BackpressurePolicy::Drop,
+            kept_klasses: vec!["HT_StringMappingEvent".to_owned()],
+            counters: &counters,
+        };
+
+        // A slow conversion, driven by the parser: an event is taken from the
+        // queue only when the parser would otherwise wait for a kept event.
+        let mut reg = EventKlassRegistry::new();
+        let mut events = vec![];
+        while let Ok(event) = parser.reader.read_event(&mut reg) {
+            let event = event.flat_event();
+            if parser.is_kept(&event, &reg) {
+                events.extend(receiver.try_recv());
+            }
+            assert!(parser.send(event, &reg));
+        }
+        drop(parser);
+        events.extend(receiver.try_iter());
+
+        let count_klass = |name: &str| {
+            events
+                .iter()
+                .filter(|event| {
+                    reg.get_klass_by_id(event.get_klass_id())
+                        .is_some_and(|klass| klass.get_name() == name)
+                })
+                .count()
+        };
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| CoreEventKlassId::is_core_klass(event.get_klass_id()))
+                .count(),
+            39
+        );
+        assert_eq!(count_klass("HT_StringMappingEvent"), 2);
+        // The queue is always full when other events come.
+        assert_eq!(events.len(), 41);
+        assert_eq!(counters.dropped_events.load(Ordering::Relaxed), 53 - 41);
+    }
+
+    #[test]
+    fn pipeline_should_stop_when_running_flag_is_cleared() {
+        let running_flag = Arc::new(AtomicBool::new(false));
+        let mut pipeline = EventPipeline::start(
+            Box::new(std::fs::File::open(TEST_TRACE).unwrap()),
+            4,
+            BackpressurePolicy::Block,
+            vec![],
+            running_flag,
+        );
+
+        assert!(receive_all(&mut pipeline).is_empty());
+        assert_eq!(pipeline.get_stats().parsed_events, 0);
+    }
+
+    #[test]
+    fn backpressure_policy_should_be_parsed() {
+        assert_eq!("block".parse(), Ok(BackpressurePolicy::Block));
+        assert_eq!("drop".parse(), Ok(BackpressurePolicy::Drop));
+        assert!("wait".parse::<BackpressurePolicy>().is_err());
+    }
+}
@@ -0,0 +1,368 @@
+//! C interface of the conversion, see `include/hawktracer_converter.h`.
+
+use crate::Compression;
+use crate::Conversion;
+use crate::ConverterManager;
+use crate::ConverterOptions;
+use crate::LabelMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+/// Writes data to the conversion output; returns 0 on success.
+pub type ConversionWriteFn =
+    unsafe extern "C" fn(write_context: *mut c_void, data: *const u8, size: usize) -> c_int;
+
+/// Conversion of a capture fed from memory buffers; the data is kept until
+/// the conversion is finished.
+pub struct FfiConversion {
+    format: String,
+    options: std::vec::Vec<(String, String)>,
+    map_files: std::vec::Vec<String>,
+    data: std::vec::Vec<u8>,
+    is_finished: bool,
+    event_count: u64,
+    failed_event_count: u64,
+    error: Option<CString>,
+}
+
+impl FfiConversion {
+    fn set_error(&mut self, message: &str) -> c_int {
+        self.error = Some(CString::new(message.replace('\0', "")).unwrap());
+        -1
+    }
+
+    fn finish(
+        &mut self,
+        writable: Box<dyn std::io::Write>,
+        compression: Option<Compression>,
+    ) -> c_int {
+        if self.is_finished {
+            return self.set_error("conversion is already finished");
+        }
+        self.is_finished = true;
+
+        let mut conversion = Conversion::from_reader(Box::new(std::io::Cursor::new(
+            std::mem::take(&mut self.data),
+        )))
+        .format(&self.format);
+        for (name, value) in &self.options {
+            conversion = conversion.option(name, value);
+        }
+        for path in &self.map_files {
+            conversion = conversion.map_file(path);
+        }
+        if let Some(compression) = compression {
+            conversion = conversion.compression(compression, None);
+        }
+
+        // Panics (e.g. of converters) must not cross the C boundary.
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| conversion.run(writable))) {
+            Ok(Ok(stats)) => {
+                self.event_count = stats.errors.get_event_count();
+                self.failed_event_count = stats.errors.get_failed_event_count();
+                match stats.errors.get_finish_errors().first() {
+                    Some(error) => {
+                        self.set_error(&format!("failed to write the output: {}", error))
+                    }
+                    None => 0,
+                }
+            }
+            Ok(Err(err)) => self.set_error(&err.to_string()),
+            Err(_) => self.set_error("failed to write the output"),
+        }
+    }
+}
+
+struct CallbackWriter {
+    write: ConversionWriteFn,
+    write_context: *mut c_void,
+}
+
+impl std::io::Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match unsafe { (self.write)(self.write_context, buf.as_ptr(), buf.len()) } {
+            0 => Ok(buf.len()),
+            code => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("output callback failed (error code {})", code),
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+unsafe fn from_c_string(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+    }
+}
+
+/// Creates a conversion to the format (e.g. `chrome-tracing`); returns NULL
+/// if the format doesn't exist.
+///
+/// # Safety
+/// `format` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_create(format: *const c_char) -> *mut FfiConversion {
+    let format = match from_c_string(format) {
+        Some(format) => format,
+        None => return std::ptr::null_mut(),
+    };
+    if ConverterManager::new().get_options(&format).is_none() {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(FfiConversion {
+        format,
+        options: vec![],
+        map_files: vec![],
+        data: vec![],
+        is_finished: false,
+        event_count: 0,
+        failed_event_count: 0,
+        error: None,
+    }))
+}
+
+/// Sets an option of the converter; returns 0 on success.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`; `name` and `value`
+/// must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_set_option(
+    conversion: *mut FfiConversion,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let conversion = &mut *conversion;
+    let (name, value) = match (from_c_string(name), from_c_string(value)) {
+        (Some(name), Some(value)) => (name, value),
+        _ => return conversion.set_error("option name and value must be set"),
+    };
+    let schema = ConverterManager::new()
+        .get_options(&conversion.format)
+        .unwrap_or_default();
+    if let Err(err) = ConverterOptions::parse(&schema, &[(&name, &value)]) {
+        return conversion.set_error(&err.to_string());
+    }
+    conversion.options.push((name, value));
+    0
+}
+
+/// Adds a mapping file; returns 0 on success.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`; `path` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_add_map_file(
+    conversion: *mut FfiConversion,
+    path: *const c_char,
+) -> c_int {
+    let conversion = &mut *conversion;
+    let path = match from_c_string(path) {
+        Some(path) => path,
+        None => return conversion.set_error("path must be set"),
+    };
+    if let Err(err) = LabelMap::new().load_from_file(&path) {
+        return conversion.set_error(&format!("failed to load mapping from {}: {}", path, err));
+    }
+    conversion.map_files.push(path);
+    0
+}
+
+/// Appends HawkTracer data (e.g. a buffer flushed by a HawkTracer listener);
+/// returns 0 on success.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`; `data` must point to
+/// `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_feed(
+    conversion: *mut FfiConversion,
+    data: *const u8,
+    size: usize,
+) -> c_int {
+    let conversion = &mut *conversion;
+    if conversion.is_finished {
+        return conversion.set_error("conversion is already finished");
+    }
+    if size > 0 {
+        conversion
+            .data
+            .extend_from_slice(std::slice::from_raw_parts(data, size));
+    }
+    0
+}
+
+/// Converts all the fed data, writing the output to the file; returns 0 on success.
+/// The output is compressed if the file has a `.gz` or `.zst` extension.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`; `path` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_finish_to_file(
+    conversion: *mut FfiConversion,
+    path: *const c_char,
+) -> c_int {
+    let conversion = &mut *conversion;
+    let path = match from_c_string(path) {
+        Some(path) => path,
+        None => return conversion.set_error("path must be set"),
+    };
+    match std::fs::File::create(&path) {
+        Ok(file) => conversion.finish(Box::new(file), Compression::from_path(&path)),
+        Err(err) => conversion.set_error(&format!("unable to create {}: {}", path, err)),
+    }
+}
+
+/// Converts all the fed data, passing the output to the callback; returns 0 on success.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`; `write` must be safe
+/// to call with `write_context`.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_finish_to_callback(
+    conversion: *mut FfiConversion,
+    write: ConversionWriteFn,
+    write_context: *mut c_void,
+) -> c_int {
+    (*conversion).finish(
+        Box::new(CallbackWriter {
+            write,
+            write_context,
+        }),
+        None,
+    )
+}
+
+/// Number of events processed by the finished conversion.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_get_event_count(conversion: *const FfiConversion) -> u64 {
+    (*conversion).event_count
+}
+
+/// Number of events the finished conversion failed to convert.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_get_failed_event_count(
+    conversion: *const FfiConversion,
+) -> u64 {
+    (*conversion).failed_event_count
+}
+
+/// Message of the last error, or NULL; valid until the next call.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_get_error(
+    conversion: *const FfiConversion,
+) -> *const c_char {
+    (*conversion)
+        .error
+        .as_ref()
+        .map_or(std::ptr::null(), |error| error.as_ptr())
+}
+
+/// Releases the conversion; NULL is ignored.
+///
+/// # Safety
+/// `conversion` must come from `htc_conversion_create`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn htc_conversion_destroy(conversion: *mut FfiConversion) {
+    if !conversion.is_null() {
+        drop(Box::from_raw(conversion));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_str(value: &str) -> CString {
+        CString::new(value).unwrap()
+    }
+
+    unsafe extern "C" fn write_to_vec(context: *mut c_void, data: *const u8, size: usize) -> c_int {
+        let output = &mut *(context as *mut std::vec::Vec<u8>);
+        output.extend_from_slice(std::slice::from_raw_parts(data, size));
+        0
+    }
+
+    #[test]
+    fn conversion_should_convert_data_fed_in_chunks() {
+        let data = std::fs::read("resources/test/test_trace.htdump").unwrap();
+        let mut output = std::vec::Vec::<u8>::new();
+        unsafe {
+            let conversion = htc_conversion_create(c_str("chrome-tracing").as_ptr());
+            assert!(!conversion.is_null());
+            assert_eq!(
+                htc_conversion_set_option(
+                    conversion,
+                    c_str("precision").as_ptr(),
+                    c_str("ns").as_ptr()
+                ),
+                0
+            );
+            for chunk in data.chunks(100) {
+                assert_eq!(
+                    htc_conversion_feed(conversion, chunk.as_ptr(), chunk.len()),
+                    0
+                );
+            }
+            assert_eq!(
+                htc_conversion_finish_to_callback(
+                    conversion,
+                    write_to_vec,
+                    &mut output as *mut std::vec::Vec<u8> as *mut c_void
+                ),
+                0
+            );
+            assert_eq!(htc_conversion_get_event_count(conversion), 53);
+            assert_eq!(htc_conversion_get_failed_event_count(conversion), 0);
+            assert_ne!(htc_conversion_feed(conversion, data.as_ptr(), 1), 0);
+            htc_conversion_destroy(conversion);
+        }
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("[{\"name\":\"_second_tracepoint\""));
+        assert_eq!(output.matches("\"ph\":\"X\"").count(), 11);
+    }
+
+    #[test]
+    fn conversion_should_report_errors() {
+        unsafe {
+            assert!(htc_conversion_create(c_str("unknown").as_ptr()).is_null());
+
+            let conversion = htc_conversion_create(c_str("flamegraph").as_ptr());
+            assert!(htc_conversion_get_error(conversion).is_null());
+            assert_ne!(
+                htc_conversion_set_option(
+                    conversion,
+                    c_str("mode").as_ptr(),
+                    c_str("stacked").as_ptr()
+                ),
+                0
+            );
+            assert_ne!(
+                htc_conversion_add_map_file(conversion, c_str("/nonexistent.map").as_ptr()),
+                0
+            );
+            let error = CStr::from_ptr(htc_conversion_get_error(conversion));
+            assert!(error.to_str().unwrap().contains("/nonexistent.map"));
+            htc_conversion_destroy(conversion);
+        }
+    }
+}
@@ -1,118 +1,749 @@
-use hawktracer_parser::{CoreEventKlassId, Event, Value};
-use std::io::BufRead;
+use hawktracer_parser::{CoreEventKlassId, Event, EventKlassRegistry, Value};
+use std::convert::TryFrom;
 
-// TODO: SUPPORT MAPPING EVENT
+mod demangler;
+pub use self::demangler::DemangleOptions;
+use self::demangler::Demangler;
 
-#[derive(Default)]
+mod label_template;
+use self::label_template::TemplateContext;
+pub use self::label_template::{LabelTemplate, TemplateError};
+
+mod rewrite_rules;
+pub use self::rewrite_rules::{RewriteRules, RewriteRulesError};
+
+mod symbolizer;
+pub use self::symbolizer::{SymbolFile, SymbolFileError, Symbolizer};
+
+mod mapping_file;
+pub use self::mapping_file::{MalformedEntry, MappingFileError, MappingFileFormat};
+
+/// Where a mapping has been defined.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MappingSource {
+    /// Mapping added through the API (e.g. `LabelMap::add_mapping`).
+    Api,
+    File {
+        path: String,
+        line: usize,
+    },
+    /// Mapping event received in the data stream; `event_id` is the event's `id` field.
+    Stream {
+        event_id: Option<u64>,
+    },
+}
+
+impl std::fmt::Display for MappingSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MappingSource::Api => write!(f, "API"),
+            MappingSource::File { path, line } => write!(f, "{}:{}", path, line),
+            MappingSource::Stream {
+                event_id: Some(event_id),
+            } => write!(f, "stream event #{}", event_id),
+            MappingSource::Stream { event_id: None } => write!(f, "stream"),
+        }
+    }
+}
+
+/// Decides which definition is kept if the same identifier is mapped more than once.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConflictPolicy {
+    FirstWins,
+    #[default]
+    LastWins,
+    /// Keeps the first definition, and reports the conflict as an error.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingDefinition {
+    pub label: String,
+    pub category: Option<String>,
+    pub source: MappingSource,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingConflict {
+    pub id: u64,
+    pub existing: MappingDefinition,
+    pub rejected: MappingDefinition,
+}
+
+impl std::fmt::Display for MappingConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ID {}: kept {:?} ({}), rejected {:?} ({})",
+            self.id,
+            self.existing.label,
+            self.existing.source,
+            self.rejected.label,
+            self.rejected.source
+        )
+    }
+}
+
+impl std::error::Error for MappingConflict {}
+
+/// Event field that referenced an identifier without a mapping.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LabelOrigin {
+    pub klass_id: u32,
+    pub field: String,
+}
+
+impl LabelOrigin {
+    pub fn describe(&self, reg: &EventKlassRegistry) -> String {
+        match reg.get_klass_by_id(self.klass_id) {
+            Some(klass) => format!("{}.{}", klass.get_name(), self.field),
+            None => format!("<klass {}>.{}", self.klass_id, self.field),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnresolvedLabel {
+    pub id: u64,
+    pub hits: u64,
+    pub origins: std::collections::HashMap<LabelOrigin, u64>,
+    fallback_label: String,
+}
+
+/// Cloning a map is cheap compared to loading it again; symbol files are shared by the clones.
+#[derive(Clone, Default)]
 pub struct LabelMap {
-    mapping: std::collections::HashMap<u64, String>,
+    mapping: std::collections::HashMap<u64, MappingDefinition>,
+    unresolved: std::collections::HashMap<u64, UnresolvedLabel>,
+    conflict_policy: ConflictPolicy,
+    conflicts: std::vec::Vec<MappingConflict>,
+    symbolizer: Option<std::rc::Rc<Symbolizer>>,
+    symbols: std::collections::HashMap<u64, Option<String>>,
 }
 
 impl LabelMap {
     pub fn new() -> LabelMap {
         LabelMap {
-            mapping: std::collections::HashMap::<u64, String>::new(),
+            mapping: std::collections::HashMap::<u64, MappingDefinition>::new(),
+            unresolved: std::collections::HashMap::<u64, UnresolvedLabel>::new(),
+            conflict_policy: ConflictPolicy::default(),
+            conflicts: vec![],
+            symbolizer: None,
+            symbols: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Identifiers without a mapping are resolved as code addresses
+    /// using the symbolizer, before they're considered unresolved.
+    pub fn set_symbolizer(&mut self, symbolizer: Symbolizer) {
+        self.symbolizer = Some(std::rc::Rc::new(symbolizer));
+        self.symbols.clear();
+    }
+
+    fn symbolize(&mut self, id: u64) -> bool {
+        let symbolizer = match &self.symbolizer {
+            Some(symbolizer) => symbolizer,
+            None => return false,
+        };
+        self.symbols
+            .entry(id)
+            .or_insert_with(|| symbolizer.resolve(id))
+            .is_some()
+    }
+
+    /// True if the identifier has a mapping or can be resolved by the symbolizer.
+    pub fn can_resolve(&self, id: u64) -> bool {
+        if self.mapping.contains_key(&id) {
+            return true;
+        }
+        match (self.symbols.get(&id), &self.symbolizer) {
+            (Some(symbol), _) => symbol.is_some(),
+            (None, Some(symbolizer)) => symbolizer.can_resolve(id),
+            (None, None) => false,
         }
     }
 
-    pub fn load_from_file(&mut self, path: &str) -> std::io::Result<()> {
-        let f = std::fs::File::open(path)?;
-        let file = std::io::BufReader::new(&f);
-        for (i, line) in file.lines().enumerate() {
-            let line = line?;
-            let data: Vec<&str> = line.split(' ').collect();
-            if data.len() != 3 {
+    pub fn set_conflict_policy(&mut self, policy: ConflictPolicy) {
+        self.conflict_policy = policy;
+    }
+
+    /// All conflicting definitions found so far, in order of appearance.
+    pub fn get_conflicts(&self) -> &[MappingConflict] {
+        &self.conflicts
+    }
+
+    fn load(&mut self, path: &str, strict: bool) -> Result<(), MappingFileError> {
+        let content = std::fs::read_to_string(path)?;
+        let format = MappingFileFormat::detect(path, &content);
+        let parsed = mapping_file::parse(&content, format);
+
+        if !parsed.malformed.is_empty() {
+            if strict || !format.is_line_based() {
+                return Err(MappingFileError::Malformed {
+                    file: path.to_owned(),
+                    entries: parsed.malformed,
+                });
+            }
+            for entry in &parsed.malformed {
                 eprintln!(
-                    "invalid mapping in line {}. Expected 3 arguments, was {}",
-                    i,
-                    data.len()
+                    "invalid mapping in {} line {}. {}",
+                    path, entry.line, entry.reason
                 );
-                continue;
             }
+        }
 
-            let id = match data[2].parse::<u64>() {
-                Err(e) => {
-                    eprintln!(
-                        "Can not parse identifier '{}' in line {}. Error message: {}",
-                        data[2], i, e
-                    );
-                    continue;
-                }
-                Ok(id) => id,
+        let mut conflicts = vec![];
+        for entry in parsed.entries {
+            let definition = MappingDefinition {
+                label: entry.label,
+                category: entry.category,
+                source: MappingSource::File {
+                    path: path.to_owned(),
+                    line: entry.line,
+                },
             };
+            if let Err(conflict) = self.add_definition(entry.id, definition) {
+                conflicts.push(*conflict);
+            }
+        }
 
-            let label = data[1].to_string();
-            self.mapping.insert(id, label); // TODO support data[0] (category)?
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(MappingFileError::Conflicts(conflicts))
         }
+    }
 
-        Ok(())
+    /// Loads mapping file, skipping (and reporting) malformed entries.
+    /// File format (text, JSON, CSV or TOML) is detected by extension or content.
+    pub fn load_from_file(&mut self, path: &str) -> Result<(), MappingFileError> {
+        self.load(path, false)
+    }
+
+    /// Same as `load_from_file`, but any malformed entry fails the whole file
+    /// and no mapping from that file is loaded.
+    pub fn load_from_file_strict(&mut self, path: &str) -> Result<(), MappingFileError> {
+        self.load(path, true)
     }
 
+    fn resolve_label(&mut self, id: u64, origin: Option<(u32, &str)>) -> &String {
+        if self.mapping.contains_key(&id) {
+            return &self.mapping[&id].label;
+        }
+        if self.symbolize(id) {
+            return self.symbols[&id].as_ref().unwrap();
+        }
+
+        let unresolved = self
+            .unresolved
+            .entry(id)
+            .or_insert_with(|| UnresolvedLabel {
+                id,
+                hits: 0,
+                origins: std::collections::HashMap::new(),
+                fallback_label: id.to_string(),
+            });
+        unresolved.hits += 1;
+        if let Some((klass_id, field)) = origin {
+            let origin = LabelOrigin {
+                klass_id,
+                field: field.to_owned(),
+            };
+            *unresolved.origins.entry(origin).or_insert(0) += 1;
+        }
+        &unresolved.fallback_label
+    }
+
+    /// Returns the label for the identifier, or the identifier itself if there's
+    /// no mapping for it (such identifiers are tracked, see `get_unresolved`).
     pub fn get_label(&mut self, id: u64) -> &String {
-        self.mapping.entry(id).or_insert_with(|| {
-            eprintln!("Label for ID {} does not exist in the mapping", id);
-            id.to_string()
-        })
+        self.resolve_label(id, None)
+    }
+
+    /// Same as `get_label`, but also records which event field referenced
+    /// an unresolved identifier.
+    pub fn get_label_for(&mut self, id: u64, klass_id: u32, field: &str) -> &String {
+        self.resolve_label(id, Some((klass_id, field)))
+    }
+
+    pub fn has_mapping(&self, id: u64) -> bool {
+        self.mapping.contains_key(&id)
+    }
+
+    pub fn get_category(&self, id: u64) -> Option<&String> {
+        self.mapping
+            .get(&id)
+            .and_then(|definition| definition.category.as_ref())
+    }
+
+    pub fn get_source(&self, id: u64) -> Option<&MappingSource> {
+        self.mapping.get(&id).map(|definition| &definition.source)
+    }
+
+    /// Identifiers looked up without a mapping, the most frequent first.
+    pub fn get_unresolved(&self) -> std::vec::Vec<&UnresolvedLabel> {
+        let mut unresolved: std::vec::Vec<&UnresolvedLabel> = self
+            .unresolved
+            .values()
+            .filter(|unresolved| !self.mapping.contains_key(&unresolved.id))
+            .collect();
+        unresolved.sort_by(|a, b| b.hits.cmp(&a.hits).then(a.id.cmp(&b.id)));
+        unresolved
+    }
+
+    /// Writes a text mapping file with a placeholder label for every unresolved identifier.
+    pub fn write_unresolved_skeleton(
+        &self,
+        writable: &mut dyn std::io::Write,
+        reg: &EventKlassRegistry,
+    ) -> std::io::Result<()> {
+        writable.write_all(
+            b"# Unresolved label identifiers. Replace placeholder labels and load the file with --map-files.\n",
+        )?;
+        for unresolved in self.get_unresolved() {
+            let mut origins: std::vec::Vec<String> = unresolved
+                .origins
+                .keys()
+                .map(|origin| origin.describe(reg))
+                .collect();
+            origins.sort();
+            writable.write_fmt(format_args!(
+                "# {} hit(s) in {}\nunknown TODO_{} {}\n",
+                unresolved.hits,
+                origins.join(", "),
+                unresolved.id,
+                unresolved.id
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Writes all the mappings (loaded from files, received in the stream, or added
+    /// through the API), ordered by identifier, in a format readable by `load_from_file`.
+    pub fn write_mapping(
+        &self,
+        writable: &mut dyn std::io::Write,
+        format: MappingFileFormat,
+    ) -> std::io::Result<()> {
+        let mut mappings: std::vec::Vec<(u64, &MappingDefinition)> = self
+            .mapping
+            .iter()
+            .map(|(id, definition)| (*id, definition))
+            .collect();
+        mappings.sort_by_key(|(id, _)| *id);
+        mapping_file::write(writable, &mappings, format)
+    }
+
+    /// Same as `write_mapping`; the format is chosen by the file extension (text by default).
+    pub fn save_to_file(&self, path: &str) -> std::io::Result<()> {
+        let format = MappingFileFormat::from_extension(path).unwrap_or(MappingFileFormat::Text);
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_mapping(&mut file, format)?;
+        std::io::Write::flush(&mut file)
+    }
+
+    /// Adds a mapping, resolving a clash with an existing definition according to
+    /// the conflict policy. Every conflict is recorded (see `get_conflicts`), but an
+    /// error is only returned with `ConflictPolicy::Error`.
+    pub fn add_definition(
+        &mut self,
+        id: u64,
+        definition: MappingDefinition,
+    ) -> Result<(), Box<MappingConflict>> {
+        let existing = match self.mapping.get_mut(&id) {
+            Some(existing) => existing,
+            None => {
+                self.mapping.insert(id, definition);
+                return Ok(());
+            }
+        };
+
+        if existing.label == definition.label && existing.category == definition.category {
+            return Ok(());
+        }
+
+        let conflict = if self.conflict_policy == ConflictPolicy::LastWins {
+            MappingConflict {
+                id,
+                existing: definition.clone(),
+                rejected: std::mem::replace(existing, definition),
+            }
+        } else {
+            MappingConflict {
+                id,
+                existing: existing.clone(),
+                rejected: definition,
+            }
+        };
+        self.conflicts.push(conflict.clone());
+
+        if self.conflict_policy == ConflictPolicy::Error {
+            Err(Box::new(conflict))
+        } else {
+            Ok(())
+        }
     }
 
     pub fn add_mapping(&mut self, id: u64, label: &str) {
-        self.mapping.insert(id, label.to_owned());
+        let _ = self.add_definition(
+            id,
+            MappingDefinition {
+                label: label.to_owned(),
+                category: None,
+                source: MappingSource::Api,
+            },
+        );
     }
+
+    pub fn add_mapping_with_category(&mut self, id: u64, label: &str, category: &str) {
+        let _ = self.add_definition(
+            id,
+            MappingDefinition {
+                label: label.to_owned(),
+                category: Some(category.to_owned()),
+                source: MappingSource::Api,
+            },
+        );
+    }
+}
+
+/// Event klass carrying label mappings; `HT_StringMappingEvent` by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingKlass {
+    pub klass_name: String,
+    /// Integer field (of any width) with the identifier.
+    pub identifier_field: String,
+    pub label_field: String,
+    pub category_field: Option<String>,
+}
+
+impl MappingKlass {
+    pub fn new(klass_name: &str, identifier_field: &str, label_field: &str) -> MappingKlass {
+        MappingKlass {
+            klass_name: klass_name.to_owned(),
+            identifier_field: identifier_field.to_owned(),
+            label_field: label_field.to_owned(),
+            category_field: None,
+        }
+    }
+}
+
+impl Default for MappingKlass {
+    fn default() -> MappingKlass {
+        MappingKlass::new("HT_StringMappingEvent", "identifier", "label")
+    }
+}
+
+/// Converts integer value of any width to a label identifier; negative values are not identifiers.
+fn label_id(value: &Value) -> Option<u64> {
+    match value {
+        Value::U8(v) => Some(u64::from(*v)),
+        Value::U16(v) => Some(u64::from(*v)),
+        Value::U32(v) => Some(u64::from(*v)),
+        Value::U64(v) => Some(*v),
+        Value::I8(v) => u64::try_from(*v).ok(),
+        Value::I16(v) => u64::try_from(*v).ok(),
+        Value::I32(v) => u64::try_from(*v).ok(),
+        Value::I64(v) => u64::try_from(*v).ok(),
+        Value::Str(_) | Value::Struct(_) => None,
+    }
+}
+
+enum LabelValue<'a> {
+    Id(u64),
+    Text(&'a String),
 }
 
+#[derive(Clone)]
+struct LabelRules {
+    label_fields: std::vec::Vec<String>,
+    klass_label_fields: std::collections::HashMap<String, std::vec::Vec<String>>,
+    template: Option<LabelTemplate>,
+    klass_templates: std::collections::HashMap<String, LabelTemplate>,
+}
+
+impl LabelRules {
+    fn find_klass_template(
+        &self,
+        event: &Event,
+        reg: &EventKlassRegistry,
+    ) -> Option<&LabelTemplate> {
+        reg.get_klass_by_id(event.get_klass_id())
+            .and_then(|klass| self.klass_templates.get(klass.get_name()))
+    }
+
+    fn find_label_value<'a>(
+        &'a self,
+        event: &'a Event,
+        reg: &EventKlassRegistry,
+    ) -> Option<(&'a String, LabelValue<'a>)> {
+        let klass_fields = reg
+            .get_klass_by_id(event.get_klass_id())
+            .and_then(|klass| self.klass_label_fields.get(klass.get_name()));
+
+        for label_field in klass_fields.into_iter().flatten().chain(&self.label_fields) {
+            match event.get_raw_value(label_field) {
+                Some(Value::Str(value)) => return Some((label_field, LabelValue::Text(value))),
+                Some(value) => {
+                    if let Some(id) = label_id(value) {
+                        return Some((label_field, LabelValue::Id(id)));
+                    }
+                }
+                None => (),
+            }
+        }
+        None
+    }
+}
+
+/// Label field reported for labels rendered from a klass template only.
+static NO_LABEL_FIELD: String = String::new();
+
+/// Every converter (and the filter, and the time transform) needs its own getter;
+/// they're cloned from one configured getter, so files are loaded only once.
+#[derive(Clone)]
 pub struct LabelGetter {
     label_map: LabelMap,
-    label_fields: std::vec::Vec<String>,
-    mapping_event_id: Option<u32>,
+    rules: LabelRules,
+    mapping_klasses: std::vec::Vec<MappingKlass>,
+    /// Identifiers of mapping event klasses seen in the stream, with the index in `mapping_klasses`.
+    mapping_klass_ids: std::collections::HashMap<u32, usize>,
+    klass_category_fallback: bool,
+    demangler: Option<Demangler>,
+    rewrite_rules: Option<RewriteRules>,
+    label_buffer: String,
 }
 
 impl LabelGetter {
     pub fn new(label_map: LabelMap, label_fields: std::vec::Vec<String>) -> LabelGetter {
         LabelGetter {
             label_map,
-            label_fields,
-            mapping_event_id: None,
+            rules: LabelRules {
+                label_fields,
+                klass_label_fields: std::collections::HashMap::new(),
+                template: None,
+                klass_templates: std::collections::HashMap::new(),
+            },
+            mapping_klasses: vec![MappingKlass::default()],
+            mapping_klass_ids: std::collections::HashMap::new(),
+            klass_category_fallback: false,
+            demangler: None,
+            rewrite_rules: None,
+            label_buffer: String::new(),
         }
     }
 
-    // TODO TEST ME PLEASE!
-    fn update_mapping_event_info(&mut self, event: &Event) -> bool {
-        if self.mapping_event_id.is_none()
-            && event.get_klass_id() == CoreEventKlassId::KlassInfo as u32
-        {
-            let klass_name = event.get_value_string("event_klass_name");
-            if klass_name.is_ok() && klass_name.unwrap() == "HT_StringMappingEvent" {
-                // TODO We should have KlassInfo event wrapper with get_klass_name method in parser
-                self.mapping_event_id = event.get_value_u32("info_klass_id").ok();
-            }
-        } else if self.mapping_event_id.is_some()
-            && event.get_klass_id() == self.mapping_event_id.unwrap()
+    /// Enables demangling of C++ and Rust symbols used as labels (after mapping lookup).
+    pub fn set_demangle_options(&mut self, options: DemangleOptions) {
+        self.demangler = Some(Demangler::new(options));
+    }
+
+    /// Rules applied to every label, after demangling and template rendering.
+    pub fn set_rewrite_rules(&mut self, rules: RewriteRules) {
+        self.rewrite_rules = Some(rules).filter(|rules| !rules.is_empty());
+    }
+
+    /// Template used to compose the label of every event (unless there's a klass-specific one).
+    pub fn set_label_template(&mut self, template: LabelTemplate) {
+        self.rules.template = Some(template);
+    }
+
+    pub fn set_klass_label_template(&mut self, klass_name: &str, template: LabelTemplate) {
+        self.rules
+            .klass_templates
+            .insert(klass_name.to_owned(), template);
+    }
+
+    /// Label fields checked for events of the given klass, before the default label fields.
+    pub fn set_klass_label_fields(
+        &mut self,
+        klass_name: &str,
+        label_fields: std::vec::Vec<String>,
+    ) {
+        self.rules
+            .klass_label_fields
+            .insert(klass_name.to_owned(), label_fields);
+    }
+
+    pub fn get_label_map(&self) -> &LabelMap {
+        &self.label_map
+    }
+
+    /// When enabled, events without a mapped category fall back to their klass name.
+    pub fn set_klass_category_fallback(&mut self, enabled: bool) {
+        self.klass_category_fallback = enabled;
+    }
+
+    /// Declares an event klass carrying mappings. A klass with the same name
+    /// (including the default `HT_StringMappingEvent`) is replaced.
+    pub fn add_mapping_klass(&mut self, mapping_klass: MappingKlass) {
+        match self
+            .mapping_klasses
+            .iter_mut()
+            .find(|klass| klass.klass_name == mapping_klass.klass_name)
         {
-            if let Ok(label) = event.get_value_string("label") {
-                if let Ok(id) = event.get_value_u64("identifier") {
-                    self.label_map.add_mapping(id, label);
-                    return true;
+            Some(klass) => *klass = mapping_klass,
+            None => self.mapping_klasses.push(mapping_klass),
+        }
+    }
+
+    pub fn get_mapping_klasses(&self) -> &[MappingKlass] {
+        &self.mapping_klasses
+    }
+
+    fn update_mapping_event_info(&mut self, event: &Event) -> bool {
+        if event.get_klass_id() == CoreEventKlassId::KlassInfo as u32 {
+            // TODO We should have KlassInfo event wrapper with get_klass_name method in parser
+            if let (Ok(klass_name), Ok(klass_id)) = (
+                event.get_value_string("event_klass_name"),
+                event.get_value_u32("info_klass_id"),
+            ) {
+                if let Some(index) = self
+                    .mapping_klasses
+                    .iter()
+                    .position(|klass| &klass.klass_name == klass_name)
+                {
+                    self.mapping_klass_ids.insert(klass_id, index);
                 }
             }
+            return false;
+        }
+
+        let mapping_klass = match self.mapping_klass_ids.get(&event.get_klass_id()) {
+            Some(index) => &self.mapping_klasses[*index],
+            None => return false,
+        };
+        let id = event
+            .get_raw_value(&mapping_klass.identifier_field)
+            .and_then(label_id);
+        let label = event.get_value_string(&mapping_klass.label_field);
+        if let (Some(id), Ok(label)) = (id, label) {
+            let category = mapping_klass
+                .category_field
+                .as_ref()
+                .and_then(|field| event.get_value_string(field).ok());
+            let definition = MappingDefinition {
+                label: label.clone(),
+                category: category.cloned(),
+                source: MappingSource::Stream {
+                    event_id: event.get_value_u64("id").ok(),
+                },
+            };
+            // Conflicts are recorded by the map, and reported at the end of conversion.
+            let _ = self.label_map.add_definition(id, definition);
+            return true;
         }
         false
     }
 
-    pub fn get_label<'a>(&'a mut self, event: &'a Event) -> Option<(&'a String, &'a String)> {
+    /// Learns mapping from the event, if it's a mapping event (or defines the
+    /// mapping event klass). Used for collecting mappings ahead of conversion.
+    pub fn learn_mapping(&mut self, event: &Event) -> bool {
+        self.update_mapping_event_info(event)
+    }
+
+    /// True for events that define mappings (or the mapping event klass).
+    pub fn is_mapping_event(&self, event: &Event) -> bool {
+        event.get_klass_id() == CoreEventKlassId::KlassInfo as u32
+            || self.mapping_klass_ids.contains_key(&event.get_klass_id())
+    }
+
+    /// True if the label of the event is an identifier without a mapping (yet),
+    /// that can't be resolved by the symbolizer either.
+    pub fn has_unresolved_label(&self, event: &Event, reg: &EventKlassRegistry) -> bool {
+        match self.rules.find_label_value(event, reg) {
+            Some((_, LabelValue::Id(id))) => !self.label_map.can_resolve(id),
+            _ => false,
+        }
+    }
+
+    pub fn get_label<'a>(
+        &'a mut self,
+        event: &'a Event,
+        reg: &EventKlassRegistry,
+    ) -> Option<(&'a String, &'a String)> {
         if self.update_mapping_event_info(event) {
-            return None
+            return None;
         }
 
-        for label_field in &self.label_fields {
-            if let Some(value) = event.get_raw_value(label_field) {
-                match value {
-                    Value::U64(value) => {
-                        return Some((label_field, self.label_map.get_label(*value)));
-                    }
-                    Value::Str(value) => return Some((label_field, value)),
-                    _ => (),
-                }
+        let klass_template = self.rules.find_klass_template(event, reg);
+        let template = klass_template.or(self.rules.template.as_ref());
+        if template.is_none() && self.demangler.is_none() && self.rewrite_rules.is_none() {
+            return match self.rules.find_label_value(event, reg) {
+                Some((label_field, LabelValue::Id(id))) => Some((
+                    label_field,
+                    self.label_map
+                        .get_label_for(id, event.get_klass_id(), label_field),
+                )),
+                Some((label_field, LabelValue::Text(value))) => Some((label_field, value)),
+                None => None,
+            };
+        }
+
+        let category = self.get_category(event, reg).cloned();
+        let (label_field, mut label) = match self.rules.find_label_value(event, reg) {
+            Some((label_field, LabelValue::Id(id))) => (
+                label_field,
+                self.label_map
+                    .get_label_for(id, event.get_klass_id(), label_field)
+                    .clone(),
+            ),
+            Some((label_field, LabelValue::Text(value))) => (label_field, value.clone()),
+            // Klass templates can compose labels of events without any label field;
+            // the default template would label every event (e.g. HT_SystemInfoEvent).
+            None if klass_template.is_some() => (&NO_LABEL_FIELD, String::new()),
+            None => return None,
+        };
+
+        if let Some(demangled) = self
+            .demangler
+            .as_ref()
+            .and_then(|demangler| demangler.demangle(&label))
+        {
+            label = demangled;
+        }
+
+        match template {
+            Some(template) => {
+                let context = TemplateContext {
+                    event,
+                    label: &label,
+                    category: category.as_deref(),
+                    klass_name: reg
+                        .get_klass_by_id(event.get_klass_id())
+                        .map(|klass| &klass.get_name()[..]),
+                };
+                self.label_buffer.clear();
+                template.render(&mut self.label_buffer, &context, &mut self.label_map);
             }
+            None => self.label_buffer = label,
+        }
+
+        if let Some(rewritten) = self
+            .rewrite_rules
+            .as_ref()
+            .and_then(|rules| rules.apply(&self.label_buffer))
+        {
+            self.label_buffer = rewritten;
+        }
+        Some((label_field, &self.label_buffer))
+    }
+
+    pub fn get_category<'a>(
+        &'a self,
+        event: &'a Event,
+        reg: &'a EventKlassRegistry,
+    ) -> Option<&'a String> {
+        if let Some((_, LabelValue::Id(id))) = self.rules.find_label_value(event, reg) {
+            if let Some(category) = self.label_map.get_category(id) {
+                return Some(category);
+            }
+        }
+
+        if self.klass_category_fallback {
+            return reg
+                .get_klass_by_id(event.get_klass_id())
+                .map(|klass| klass.get_name());
         }
         None
     }
@@ -128,11 +759,12 @@ mod tests {
         path.push("resources/test/label_map_valid.txt");
         let mut map = LabelMap::new();
 
-        map.load_from_file(&path.to_str().unwrap()).unwrap();
+        map.load_from_file(path.to_str().unwrap()).unwrap();
 
         assert_eq!(map.get_label(1), "label1");
         assert_eq!(map.get_label(2), "label2");
         assert_eq!(map.get_label(3), "label3");
+        assert_eq!(map.get_category(2).unwrap(), "cpp");
     }
 
     #[test]
@@ -141,11 +773,47 @@ mod tests {
         path.push("resources/test/label_map_invalid.txt");
         let mut map = LabelMap::new();
 
-        map.load_from_file(&path.to_str().unwrap()).unwrap();
+        map.load_from_file(path.to_str().unwrap()).unwrap();
 
         assert_eq!(map.get_label(8), "valid_label");
     }
 
+    #[test]
+    fn load_from_file_should_load_structured_formats() {
+        for file_name in &[
+            "label_map_valid.json",
+            "label_map_valid.csv",
+            "label_map_valid.toml",
+        ] {
+            let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            path.push("resources/test");
+            path.push(file_name);
+            let mut map = LabelMap::new();
+
+            map.load_from_file_strict(path.to_str().unwrap()).unwrap();
+
+            assert_eq!(map.get_label(1), "Foo<int, bar>::run");
+            assert_eq!(map.get_category(1).unwrap(), "cpp");
+            assert_eq!(map.get_label(2), "label2");
+        }
+    }
+
+    #[test]
+    fn load_from_file_strict_should_fail_on_malformed_lines() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/label_map_invalid.txt");
+        let mut map = LabelMap::new();
+
+        match map.load_from_file_strict(path.to_str().unwrap()) {
+            Err(MappingFileError::Malformed { entries, .. }) => {
+                let lines: Vec<usize> = entries.iter().map(|entry| entry.line).collect();
+                assert_eq!(lines, vec![1, 2, 3]);
+            }
+            _ => panic!("expected malformed mapping file error"),
+        }
+        assert_eq!(map.get_label(8), "8");
+    }
+
     #[test]
     fn load_from_file_should_fail_if_file_does_not_exist() {
         let mut map = LabelMap::new();
@@ -160,15 +828,171 @@ mod tests {
         assert_eq!(map.get_label(4), "4");
     }
 
+    #[test]
+    fn map_should_track_unresolved_identifiers() {
+        let mut map = LabelMap::new();
+        map.add_mapping(1, "label");
+
+        map.get_label_for(5, 20, "name");
+        map.get_label_for(5, 20, "name");
+        map.get_label_for(5, 21, "label");
+        map.get_label_for(7, 20, "name");
+        map.get_label(1);
+
+        let unresolved = map.get_unresolved();
+        assert_eq!(unresolved.len(), 2);
+        assert_eq!(unresolved[0].id, 5);
+        assert_eq!(unresolved[0].hits, 3);
+        let origin = LabelOrigin {
+            klass_id: 20,
+            field: "name".to_owned(),
+        };
+        assert_eq!(unresolved[0].origins[&origin], 2);
+        assert!(map.get_source(5).is_none());
+    }
+
+    #[test]
+    fn map_should_not_report_identifiers_mapped_later() {
+        let mut map = LabelMap::new();
+
+        map.get_label(5);
+        map.add_mapping(5, "label");
+
+        assert!(map.get_unresolved().is_empty());
+        assert_eq!(map.get_label(5), "label");
+    }
+
+    #[test]
+    fn unresolved_skeleton_should_be_loadable_mapping_file() {
+        let mut map = LabelMap::new();
+        map.get_label_for(5, 1, "name");
+        let mut skeleton = vec![];
+
+        map.write_unresolved_skeleton(&mut skeleton, &EventKlassRegistry::new())
+            .unwrap();
+
+        let parsed = mapping_file::parse(
+            std::str::from_utf8(&skeleton).unwrap(),
+            MappingFileFormat::Text,
+        );
+        assert!(parsed.malformed.is_empty());
+        assert_eq!(parsed.entries[0].id, 5);
+        assert_eq!(parsed.entries[0].label, "TODO_5");
+    }
+
+    #[test]
+    fn saved_mapping_should_be_loadable() {
+        let mut map = LabelMap::new();
+        map.load_from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/resources/test/label_map_valid.json"
+        ))
+        .unwrap();
+        map.add_mapping(10, "stream label");
+        let path = std::env::temp_dir().join(format!(
+            "hawktracer-converter-saved-mapping-{}.csv",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+
+        map.save_to_file(path).unwrap();
+        let mut loaded = LabelMap::new();
+        loaded.load_from_file_strict(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.get_label(1), "Foo<int, bar>::run");
+        assert_eq!(loaded.get_category(1).unwrap(), "cpp");
+        assert_eq!(loaded.get_label(10), "stream label");
+        assert!(loaded.get_category(10).is_none());
+    }
+
     #[test]
     fn map_should_return_label_if_mapping_exist() {
         let mut map = LabelMap::new();
         let label = "test";
-        map.mapping.insert(4, label.to_owned());
+        map.add_mapping(4, label);
 
         assert_eq!(map.get_label(4), label);
     }
 
+    #[test]
+    fn map_should_not_return_category_if_mapping_has_no_category() {
+        let mut map = LabelMap::new();
+        map.add_mapping(4, "test");
+
+        assert!(map.get_category(4).is_none());
+    }
+
+    fn make_definition(label: &str, line: usize) -> MappingDefinition {
+        MappingDefinition {
+            label: label.to_owned(),
+            category: None,
+            source: MappingSource::File {
+                path: "map.txt".to_owned(),
+                line,
+            },
+        }
+    }
+
+    #[test]
+    fn load_from_file_should_record_file_and_line() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/label_map_valid.txt");
+        let mut map = LabelMap::new();
+
+        map.load_from_file(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(
+            map.get_source(2).unwrap(),
+            &MappingSource::File {
+                path: path.to_str().unwrap().to_owned(),
+                line: 2
+            }
+        );
+    }
+
+    #[test]
+    fn add_definition_should_apply_conflict_policy() {
+        for (policy, expected_label) in &[
+            (ConflictPolicy::FirstWins, "first"),
+            (ConflictPolicy::LastWins, "second"),
+            (ConflictPolicy::Error, "first"),
+        ] {
+            let mut map = LabelMap::new();
+            map.set_conflict_policy(*policy);
+
+            assert!(map.add_definition(1, make_definition("first", 1)).is_ok());
+            let result = map.add_definition(1, make_definition("second", 2));
+
+            assert_eq!(result.is_err(), *policy == ConflictPolicy::Error);
+            assert_eq!(map.get_label(1), expected_label);
+            assert_eq!(map.get_conflicts().len(), 1);
+            assert_eq!(map.get_conflicts()[0].existing.label, *expected_label);
+        }
+    }
+
+    #[test]
+    fn add_definition_should_ignore_identical_definitions() {
+        let mut map = LabelMap::new();
+
+        map.add_definition(1, make_definition("label", 1)).unwrap();
+        map.add_definition(1, make_definition("label", 2)).unwrap();
+
+        assert!(map.get_conflicts().is_empty());
+    }
+
+    #[test]
+    fn add_definition_should_resolve_previously_unresolved_label_without_conflict() {
+        let mut map = LabelMap::new();
+        map.set_conflict_policy(ConflictPolicy::Error);
+
+        assert_eq!(map.get_label(1), "1");
+        map.add_definition(1, make_definition("label", 1)).unwrap();
+
+        assert_eq!(map.get_label(1), "label");
+        assert!(map.get_conflicts().is_empty());
+    }
+
     fn make_event(field_name: &str, value: Value) -> Event {
         let mut values = std::collections::HashMap::<String, Value>::new();
         values.insert(field_name.to_owned(), value);
@@ -180,7 +1004,9 @@ mod tests {
         let mut getter = LabelGetter::new(LabelMap::new(), vec!["name".to_owned()]);
         let event = make_event("name", Value::Str("test1".to_owned()));
 
-        let (field, value) = getter.get_label(&event).unwrap();
+        let (field, value) = getter
+            .get_label(&event, &EventKlassRegistry::new())
+            .unwrap();
 
         assert_eq!(field, "name");
         assert_eq!(value, "test1");
@@ -191,7 +1017,7 @@ mod tests {
         let mut getter = LabelGetter::new(LabelMap::new(), vec!["unknown".to_owned()]);
         let event = make_event("name", Value::Str("test1".to_owned()));
 
-        let mapping = getter.get_label(&event);
+        let mapping = getter.get_label(&event, &EventKlassRegistry::new());
 
         assert!(mapping.is_none());
     }
@@ -207,8 +1033,230 @@ mod tests {
             )),
         );
 
-        let mapping = getter.get_label(&event);
+        let mapping = getter.get_label(&event, &EventKlassRegistry::new());
 
         assert!(mapping.is_none());
     }
+
+    #[test]
+    fn getter_should_accept_identifiers_of_any_integer_width() {
+        let mut map = LabelMap::new();
+        map.add_mapping(3, "label3");
+        let mut getter = LabelGetter::new(map, vec!["name".to_owned()]);
+        let reg = EventKlassRegistry::new();
+
+        for value in [Value::U8(3), Value::U16(3), Value::U32(3), Value::I64(3)] {
+            let event = make_event("name", value);
+            assert_eq!(getter.get_label(&event, &reg).unwrap().1, "label3");
+        }
+        let event = make_event("name", Value::I32(-3));
+        assert!(getter.get_label(&event, &reg).is_none());
+    }
+
+    #[test]
+    fn getter_should_prefer_klass_specific_label_fields() {
+        let mut getter = LabelGetter::new(LabelMap::new(), vec!["name".to_owned()]);
+        getter.set_klass_label_fields("MyIoEvent", vec!["path".to_owned()]);
+        let mut reg = EventKlassRegistry::new();
+        reg.add_klass(hawktracer_parser::event_klass::EventKlass::new(
+            100,
+            "MyIoEvent".to_owned(),
+        ));
+        let mut values = std::collections::HashMap::<String, Value>::new();
+        values.insert("name".to_owned(), Value::Str("io".to_owned()));
+        values.insert("path".to_owned(), Value::Str("/tmp/file".to_owned()));
+        let io_event = Event::new(100, values);
+        let other_event = make_event("name", Value::Str("other".to_owned()));
+
+        assert_eq!(
+            getter.get_label(&io_event, &reg).unwrap(),
+            (&"path".to_owned(), &"/tmp/file".to_owned())
+        );
+        assert_eq!(getter.get_label(&other_event, &reg).unwrap().1, "other");
+    }
+
+    #[test]
+    fn getter_should_fall_back_to_default_label_fields() {
+        let mut getter = LabelGetter::new(LabelMap::new(), vec!["name".to_owned()]);
+        getter.set_klass_label_fields("MyIoEvent", vec!["path".to_owned()]);
+        let mut reg = EventKlassRegistry::new();
+        reg.add_klass(hawktracer_parser::event_klass::EventKlass::new(
+            100,
+            "MyIoEvent".to_owned(),
+        ));
+        let mut values = std::collections::HashMap::<String, Value>::new();
+        values.insert("name".to_owned(), Value::Str("io".to_owned()));
+        let event = Event::new(100, values);
+
+        assert_eq!(getter.get_label(&event, &reg).unwrap().1, "io");
+    }
+
+    #[test]
+    fn getter_should_compose_label_from_template() {
+        let mut map = LabelMap::new();
+        map.add_mapping_with_category(3, "load", "io");
+        let mut getter = LabelGetter::new(map, vec!["name".to_owned()]);
+        getter.set_label_template(LabelTemplate::parse("{label}(id={id}) [{category}]").unwrap());
+        let mut values = std::collections::HashMap::<String, Value>::new();
+        values.insert("name".to_owned(), Value::U64(3));
+        values.insert("id".to_owned(), Value::U32(42));
+        let event = Event::new(1, values);
+
+        let (field, label) = getter
+            .get_label(&event, &EventKlassRegistry::new())
+            .unwrap();
+
+        assert_eq!(field, "name");
+        assert_eq!(label, "load(id=42) [io]");
+    }
+
+    #[test]
+    fn getter_should_render_template_for_klass_without_label_field() {
+        let mut getter = LabelGetter::new(LabelMap::new(), vec!["label".to_owned()]);
+        getter.set_klass_label_template(
+            "MyIoEvent",
+            LabelTemplate::parse("{klass}: {path}").unwrap(),
+        );
+        let mut reg = EventKlassRegistry::new();
+        reg.add_klass(hawktracer_parser::event_klass::EventKlass::new(
+            100,
+            "MyIoEvent".to_owned(),
+        ));
+        let mut values = std::collections::HashMap::<String, Value>::new();
+        values.insert("path".to_owned(), Value::Str("/tmp/file".to_owned()));
+        let event = Event::new(100, values);
+
+        let (field, label) = getter.get_label(&event, &reg).unwrap();
+
+        assert_eq!(field, "");
+        assert_eq!(label, "MyIoEvent: /tmp/file");
+        assert!(getter
+            .get_label(&make_event("other", Value::U32(1)), &reg)
+            .is_none());
+    }
+
+    #[test]
+    fn getter_should_demangle_mapped_label() {
+        let mut map = LabelMap::new();
+        map.add_mapping(3, "_ZN2ns3FooIiE3runEv");
+        let mut getter = LabelGetter::new(map, vec!["name".to_owned()]);
+        getter.set_demangle_options(DemangleOptions::default());
+        let event = make_event("name", Value::U64(3));
+
+        let (_, label) = getter
+            .get_label(&event, &EventKlassRegistry::new())
+            .unwrap();
+
+        assert_eq!(label, "ns::Foo<int>::run()");
+    }
+
+    #[test]
+    fn getter_should_rewrite_labels() {
+        let mut label_map = LabelMap::new();
+        label_map.add_mapping(3, "frame_42");
+        let mut label_getter = LabelGetter::new(label_map, vec!["label".to_owned()]);
+        let mut rules = RewriteRules::new();
+        rules.add_rule("_[0-9]+$", "_N").unwrap();
+        label_getter.set_rewrite_rules(rules);
+        let reg = EventKlassRegistry::new();
+
+        let event = make_event("label", Value::U64(3));
+        assert_eq!(label_getter.get_label(&event, &reg).unwrap().1, "frame_N");
+        let event = make_event("label", Value::Str("tile_7".to_owned()));
+        assert_eq!(label_getter.get_label(&event, &reg).unwrap().1, "tile_N");
+    }
+
+    fn make_klass_info(klass_name: &str, klass_id: u32) -> Event {
+        let mut values = std::collections::HashMap::new();
+        values.insert(
+            "event_klass_name".to_owned(),
+            Value::Str(klass_name.to_owned()),
+        );
+        values.insert("info_klass_id".to_owned(), Value::U32(klass_id));
+        Event::new(CoreEventKlassId::KlassInfo as u32, values)
+    }
+
+    fn make_mapping_events() -> (Event, Event) {
+        let klass_info = make_klass_info("HT_StringMappingEvent", 100);
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("identifier".to_owned(), Value::U64(3));
+        values.insert("label".to_owned(), Value::Str("late".to_owned()));
+        (klass_info, Event::new(100, values))
+    }
+
+    #[test]
+    fn getter_should_detect_unresolved_labels_until_mapping_arrives() {
+        let reg = EventKlassRegistry::new();
+        let (klass_info, mapping_event) = make_mapping_events();
+        let mut getter = LabelGetter::new(LabelMap::new(), vec!["name".to_owned()]);
+        let event = make_event("name", Value::U64(3));
+
+        assert!(getter.has_unresolved_label(&event, &reg));
+        assert!(getter.is_mapping_event(&klass_info));
+        assert!(!getter.is_mapping_event(&mapping_event));
+
+        getter.learn_mapping(&klass_info);
+        assert!(getter.is_mapping_event(&mapping_event));
+        assert!(getter.learn_mapping(&mapping_event));
+
+        assert!(!getter.has_unresolved_label(&event, &reg));
+        assert_eq!(getter.get_label(&event, &reg).unwrap().1, "late");
+    }
+
+    #[test]
+    fn getter_should_learn_mappings_from_custom_mapping_klasses() {
+        let mut getter = LabelGetter::new(LabelMap::new(), vec!["name".to_owned()]);
+        let mut mapping_klass = MappingKlass::new("MyMappingEvent", "key", "text");
+        mapping_klass.category_field = Some("group".to_owned());
+        getter.add_mapping_klass(mapping_klass);
+        getter.learn_mapping(&make_klass_info("HT_StringMappingEvent", 100));
+        getter.learn_mapping(&make_klass_info("MyMappingEvent", 101));
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("key".to_owned(), Value::U32(7));
+        values.insert("text".to_owned(), Value::Str("custom".to_owned()));
+        values.insert("group".to_owned(), Value::Str("io".to_owned()));
+        assert!(getter.learn_mapping(&Event::new(101, values)));
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("identifier".to_owned(), Value::U64(8));
+        values.insert("label".to_owned(), Value::Str("default".to_owned()));
+        assert!(getter.learn_mapping(&Event::new(100, values)));
+
+        let label_map = getter.get_label_map();
+        assert_eq!(label_map.mapping[&7].label, "custom");
+        assert_eq!(label_map.get_category(7).unwrap(), "io");
+        assert_eq!(label_map.mapping[&8].label, "default");
+    }
+
+    #[test]
+    fn getter_should_return_mapped_category() {
+        let mut map = LabelMap::new();
+        map.add_mapping_with_category(3, "test", "io");
+        let getter = LabelGetter::new(map, vec!["name".to_owned()]);
+        let event = make_event("name", Value::U64(3));
+
+        let reg = EventKlassRegistry::new();
+
+        let category = getter.get_category(&event, &reg);
+
+        assert_eq!(category.unwrap(), "io");
+    }
+
+    #[test]
+    fn getter_should_fall_back_to_klass_name_if_enabled() {
+        let mut getter = LabelGetter::new(LabelMap::new(), vec!["name".to_owned()]);
+        let event = make_event("name", Value::U64(3));
+        let reg = EventKlassRegistry::new();
+
+        assert!(getter.get_category(&event, &reg).is_none());
+
+        getter.set_klass_category_fallback(true);
+
+        assert_eq!(
+            getter.get_category(&event, &reg).unwrap(),
+            reg.get_klass_by_id(1).unwrap().get_name()
+        );
+    }
 }
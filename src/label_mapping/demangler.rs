@@ -0,0 +1,213 @@
+/// Simplifications applied to demangled names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DemangleOptions {
+    /// Removes template (generic) arguments, e.g. `Foo<int>::run` becomes `Foo::run`.
+    pub drop_template_args: bool,
+    /// Removes the argument list of C++ functions.
+    pub drop_arguments: bool,
+    /// Keeps only the last N segments of the path, e.g. `ns::Foo::run` becomes `Foo::run` for 2.
+    pub keep_segments: Option<usize>,
+}
+
+/// Demangles C++ (Itanium ABI) and Rust (legacy and v0) symbols.
+#[derive(Clone)]
+pub struct Demangler {
+    options: DemangleOptions,
+}
+
+fn is_rust_legacy_symbol(symbol: &str) -> bool {
+    // Legacy Rust symbols are Itanium-like, but end with a 16-digit hash segment.
+    // Checked on bytes, as labels may contain multibyte characters.
+    let bytes = symbol.as_bytes();
+    bytes.starts_with(b"_ZN")
+        && bytes.ends_with(b"E")
+        && bytes.len() > 20
+        && bytes[bytes.len() - 2].is_ascii_hexdigit()
+        && bytes[bytes.len() - 20..].starts_with(b"17h")
+}
+
+// Returns true if '<' or '>' at `pos` is a part of an operator name (e.g. `operator<<`).
+fn is_operator_char(name: &str, pos: usize) -> bool {
+    let prefix = name[..pos].trim_end_matches(['<', '>', '-']);
+    prefix.ends_with("operator")
+}
+
+fn strip_template_args(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut depth = 0usize;
+
+    for (pos, c) in name.char_indices() {
+        match c {
+            '<' if !is_operator_char(name, pos) => depth += 1,
+            '>' if depth > 0 && !is_operator_char(name, pos) => depth -= 1,
+            c if depth == 0 => result.push(c),
+            _ => (),
+        }
+    }
+
+    result.trim_start_matches("::").to_owned()
+}
+
+fn keep_last_segments(name: &str, count: usize) -> String {
+    let mut separators = vec![];
+    let mut depth = 0usize;
+    let bytes = name.as_bytes();
+
+    for (pos, c) in name.char_indices() {
+        match c {
+            '<' | '(' | '[' if !is_operator_char(name, pos) => depth += 1,
+            '>' | ')' | ']' if depth > 0 && !is_operator_char(name, pos) => depth -= 1,
+            ':' if depth == 0 && pos > 0 && bytes[pos - 1] == b':' => separators.push(pos + 1),
+            _ => (),
+        }
+    }
+
+    if count == 0 || separators.len() < count {
+        name.to_owned()
+    } else {
+        name[separators[separators.len() - count]..].to_owned()
+    }
+}
+
+impl Demangler {
+    pub fn new(options: DemangleOptions) -> Demangler {
+        Demangler { options }
+    }
+
+    fn demangle_cpp(&self, symbol: &str) -> Option<String> {
+        let mut options = cpp_demangle::DemangleOptions::new().no_return_type();
+        if self.options.drop_arguments {
+            options = options.no_params();
+        }
+        cpp_demangle::Symbol::new(symbol)
+            .ok()?
+            .demangle(&options)
+            .ok()
+    }
+
+    fn demangle_rust(&self, symbol: &str) -> Option<String> {
+        rustc_demangle::try_demangle(symbol)
+            .ok()
+            .map(|demangled| format!("{:#}", demangled))
+    }
+
+    /// Returns the demangled (and simplified) name, or `None` if the symbol is not mangled.
+    pub fn demangle(&self, symbol: &str) -> Option<String> {
+        if let Some(name) = self.demangle_symbol(symbol) {
+            return Some(name);
+        }
+
+        // Labels resolved from symbol files may have a source location appended.
+        let (symbol, location) = symbol.split_once(" (")?;
+        self.demangle_symbol(symbol)
+            .map(|name| format!("{} ({}", name, location))
+    }
+
+    fn demangle_symbol(&self, symbol: &str) -> Option<String> {
+        let demangled = if symbol.starts_with("_R") || is_rust_legacy_symbol(symbol) {
+            self.demangle_rust(symbol)
+        } else if symbol.starts_with("_Z") {
+            self.demangle_cpp(symbol)
+                .or_else(|| self.demangle_rust(symbol))
+        } else {
+            None
+        }?;
+
+        let mut name = demangled;
+        if self.options.drop_template_args {
+            name = strip_template_args(&name);
+        }
+        if let Some(count) = self.options.keep_segments {
+            name = keep_last_segments(&name, count);
+        }
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangle_should_ignore_non_mangled_names() {
+        let demangler = Demangler::new(DemangleOptions::default());
+
+        assert!(demangler.demangle("main_loop").is_none());
+        assert!(demangler.demangle("_Zinvalid").is_none());
+    }
+
+    #[test]
+    fn demangle_should_support_cpp_symbols() {
+        let demangler = Demangler::new(DemangleOptions::default());
+
+        assert_eq!(
+            demangler.demangle("_ZN2ns3FooIiE3runEv").unwrap(),
+            "ns::Foo<int>::run()"
+        );
+    }
+
+    #[test]
+    fn demangle_should_support_rust_symbols() {
+        let demangler = Demangler::new(DemangleOptions::default());
+
+        assert_eq!(
+            demangler
+                .demangle("_ZN4core3fmt5write17h0123456789abcdefE")
+                .unwrap(),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangler.demangle("_RNvCs1234_7mycrate3foo").unwrap(),
+            "mycrate::foo"
+        );
+    }
+
+    #[test]
+    fn demangle_should_apply_simplifications() {
+        let demangler = Demangler::new(DemangleOptions {
+            drop_template_args: true,
+            drop_arguments: true,
+            keep_segments: Some(2),
+        });
+
+        assert_eq!(
+            demangler.demangle("_ZN2ns3FooIiE3runEv").unwrap(),
+            "Foo::run"
+        );
+    }
+
+    #[test]
+    fn demangle_should_keep_source_location() {
+        let demangler = Demangler::new(DemangleOptions::default());
+
+        assert_eq!(
+            demangler.demangle("_ZN2ns3runEv (src/run.cpp:12)").unwrap(),
+            "ns::run() (src/run.cpp:12)"
+        );
+    }
+
+    #[test]
+    fn demangle_should_not_panic_on_multibyte_labels() {
+        let demangler = Demangler::new(DemangleOptions::default());
+
+        assert!(demangler.demangle("_ZNé0123456789abcdef12E").is_none());
+        assert!(demangler.demangle("_ZNzażółć_gęślą_jaźńE").is_none());
+    }
+
+    #[test]
+    fn strip_template_args_should_keep_operators() {
+        assert_eq!(
+            strip_template_args("Foo<Bar<int>>::operator<<(std::vector<int>)"),
+            "Foo::operator<<(std::vector)"
+        );
+    }
+
+    #[test]
+    fn keep_last_segments_should_respect_nesting() {
+        assert_eq!(
+            keep_last_segments("a::b<c::d>::run(e::f)", 2),
+            "b<c::d>::run(e::f)"
+        );
+        assert_eq!(keep_last_segments("run", 2), "run");
+    }
+}
@@ -0,0 +1,342 @@
+use super::LabelMap;
+use hawktracer_parser::{Event, Value};
+
+// Template syntax: text with `{name[!m][:spec]}` placeholders, `{{` and `}}` are literal braces.
+// `name` is an event field, or one of: `label` (resolved label), `category`, `klass`.
+// `!m` maps an integer field through the label map.
+// `spec` is `[<|>|^][width][.precision][x|X|b]`; precision truncates strings.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Radix {
+    Decimal,
+    LowerHex,
+    UpperHex,
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FormatSpec {
+    align: Option<Align>,
+    width: usize,
+    precision: Option<usize>,
+    radix: Radix,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Placeholder {
+        name: String,
+        map: bool,
+        spec: FormatSpec,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelTemplate {
+    segments: std::vec::Vec<Segment>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TemplateError {
+    pub template: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid label template {:?}: {}",
+            self.template, self.reason
+        )
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+pub struct TemplateContext<'a> {
+    pub event: &'a Event,
+    pub label: &'a str,
+    pub category: Option<&'a str>,
+    pub klass_name: Option<&'a str>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<FormatSpec, String> {
+        let mut chars = spec.chars().peekable();
+        let align = match chars.peek() {
+            Some('<') => Some(Align::Left),
+            Some('>') => Some(Align::Right),
+            Some('^') => Some(Align::Center),
+            _ => None,
+        };
+        if align.is_some() {
+            chars.next();
+        }
+
+        let mut width = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            width.push(*c);
+            chars.next();
+        }
+
+        let mut precision = None;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut value = String::new();
+            while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                value.push(*c);
+                chars.next();
+            }
+            precision = Some(value.parse::<usize>().map_err(|_| "missing precision")?);
+        }
+
+        let radix = match chars.next() {
+            None => Radix::Decimal,
+            Some('x') => Radix::LowerHex,
+            Some('X') => Radix::UpperHex,
+            Some('b') => Radix::Binary,
+            Some(c) => return Err(format!("unknown format type '{}'", c)),
+        };
+        if let Some(c) = chars.next() {
+            return Err(format!("unexpected '{}' in format spec", c));
+        }
+
+        Ok(FormatSpec {
+            align,
+            width: if width.is_empty() {
+                0
+            } else {
+                width.parse().map_err(|_| "invalid width")?
+            },
+            precision,
+            radix,
+        })
+    }
+
+    fn format_integer(&self, value: i128) -> String {
+        let sign = if value < 0 { "-" } else { "" };
+        let value = value.unsigned_abs();
+        match self.radix {
+            Radix::Decimal => format!("{}{}", sign, value),
+            Radix::LowerHex => format!("{}{:x}", sign, value),
+            Radix::UpperHex => format!("{}{:X}", sign, value),
+            Radix::Binary => format!("{}{:b}", sign, value),
+        }
+    }
+
+    fn write(&self, out: &mut String, value: &str) {
+        let value = match self.precision {
+            Some(precision) => match value.char_indices().nth(precision) {
+                Some((end, _)) => &value[..end],
+                None => value,
+            },
+            None => value,
+        };
+
+        let padding = self.width.saturating_sub(value.chars().count());
+        let (left, right) = match self.align.unwrap_or(Align::Left) {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+        out.extend(std::iter::repeat(' ').take(left));
+        out.push_str(value);
+        out.extend(std::iter::repeat(' ').take(right));
+    }
+}
+
+fn integer_value(value: &Value) -> Option<i128> {
+    match value {
+        Value::U8(v) => Some(i128::from(*v)),
+        Value::I8(v) => Some(i128::from(*v)),
+        Value::U16(v) => Some(i128::from(*v)),
+        Value::I16(v) => Some(i128::from(*v)),
+        Value::U32(v) => Some(i128::from(*v)),
+        Value::I32(v) => Some(i128::from(*v)),
+        Value::U64(v) => Some(i128::from(*v)),
+        Value::I64(v) => Some(i128::from(*v)),
+        Value::Str(_) | Value::Struct(_) => None,
+    }
+}
+
+impl LabelTemplate {
+    pub fn parse(template: &str) -> Result<LabelTemplate, TemplateError> {
+        let error = |reason: &str| TemplateError {
+            template: template.to_owned(),
+            reason: reason.to_owned(),
+        };
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => return Err(error("unmatched '}'")),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(error("unclosed placeholder")),
+                            Some(c) => placeholder.push(c),
+                        }
+                    }
+
+                    let (name, spec) = match placeholder.find(':') {
+                        Some(pos) => (&placeholder[..pos], &placeholder[pos + 1..]),
+                        None => (&placeholder[..], ""),
+                    };
+                    let (name, map) = match name.strip_suffix("!m") {
+                        Some(name) => (name, true),
+                        None => (name, false),
+                    };
+                    if name.is_empty() {
+                        return Err(error("empty placeholder name"));
+                    }
+                    let spec = FormatSpec::parse(spec).map_err(|reason| error(&reason))?;
+
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Placeholder {
+                        name: name.to_owned(),
+                        map,
+                        spec,
+                    });
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(LabelTemplate { segments })
+    }
+
+    /// Renders the template into `out`. Placeholders referring to missing values are left empty.
+    pub fn render(&self, out: &mut String, context: &TemplateContext, label_map: &mut LabelMap) {
+        for segment in &self.segments {
+            let (name, map, spec) = match segment {
+                Segment::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Segment::Placeholder { name, map, spec } => (name, *map, spec),
+            };
+
+            match &name[..] {
+                "label" => spec.write(out, context.label),
+                "category" => spec.write(out, context.category.unwrap_or("")),
+                "klass" => spec.write(out, context.klass_name.unwrap_or("")),
+                field => match context.event.get_raw_value(field) {
+                    Some(Value::Str(value)) => spec.write(out, value),
+                    Some(value) => match integer_value(value) {
+                        Some(id) if map && id >= 0 => {
+                            let label = label_map.get_label_for(
+                                id as u64,
+                                context.event.get_klass_id(),
+                                field,
+                            );
+                            spec.write(out, label)
+                        }
+                        Some(value) => spec.write(out, &spec.format_integer(value)),
+                        None => spec.write(out, &value.to_string()),
+                    },
+                    None => spec.write(out, ""),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, values: std::vec::Vec<(&str, Value)>) -> String {
+        let mut label_map = LabelMap::new();
+        label_map.add_mapping(7, "texture.png");
+        let event = Event::new(
+            1,
+            values
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+        );
+        let context = TemplateContext {
+            event: &event,
+            label: "load",
+            category: Some("io"),
+            klass_name: Some("MyEvent"),
+        };
+        let mut out = String::new();
+        LabelTemplate::parse(template)
+            .unwrap()
+            .render(&mut out, &context, &mut label_map);
+        out
+    }
+
+    #[test]
+    fn render_should_interpolate_fields_and_builtins() {
+        assert_eq!(
+            render(
+                "{label}(id={id}) [{category}] {klass}",
+                vec![("id", Value::U32(42))]
+            ),
+            "load(id=42) [io] MyEvent"
+        );
+    }
+
+    #[test]
+    fn render_should_map_field_through_label_map() {
+        assert_eq!(
+            render("{label} {texture!m}", vec![("texture", Value::U64(7))]),
+            "load texture.png"
+        );
+    }
+
+    #[test]
+    fn render_should_apply_format_spec() {
+        assert_eq!(
+            render(
+                "[{id:>6x}] [{name:.3}] [{id:^5}]",
+                vec![
+                    ("id", Value::U16(255)),
+                    ("name", Value::Str("abcdef".to_owned()))
+                ]
+            ),
+            "[    ff] [abc] [ 255 ]"
+        );
+    }
+
+    #[test]
+    fn render_should_leave_missing_fields_empty_and_keep_escaped_braces() {
+        assert_eq!(render("{{{missing}}}", vec![]), "{}");
+    }
+
+    #[test]
+    fn parse_should_reject_invalid_templates() {
+        assert!(LabelTemplate::parse("{label").is_err());
+        assert!(LabelTemplate::parse("label}").is_err());
+        assert!(LabelTemplate::parse("{}").is_err());
+        assert!(LabelTemplate::parse("{id:q}").is_err());
+    }
+}
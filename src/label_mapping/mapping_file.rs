@@ -0,0 +1,485 @@
+use super::{MappingConflict, MappingDefinition};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingFileFormat {
+    Text,
+    Json,
+    Csv,
+    Toml,
+}
+
+impl MappingFileFormat {
+    pub fn from_extension(path: &str) -> Option<MappingFileFormat> {
+        let extension = std::path::Path::new(path)
+            .extension()?
+            .to_str()?
+            .to_lowercase();
+
+        match &extension[..] {
+            "json" => Some(MappingFileFormat::Json),
+            "csv" => Some(MappingFileFormat::Csv),
+            "toml" => Some(MappingFileFormat::Toml),
+            "txt" | "map" => Some(MappingFileFormat::Text),
+            _ => None,
+        }
+    }
+
+    /// Structured formats are only picked on unambiguous markers, as text
+    /// labels may contain any characters (e.g. `Foo<int, bar>::run`).
+    pub fn from_content(content: &str) -> MappingFileFormat {
+        let first_line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or("");
+
+        if first_line == "[[mapping]]" {
+            MappingFileFormat::Toml
+        } else if first_line.starts_with('[') || first_line.starts_with('{') {
+            MappingFileFormat::Json
+        } else {
+            MappingFileFormat::Text
+        }
+    }
+
+    /// Line based formats can skip malformed lines, structured
+    /// formats (JSON, TOML) are either loaded entirely or not at all.
+    pub fn is_line_based(self) -> bool {
+        match self {
+            MappingFileFormat::Text | MappingFileFormat::Csv => true,
+            MappingFileFormat::Json | MappingFileFormat::Toml => false,
+        }
+    }
+
+    pub fn detect(path: &str, content: &str) -> MappingFileFormat {
+        MappingFileFormat::from_extension(path)
+            .unwrap_or_else(|| MappingFileFormat::from_content(content))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MappingFileEntry {
+    /// Line number, or entry index for structured formats.
+    pub line: usize,
+    pub category: Option<String>,
+    pub label: String,
+    pub id: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MalformedEntry {
+    pub line: usize,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum MappingFileError {
+    Io(std::io::Error),
+    Malformed {
+        file: String,
+        entries: Vec<MalformedEntry>,
+    },
+    /// Returned only if the map uses `ConflictPolicy::Error`; non-conflicting
+    /// entries of the file are loaded anyway.
+    Conflicts(Vec<MappingConflict>),
+}
+
+impl std::fmt::Display for MappingFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MappingFileError::Io(err) => write!(f, "{}", err),
+            MappingFileError::Malformed { file, entries } => {
+                write!(f, "invalid mapping file {}:", file)?;
+                for entry in entries {
+                    write!(f, "\n  {}:{}: {}", file, entry.line, entry.reason)?;
+                }
+                Ok(())
+            }
+            MappingFileError::Conflicts(conflicts) => {
+                write!(f, "conflicting mappings:")?;
+                for conflict in conflicts {
+                    write!(f, "\n  {}", conflict)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MappingFileError {}
+
+impl From<std::io::Error> for MappingFileError {
+    fn from(err: std::io::Error) -> MappingFileError {
+        MappingFileError::Io(err)
+    }
+}
+
+pub struct ParsedMappingFile {
+    pub entries: Vec<MappingFileEntry>,
+    pub malformed: Vec<MalformedEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct StructuredEntry {
+    id: u64,
+    label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TomlMappingFile {
+    mapping: Vec<StructuredEntry>,
+}
+
+/// Mappings without a category have an empty category in line based formats.
+fn parse_category(value: &str) -> Option<String> {
+    Some(value.to_owned()).filter(|category| !category.is_empty())
+}
+
+fn parse_id(value: &str) -> Result<u64, String> {
+    value
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("Can not parse identifier '{}'. Error message: {}", value, e))
+}
+
+fn parse_text(content: &str) -> ParsedMappingFile {
+    let mut parsed = ParsedMappingFile {
+        entries: vec![],
+        malformed: vec![],
+    };
+
+    for (i, line) in content.lines().enumerate() {
+        let line_number = i + 1;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Category is the first word and identifier the last one,
+        // so the label itself is allowed to contain spaces.
+        let category_end = line.find(' ');
+        let id_start = line.rfind(' ');
+        let (category_end, id_start) = match (category_end, id_start) {
+            (Some(category_end), Some(id_start)) if category_end < id_start => {
+                (category_end, id_start)
+            }
+            _ => {
+                parsed.malformed.push(MalformedEntry {
+                    line: line_number,
+                    reason: format!("Expected 3 arguments, was {}", line.split(' ').count()),
+                });
+                continue;
+            }
+        };
+
+        match parse_id(&line[id_start + 1..]) {
+            Ok(id) => parsed.entries.push(MappingFileEntry {
+                line: line_number,
+                category: parse_category(&line[..category_end]),
+                label: line[category_end + 1..id_start].to_owned(),
+                id,
+            }),
+            Err(reason) => parsed.malformed.push(MalformedEntry {
+                line: line_number,
+                reason,
+            }),
+        }
+    }
+
+    parsed
+}
+
+fn parse_csv(content: &str) -> ParsedMappingFile {
+    let mut parsed = ParsedMappingFile {
+        entries: vec![],
+        malformed: vec![],
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(content.as_bytes());
+
+    for (i, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                parsed.malformed.push(MalformedEntry {
+                    line: err.position().map_or(0, |pos| pos.line() as usize),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |pos| pos.line() as usize);
+
+        if record.len() != 3 {
+            parsed.malformed.push(MalformedEntry {
+                line,
+                reason: format!("Expected 3 arguments, was {}", record.len()),
+            });
+            continue;
+        }
+
+        if i == 0 && record[2].trim() == "id" {
+            continue;
+        }
+
+        match parse_id(&record[2]) {
+            Ok(id) => parsed.entries.push(MappingFileEntry {
+                line,
+                category: parse_category(&record[0]),
+                label: record[1].to_owned(),
+                id,
+            }),
+            Err(reason) => parsed.malformed.push(MalformedEntry { line, reason }),
+        }
+    }
+
+    parsed
+}
+
+fn structured_entries(entries: Vec<StructuredEntry>) -> ParsedMappingFile {
+    ParsedMappingFile {
+        entries: entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| MappingFileEntry {
+                line: i + 1,
+                category: entry.category,
+                label: entry.label,
+                id: entry.id,
+            })
+            .collect(),
+        malformed: vec![],
+    }
+}
+
+fn parse_json(content: &str) -> ParsedMappingFile {
+    match serde_json::from_str::<Vec<StructuredEntry>>(content) {
+        Ok(entries) => structured_entries(entries),
+        Err(err) => ParsedMappingFile {
+            entries: vec![],
+            malformed: vec![MalformedEntry {
+                line: err.line(),
+                reason: err.to_string(),
+            }],
+        },
+    }
+}
+
+fn parse_toml(content: &str) -> ParsedMappingFile {
+    match toml::from_str::<TomlMappingFile>(content) {
+        Ok(file) => structured_entries(file.mapping),
+        Err(err) => ParsedMappingFile {
+            entries: vec![],
+            malformed: vec![MalformedEntry {
+                line: err.line_col().map_or(0, |(line, _)| line + 1),
+                reason: err.to_string(),
+            }],
+        },
+    }
+}
+
+pub fn parse(content: &str, format: MappingFileFormat) -> ParsedMappingFile {
+    match format {
+        MappingFileFormat::Text => parse_text(content),
+        MappingFileFormat::Json => parse_json(content),
+        MappingFileFormat::Csv => parse_csv(content),
+        MappingFileFormat::Toml => parse_toml(content),
+    }
+}
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+}
+
+fn category(definition: &MappingDefinition) -> &str {
+    definition.category.as_deref().unwrap_or("")
+}
+
+/// Writes the mappings in a format that `parse` reads back.
+pub fn write(
+    writable: &mut dyn std::io::Write,
+    mappings: &[(u64, &MappingDefinition)],
+    format: MappingFileFormat,
+) -> std::io::Result<()> {
+    let structured_entries = || {
+        mappings
+            .iter()
+            .map(|(id, definition)| StructuredEntry {
+                id: *id,
+                label: definition.label.clone(),
+                category: definition.category.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    match format {
+        MappingFileFormat::Text => {
+            for (id, definition) in mappings {
+                writeln!(
+                    writable,
+                    "{} {} {}",
+                    category(definition),
+                    definition.label,
+                    id
+                )?;
+            }
+        }
+        MappingFileFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writable);
+            writer.write_record(["category", "label", "id"])?;
+            for (id, definition) in mappings {
+                writer.write_record([category(definition), &definition.label, &id.to_string()])?;
+            }
+            writer.flush()?;
+        }
+        MappingFileFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writable, &structured_entries())?;
+            writeln!(writable)?;
+        }
+        MappingFileFormat::Toml => {
+            let content = toml::to_string(&TomlMappingFile {
+                mapping: structured_entries(),
+            })
+            .map_err(to_io_error)?;
+            writable.write_all(content.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_should_prefer_file_extension() {
+        assert_eq!(
+            MappingFileFormat::detect("map.csv", "cat label 1"),
+            MappingFileFormat::Csv
+        );
+        assert_eq!(
+            MappingFileFormat::detect("map.JSON", ""),
+            MappingFileFormat::Json
+        );
+    }
+
+    #[test]
+    fn detect_should_guess_format_from_content() {
+        assert_eq!(
+            MappingFileFormat::detect("map", "[{\"id\": 1, \"label\": \"a\"}]"),
+            MappingFileFormat::Json
+        );
+        assert_eq!(
+            MappingFileFormat::detect("map", "# comment\n[[mapping]]\nid = 1"),
+            MappingFileFormat::Toml
+        );
+        assert_eq!(
+            MappingFileFormat::detect("map", "cpp label 1"),
+            MappingFileFormat::Text
+        );
+    }
+
+    #[test]
+    fn detect_should_treat_labels_with_separators_as_text() {
+        let content = "cpp Foo<int, bar>::run 12\ncpp operator=(Foo) 13";
+        let format = MappingFileFormat::detect("map", content);
+        assert_eq!(format, MappingFileFormat::Text);
+
+        let parsed = parse(content, format);
+        assert!(parsed.malformed.is_empty());
+        assert_eq!(parsed.entries[0].label, "Foo<int, bar>::run");
+        assert_eq!(parsed.entries[1].label, "operator=(Foo)");
+
+        assert_eq!(
+            MappingFileFormat::detect("map", "cpp a=b 1"),
+            MappingFileFormat::Text
+        );
+    }
+
+    #[test]
+    fn parse_text_should_allow_spaces_in_label() {
+        let parsed = parse("cpp Foo<int, bar>::run 12", MappingFileFormat::Text);
+
+        assert!(parsed.malformed.is_empty());
+        assert_eq!(parsed.entries[0].category, Some("cpp".to_owned()));
+        assert_eq!(parsed.entries[0].label, "Foo<int, bar>::run");
+        assert_eq!(parsed.entries[0].id, 12);
+    }
+
+    #[test]
+    fn parse_text_should_report_line_and_reason() {
+        let parsed = parse(
+            "cpp label 1\n\nonly_one\ncpp label x",
+            MappingFileFormat::Text,
+        );
+
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.malformed.len(), 2);
+        assert_eq!(parsed.malformed[0].line, 3);
+        assert_eq!(parsed.malformed[1].line, 4);
+    }
+
+    #[test]
+    fn parse_csv_should_support_quoting_and_header() {
+        let parsed = parse(
+            "category,label,id\ncpp,\"Foo<int, bar>::run\",7\ncpp,broken\n",
+            MappingFileFormat::Csv,
+        );
+
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].label, "Foo<int, bar>::run");
+        assert_eq!(parsed.entries[0].id, 7);
+        assert_eq!(parsed.malformed[0].line, 3);
+    }
+
+    #[test]
+    fn write_should_produce_parsable_content_in_all_formats() {
+        let with_category = MappingDefinition {
+            label: "Foo<int, bar>::run".to_owned(),
+            category: Some("cpp".to_owned()),
+            source: super::super::MappingSource::Api,
+        };
+        let without_category = MappingDefinition {
+            label: "frame".to_owned(),
+            category: None,
+            source: super::super::MappingSource::Api,
+        };
+        let mappings = [(1, &with_category), (2, &without_category)];
+
+        for format in [
+            MappingFileFormat::Text,
+            MappingFileFormat::Csv,
+            MappingFileFormat::Json,
+            MappingFileFormat::Toml,
+        ] {
+            let mut content = vec![];
+            write(&mut content, &mappings, format).unwrap();
+            let parsed = parse(std::str::from_utf8(&content).unwrap(), format);
+
+            assert!(parsed.malformed.is_empty(), "{:?}", format);
+            assert_eq!(parsed.entries.len(), 2);
+            assert_eq!(parsed.entries[0].id, 1);
+            assert_eq!(parsed.entries[0].label, "Foo<int, bar>::run");
+            assert_eq!(parsed.entries[0].category, Some("cpp".to_owned()));
+            assert_eq!(parsed.entries[1].label, "frame");
+            assert_eq!(parsed.entries[1].category, None, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn parse_json_should_report_syntax_error_line() {
+        let parsed = parse(
+            "[\n{\"id\": 1, \"label\": \"a\"},\n{\"id\": \"x\"}]",
+            MappingFileFormat::Json,
+        );
+
+        assert!(parsed.entries.is_empty());
+        assert_eq!(parsed.malformed[0].line, 3);
+    }
+}
@@ -0,0 +1,177 @@
+use super::MalformedEntry;
+
+// Rules file format: one `pattern => replacement` rule per line; lines starting
+// with `#` and blank lines are ignored. Replacement may refer to capture groups
+// (`$1`, `${name}`) and may be empty.
+
+const SEPARATOR: &str = "=>";
+
+#[derive(Debug, Clone)]
+struct RewriteRule {
+    pattern: regex::Regex,
+    replacement: String,
+}
+
+#[derive(Debug)]
+pub enum RewriteRulesError {
+    Io(std::io::Error),
+    Malformed {
+        file: String,
+        entries: std::vec::Vec<MalformedEntry>,
+    },
+}
+
+impl std::fmt::Display for RewriteRulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RewriteRulesError::Io(err) => write!(f, "{}", err),
+            RewriteRulesError::Malformed { file, entries } => {
+                write!(f, "invalid rewrite rules file {}:", file)?;
+                for entry in entries {
+                    write!(f, "\n  {}:{}: {}", file, entry.line, entry.reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RewriteRulesError {}
+
+impl From<std::io::Error> for RewriteRulesError {
+    fn from(err: std::io::Error) -> RewriteRulesError {
+        RewriteRulesError::Io(err)
+    }
+}
+
+/// Ordered regex rewrite rules; every rule is applied to the output of the previous one.
+#[derive(Debug, Clone, Default)]
+pub struct RewriteRules {
+    rules: std::vec::Vec<RewriteRule>,
+}
+
+impl RewriteRules {
+    pub fn new() -> RewriteRules {
+        RewriteRules { rules: vec![] }
+    }
+
+    pub fn add_rule(&mut self, pattern: &str, replacement: &str) -> Result<(), regex::Error> {
+        self.rules.push(RewriteRule {
+            pattern: regex::Regex::new(pattern)?,
+            replacement: replacement.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Parses rules file content. Rules are loaded only if all of them are valid.
+    pub fn parse(content: &str) -> Result<RewriteRules, std::vec::Vec<MalformedEntry>> {
+        let mut rules = RewriteRules::new();
+        let mut malformed = vec![];
+
+        for (i, line) in content.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (pattern, replacement) = match line.split_once(SEPARATOR) {
+                Some((pattern, replacement)) => (pattern.trim(), replacement.trim()),
+                None => {
+                    malformed.push(MalformedEntry {
+                        line: line_number,
+                        reason: format!("Expected 'pattern {} replacement'", SEPARATOR),
+                    });
+                    continue;
+                }
+            };
+            if let Err(err) = rules.add_rule(pattern, replacement) {
+                malformed.push(MalformedEntry {
+                    line: line_number,
+                    reason: err.to_string(),
+                });
+            }
+        }
+
+        if malformed.is_empty() {
+            Ok(rules)
+        } else {
+            Err(malformed)
+        }
+    }
+
+    pub fn load_from_file(path: &str) -> Result<RewriteRules, RewriteRulesError> {
+        let content = std::fs::read_to_string(path)?;
+        RewriteRules::parse(&content).map_err(|entries| RewriteRulesError::Malformed {
+            file: path.to_owned(),
+            entries,
+        })
+    }
+
+    /// Appends rules of another set, after the existing ones.
+    pub fn extend(&mut self, other: RewriteRules) {
+        self.rules.extend(other.rules);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies all the rules to the label; returns `None` if the label hasn't changed.
+    pub fn apply(&self, label: &str) -> Option<String> {
+        let mut result: Option<String> = None;
+        for rule in &self.rules {
+            let current = result.as_deref().unwrap_or(label);
+            if let std::borrow::Cow::Owned(rewritten) =
+                rule.pattern.replace_all(current, &rule.replacement[..])
+            {
+                result = Some(rewritten);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_should_run_rules_in_order() {
+        let rules = RewriteRules::parse(
+            "# collapse frame numbers\n\
+             frame_[0-9]+ => frame_N\n\
+             \n\
+             ^(draw|render)_.* => rendering\n\
+             ::h[0-9a-f]{16}$ =>\n",
+        )
+        .unwrap();
+
+        assert_eq!(rules.apply("frame_1234").unwrap(), "frame_N");
+        assert_eq!(rules.apply("draw_frame_12").unwrap(), "rendering");
+        assert_eq!(
+            rules.apply("core::fmt::write::h0123456789abcdef").unwrap(),
+            "core::fmt::write"
+        );
+        assert!(rules.apply("update").is_none());
+    }
+
+    #[test]
+    fn apply_should_expand_capture_groups() {
+        let mut rules = RewriteRules::new();
+        rules
+            .add_rule("^load_(?P<kind>[a-z]+)_[0-9]+$", "load(${kind})")
+            .unwrap();
+
+        assert_eq!(rules.apply("load_texture_7").unwrap(), "load(texture)");
+    }
+
+    #[test]
+    fn parse_should_report_invalid_rules() {
+        let malformed = RewriteRules::parse("a => b\nno separator\n(unclosed => x").unwrap_err();
+
+        assert_eq!(malformed.len(), 2);
+        assert_eq!(malformed[0].line, 2);
+        assert_eq!(malformed[1].line, 3);
+    }
+}
@@ -0,0 +1,294 @@
+/// Binary (ELF) file used for resolving code addresses, together with
+/// the address it was loaded at.
+pub struct SymbolFile {
+    path: String,
+    load_base: u64,
+    /// Address ranges of executable sections; other addresses are never resolved,
+    /// as symbol lookup matches the nearest preceding symbol.
+    code_ranges: std::vec::Vec<std::ops::Range<u64>>,
+    loader: addr2line::Loader,
+}
+
+#[derive(Debug)]
+pub struct SymbolFileError {
+    pub path: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for SymbolFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "can not load symbols from {}: {}",
+            self.path, self.reason
+        )
+    }
+}
+
+impl std::error::Error for SymbolFileError {}
+
+fn parse_address(value: &str) -> Result<u64, std::num::ParseIntError> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+}
+
+fn read_code_ranges(path: &str) -> Result<std::vec::Vec<std::ops::Range<u64>>, String> {
+    use object::{Object, ObjectSection};
+
+    let data = std::fs::read(path).map_err(|err| err.to_string())?;
+    let file = object::File::parse(&*data).map_err(|err| err.to_string())?;
+    Ok(file
+        .sections()
+        .filter(|section| section.kind() == object::SectionKind::Text)
+        .map(|section| section.address()..section.address() + section.size())
+        .collect())
+}
+
+impl SymbolFile {
+    pub fn new(path: &str, load_base: u64) -> Result<SymbolFile, SymbolFileError> {
+        let error = |reason: String| SymbolFileError {
+            path: path.to_owned(),
+            reason,
+        };
+        let code_ranges = read_code_ranges(path).map_err(error)?;
+        let loader = addr2line::Loader::new(path).map_err(|err| error(err.to_string()))?;
+
+        Ok(SymbolFile {
+            path: path.to_owned(),
+            load_base,
+            code_ranges,
+            loader,
+        })
+    }
+
+    /// Loads the file described as `PATH[@LOAD_BASE]`; the load base
+    /// is either decimal or hexadecimal (with `0x` prefix) number.
+    pub fn from_spec(spec: &str) -> Result<SymbolFile, SymbolFileError> {
+        match spec.rsplit_once('@') {
+            Some((path, load_base)) => {
+                let load_base = parse_address(load_base).map_err(|err| SymbolFileError {
+                    path: path.to_owned(),
+                    reason: format!("invalid load base '{}': {}", load_base, err),
+                })?;
+                SymbolFile::new(path, load_base)
+            }
+            None => SymbolFile::new(spec, 0),
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    fn function_name(&self, probe: u64) -> Option<String> {
+        if let Some(name) = self.loader.find_symbol(probe) {
+            return Some(name.to_owned());
+        }
+
+        // Stripped symbol table, try debug information instead.
+        let mut frames = self.loader.find_frames(probe).ok()?;
+        let mut name = None;
+        while let Ok(Some(frame)) = frames.next() {
+            if let Some(function) = frame.function {
+                name = function.raw_name().ok().map(|name| name.into_owned());
+            }
+        }
+        name
+    }
+
+    fn resolve(&self, address: u64, with_location: bool) -> Option<String> {
+        let probe = address
+            .checked_sub(self.load_base)?
+            .checked_add(self.loader.relative_address_base())?;
+        if !self.code_ranges.iter().any(|range| range.contains(&probe)) {
+            return None;
+        }
+        let name = self.function_name(probe)?;
+
+        let location = if with_location {
+            self.loader.find_location(probe).ok().flatten()
+        } else {
+            None
+        };
+        match location {
+            Some(addr2line::Location {
+                file: Some(file),
+                line: Some(line),
+                ..
+            }) => Some(format!("{} ({}:{})", name, file, line)),
+            Some(addr2line::Location {
+                file: Some(file), ..
+            }) => Some(format!("{} ({})", name, file)),
+            _ => Some(name),
+        }
+    }
+}
+
+/// Resolves code addresses to function names (and source locations,
+/// if debug information is available) using symbol files.
+pub struct Symbolizer {
+    files: std::vec::Vec<SymbolFile>,
+    with_location: bool,
+}
+
+impl Default for Symbolizer {
+    fn default() -> Symbolizer {
+        Symbolizer::new()
+    }
+}
+
+impl Symbolizer {
+    pub fn new() -> Symbolizer {
+        Symbolizer {
+            files: vec![],
+            with_location: true,
+        }
+    }
+
+    pub fn add_file(&mut self, file: SymbolFile) {
+        self.files.push(file);
+    }
+
+    pub fn set_with_location(&mut self, with_location: bool) {
+        self.with_location = with_location;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// True if the address is in a function of any of the files; cheaper than
+    /// `resolve`, as source locations are not looked up.
+    pub fn can_resolve(&self, address: u64) -> bool {
+        self.files
+            .iter()
+            .any(|file| file.resolve(address, false).is_some())
+    }
+
+    /// Returns the name of the function containing the address. Files
+    /// are checked in the order they were added.
+    pub fn resolve(&self, address: u64) -> Option<String> {
+        self.files
+            .iter()
+            .find_map(|file| file.resolve(address, self.with_location))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::{Object, ObjectSymbol};
+
+    #[no_mangle]
+    pub extern "C" fn hawktracer_converter_symbolizer_test_function() -> u64 {
+        42
+    }
+
+    fn load_test_binary(load_base: u64) -> (SymbolFile, u64) {
+        let path = std::env::current_exe().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let address = object::File::parse(&*data)
+            .unwrap()
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("hawktracer_converter_symbolizer_test_function"))
+            .unwrap()
+            .address();
+
+        (
+            SymbolFile::new(path.to_str().unwrap(), load_base).unwrap(),
+            address + load_base,
+        )
+    }
+
+    #[test]
+    fn resolve_should_return_function_name_and_location() {
+        assert_eq!(hawktracer_converter_symbolizer_test_function(), 42);
+        let (file, address) = load_test_binary(0x1000);
+        let mut symbolizer = Symbolizer::new();
+        symbolizer.add_file(file);
+
+        let label = symbolizer.resolve(address).unwrap();
+        assert!(label.starts_with("hawktracer_converter_symbolizer_test_function ("));
+        assert!(label.contains("symbolizer.rs:"));
+
+        symbolizer.set_with_location(false);
+        assert_eq!(
+            symbolizer.resolve(address).unwrap(),
+            "hawktracer_converter_symbolizer_test_function"
+        );
+    }
+
+    #[test]
+    fn resolve_should_ignore_addresses_below_load_base() {
+        let (file, _) = load_test_binary(0x1000);
+        let mut symbolizer = Symbolizer::new();
+        symbolizer.add_file(file);
+
+        assert!(symbolizer.resolve(0x10).is_none());
+    }
+
+    #[test]
+    fn label_map_should_resolve_unmapped_identifiers() {
+        let (file, address) = load_test_binary(0);
+        let mut symbolizer = Symbolizer::new();
+        symbolizer.set_with_location(false);
+        symbolizer.add_file(file);
+        let mut label_map = super::super::LabelMap::new();
+        label_map.set_symbolizer(symbolizer);
+
+        assert_eq!(
+            label_map.get_label(address),
+            "hawktracer_converter_symbolizer_test_function"
+        );
+        assert_eq!(label_map.get_label(1), "1");
+        assert_eq!(label_map.get_unresolved().len(), 1);
+    }
+
+    #[test]
+    fn label_getter_should_not_defer_events_resolved_by_symbolizer() {
+        let (file, address) = load_test_binary(0);
+        let mut symbolizer = Symbolizer::new();
+        symbolizer.add_file(file);
+        let mut label_map = super::super::LabelMap::new();
+        label_map.set_symbolizer(symbolizer);
+        let getter = super::super::LabelGetter::new(label_map, vec!["name".to_owned()]);
+        let reg = hawktracer_parser::EventKlassRegistry::new();
+        let make_event = |id: u64| {
+            let mut values = std::collections::HashMap::new();
+            values.insert("name".to_owned(), hawktracer_parser::Value::U64(id));
+            hawktracer_parser::Event::new(99, values)
+        };
+
+        assert!(!getter.has_unresolved_label(&make_event(address), &reg));
+        assert!(getter.has_unresolved_label(&make_event(1), &reg));
+
+        // Events labelled by addresses don't wait for the timeout.
+        let now = std::time::Instant::now();
+        let mut deferred = crate::DeferredEvents::new(10, std::time::Duration::from_secs(60));
+        deferred.push(make_event(address), now);
+        assert!(deferred
+            .pop_ready(now, |event| !getter.has_unresolved_label(event, &reg))
+            .is_some());
+    }
+
+    #[test]
+    fn from_spec_should_parse_load_base() {
+        let path = std::env::current_exe().unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(
+            SymbolFile::from_spec(&format!("{}@0x10", path))
+                .unwrap()
+                .load_base,
+            16
+        );
+        assert_eq!(SymbolFile::from_spec(path).unwrap().load_base, 0);
+        assert!(SymbolFile::from_spec(&format!("{}@xyz", path)).is_err());
+        assert!(SymbolFile::from_spec("does/not/exist").is_err());
+    }
+}
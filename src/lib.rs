@@ -1,9 +1,107 @@
+mod compression;
+pub use crate::compression::Compression;
+
+mod config;
+pub use crate::config::Config;
+pub use crate::config::ConfigError;
+pub use crate::config::DemangleConfig;
+pub use crate::config::LabelConfig;
+pub use crate::config::MappingKlassConfig;
+pub use crate::config::SymbolConfig;
+
+mod conversion;
+pub use crate::conversion::Conversion;
+pub use crate::conversion::ConversionSetup;
+pub use crate::conversion::ConversionStats;
+pub use crate::conversion::ResolvedEvent;
+pub use crate::conversion::ResolvedEvents;
+pub use crate::conversion::SetupError;
+
+mod converter_options;
+pub use crate::converter_options::ConverterOptions;
+pub use crate::converter_options::OptionError;
+pub use crate::converter_options::OptionSpec;
+pub use crate::converter_options::OptionType;
+pub use crate::converter_options::OptionValue;
+
 mod converters;
-use crate::converters::ConverterFactory;
+pub use crate::converters::Converter;
+pub use crate::converters::ConverterFactory;
+pub use crate::converters::ConverterMetadata;
+pub use crate::converters::FilteredConverter;
+pub use crate::converters::TransformedConverter;
 
 mod converter_manager;
 pub use crate::converter_manager::ConverterManager;
 
+mod deferred_events;
+pub use crate::deferred_events::DeferredEvents;
+
+mod error;
+pub use crate::error::ConversionError;
+pub use crate::error::ErrorKind;
+pub use crate::error::ErrorStats;
+pub use crate::error::KlassErrors;
+
+mod event_filter;
+pub use crate::event_filter::parse_duration;
+pub use crate::event_filter::EventFilter;
+pub use crate::event_filter::ExpressionError;
+pub use crate::event_filter::FilterExpression;
+
+mod event_pipeline;
+pub use crate::event_pipeline::BackpressurePolicy;
+pub use crate::event_pipeline::EventPipeline;
+pub use crate::event_pipeline::PipelineStats;
+
+mod ffi;
+pub use crate::ffi::ConversionWriteFn;
+pub use crate::ffi::FfiConversion;
+
 mod label_mapping;
+pub use crate::label_mapping::ConflictPolicy;
+pub use crate::label_mapping::DemangleOptions;
 pub use crate::label_mapping::LabelGetter;
 pub use crate::label_mapping::LabelMap;
+pub use crate::label_mapping::LabelOrigin;
+pub use crate::label_mapping::LabelTemplate;
+pub use crate::label_mapping::MalformedEntry;
+pub use crate::label_mapping::MappingConflict;
+pub use crate::label_mapping::MappingDefinition;
+pub use crate::label_mapping::MappingFileError;
+pub use crate::label_mapping::MappingFileFormat;
+pub use crate::label_mapping::MappingKlass;
+pub use crate::label_mapping::MappingSource;
+pub use crate::label_mapping::RewriteRules;
+pub use crate::label_mapping::RewriteRulesError;
+pub use crate::label_mapping::SymbolFile;
+pub use crate::label_mapping::SymbolFileError;
+pub use crate::label_mapping::Symbolizer;
+pub use crate::label_mapping::TemplateError;
+pub use crate::label_mapping::UnresolvedLabel;
+
+mod plugin;
+pub use crate::plugin::PluginConverterFactory;
+pub use crate::plugin::PluginDescriptor;
+pub use crate::plugin::PluginEntryFn;
+pub use crate::plugin::PluginError;
+pub use crate::plugin::PluginEvent;
+pub use crate::plugin::PluginField;
+pub use crate::plugin::PluginOptionSpec;
+pub use crate::plugin::PluginOptionValue;
+pub use crate::plugin::PluginWriteFn;
+pub use crate::plugin::PLUGIN_ABI_VERSION;
+pub use crate::plugin::PLUGIN_ENTRY_SYMBOL;
+pub use crate::plugin::PLUGIN_OPTION_BOOL;
+pub use crate::plugin::PLUGIN_OPTION_CHOICE;
+pub use crate::plugin::PLUGIN_OPTION_INTEGER;
+pub use crate::plugin::PLUGIN_OPTION_STRING;
+pub use crate::plugin::PLUGIN_VALUE_SIGNED;
+pub use crate::plugin::PLUGIN_VALUE_STRING;
+pub use crate::plugin::PLUGIN_VALUE_UNSIGNED;
+
+mod time_transform;
+pub use crate::time_transform::parse_time_offset;
+pub use crate::time_transform::parse_wall_clock_time;
+pub use crate::time_transform::TimeOrigin;
+pub use crate::time_transform::TimeTransform;
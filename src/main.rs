@@ -1,6 +1,4 @@
-use ctrlc;
 use hawktracer_converter_lib as hcl;
-use indicatif;
 use indicatif::{ProgressBar, ProgressStyle};
 
 fn create_output_path(path: &str) -> String {
@@ -8,37 +6,547 @@ fn create_output_path(path: &str) -> String {
     now.format(path).to_string()
 }
 
-fn create_label_getter(values: Option<clap::Values>) -> hcl::LabelGetter {
+fn parse_conflict_policy(value: &str) -> hcl::ConflictPolicy {
+    match value {
+        "first-wins" => hcl::ConflictPolicy::FirstWins,
+        "error" => hcl::ConflictPolicy::Error,
+        _ => hcl::ConflictPolicy::LastWins,
+    }
+}
+
+fn load_config(path: Option<&str>) -> hcl::Config {
+    match path {
+        Some(path) => hcl::Config::load_from_file(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load configuration from {}. Error: {}", path, err);
+            std::process::exit(1);
+        }),
+        None => hcl::Config::default(),
+    }
+}
+
+fn parse_label_template(template: &str) -> hcl::LabelTemplate {
+    hcl::LabelTemplate::parse(template).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
+
+fn parse_klass_label_template(value: &str) -> Option<(&str, &str)> {
+    let (klass_name, template) = value.split_once('=')?;
+
+    if klass_name.is_empty() {
+        None
+    } else {
+        Some((klass_name, template))
+    }
+}
+
+fn parse_klass_label_fields(value: &str) -> Option<(&str, std::vec::Vec<String>)> {
+    let (klass_name, fields) = value.split_once('=')?;
+    let fields: std::vec::Vec<String> = fields
+        .split(',')
+        .filter(|field| !field.is_empty())
+        .map(|field| field.to_owned())
+        .collect();
+
+    if klass_name.is_empty() || fields.is_empty() {
+        None
+    } else {
+        Some((klass_name, fields))
+    }
+}
+
+fn create_demangle_options(
+    matches: &clap::ArgMatches,
+    config: &hcl::DemangleConfig,
+) -> Option<hcl::DemangleOptions> {
+    let keep_segments = matches
+        .value_of("demangle-keep-segments")
+        .map(|value| value.parse().unwrap())
+        .or(config.keep_segments);
+    let options = hcl::DemangleOptions {
+        drop_template_args: matches.is_present("demangle-no-templates")
+            || config.drop_template_args,
+        drop_arguments: matches.is_present("demangle-no-args") || config.drop_arguments,
+        keep_segments,
+    };
+
+    if matches.is_present("demangle") || config.enabled || options != Default::default() {
+        Some(options)
+    } else {
+        None
+    }
+}
+
+fn create_symbolizer(
+    matches: &clap::ArgMatches,
+    config: &hcl::SymbolConfig,
+) -> Option<hcl::Symbolizer> {
+    let mut specs: std::vec::Vec<&str> = config.files.iter().map(|spec| &spec[..]).collect();
+    if let Some(values) = matches.values_of("symbol-files") {
+        specs.extend(values);
+    }
+    if specs.is_empty() {
+        return None;
+    }
+
+    let mut symbolizer = hcl::Symbolizer::new();
+    symbolizer.set_with_location(
+        !matches.is_present("no-symbol-locations") && config.locations.unwrap_or(true),
+    );
+    for spec in specs {
+        match hcl::SymbolFile::from_spec(spec) {
+            Ok(file) => {
+                eprintln!("Successfully loaded symbols from {}", file.get_path());
+                symbolizer.add_file(file);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    Some(symbolizer)
+}
+
+fn load_rewrite_rules(matches: &clap::ArgMatches, config: &hcl::LabelConfig) -> hcl::RewriteRules {
+    let mut paths: std::vec::Vec<&str> =
+        config.rewrite_rules.iter().map(|path| &path[..]).collect();
+    if let Some(values) = matches.values_of("rewrite-rules") {
+        paths.extend(values);
+    }
+
+    let mut rules = hcl::RewriteRules::new();
+    for path in paths {
+        match hcl::RewriteRules::load_from_file(path) {
+            Ok(file_rules) => rules.extend(file_rules),
+            Err(err) => {
+                eprintln!("Failed to load rewrite rules from {}. Error: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    rules
+}
+
+// Format: KLASS[=ID_FIELD,LABEL_FIELD[,CATEGORY_FIELD]]
+fn parse_mapping_klass(value: &str) -> Result<hcl::MappingKlass, String> {
+    let (klass_name, fields) = match value.split_once('=') {
+        Some((klass_name, fields)) => (klass_name, fields.split(',').collect()),
+        None => (value, vec![]),
+    };
+    if klass_name.is_empty() {
+        return Err("missing klass name".to_owned());
+    }
+
+    let default = hcl::MappingKlass::default();
+    match fields[..] {
+        [] => Ok(hcl::MappingKlass::new(
+            klass_name,
+            &default.identifier_field,
+            &default.label_field,
+        )),
+        [identifier_field, label_field] => Ok(hcl::MappingKlass::new(
+            klass_name,
+            identifier_field,
+            label_field,
+        )),
+        [identifier_field, label_field, category_field] => {
+            let mut mapping_klass =
+                hcl::MappingKlass::new(klass_name, identifier_field, label_field);
+            mapping_klass.category_field = Some(category_field.to_owned());
+            Ok(mapping_klass)
+        }
+        _ => Err("expected KLASS[=ID_FIELD,LABEL_FIELD[,CATEGORY_FIELD]]".to_owned()),
+    }
+}
+
+fn create_mapping_klass(config: &hcl::MappingKlassConfig) -> hcl::MappingKlass {
+    let default = hcl::MappingKlass::default();
+    let mut mapping_klass = hcl::MappingKlass::new(
+        &config.klass,
+        config
+            .identifier
+            .as_deref()
+            .unwrap_or(&default.identifier_field),
+        config.label.as_deref().unwrap_or(&default.label_field),
+    );
+    mapping_klass.category_field = config.category.clone();
+    mapping_klass
+}
+
+fn create_label_getter(matches: &clap::ArgMatches, config: &hcl::Config) -> hcl::LabelGetter {
+    let strict_mapping = matches.is_present("strict-mapping");
     let mut map = hcl::LabelMap::new();
+    map.set_conflict_policy(parse_conflict_policy(
+        matches.value_of("mapping-conflicts").unwrap(),
+    ));
 
-    if let Some(values) = values {
+    if let Some(values) = matches.values_of("map-files") {
         for value in values {
-            match map.load_from_file(value) {
+            let result = if strict_mapping {
+                map.load_from_file_strict(value)
+            } else {
+                map.load_from_file(value)
+            };
+            match result {
                 Ok(_) => eprintln!("Successfully loaded mapping from {}", value),
-                Err(err) => eprintln!("Failed to load mapping from {}. Error: {}", value, err),
+                Err(err) => {
+                    eprintln!("Failed to load mapping from {}. Error: {}", value, err);
+                    let is_conflict = matches!(err, hcl::MappingFileError::Conflicts(_));
+                    if strict_mapping || is_conflict {
+                        std::process::exit(1);
+                    }
+                }
             };
         }
     }
 
-    hcl::LabelGetter::new(map, vec!["label".to_owned(), "name".to_owned()])
+    if let Some(symbolizer) = create_symbolizer(matches, &config.symbols) {
+        map.set_symbolizer(symbolizer);
+    }
+
+    let label_fields = match matches.values_of("label-fields") {
+        Some(fields) => fields.map(|field| field.to_owned()).collect(),
+        None => config
+            .labels
+            .fields
+            .clone()
+            .unwrap_or_else(|| vec!["label".to_owned(), "name".to_owned()]),
+    };
+
+    let mut label_getter = hcl::LabelGetter::new(map, label_fields);
+    for (klass_name, fields) in &config.labels.klass_fields {
+        label_getter.set_klass_label_fields(klass_name, fields.clone());
+    }
+    if let Some(values) = matches.values_of("klass-label-fields") {
+        for value in values {
+            let (klass_name, fields) = parse_klass_label_fields(value).unwrap();
+            label_getter.set_klass_label_fields(klass_name, fields);
+        }
+    }
+
+    let template = matches
+        .value_of("label-template")
+        .or(config.labels.template.as_deref());
+    if let Some(template) = template {
+        label_getter.set_label_template(parse_label_template(template));
+    }
+    for (klass_name, template) in &config.labels.klass_templates {
+        label_getter.set_klass_label_template(klass_name, parse_label_template(template));
+    }
+    if let Some(values) = matches.values_of("klass-label-template") {
+        for value in values {
+            let (klass_name, template) = parse_klass_label_template(value).unwrap();
+            label_getter.set_klass_label_template(klass_name, parse_label_template(template));
+        }
+    }
+
+    if let Some(options) = create_demangle_options(matches, &config.demangle) {
+        label_getter.set_demangle_options(options);
+    }
+
+    for mapping_klass in &config.labels.mapping_klasses {
+        label_getter.add_mapping_klass(create_mapping_klass(mapping_klass));
+    }
+    if let Some(values) = matches.values_of("mapping-klass") {
+        for value in values {
+            label_getter.add_mapping_klass(parse_mapping_klass(value).unwrap());
+        }
+    }
+
+    label_getter.set_rewrite_rules(load_rewrite_rules(matches, &config.labels));
+    label_getter.set_klass_category_fallback(matches.is_present("klass-as-category"));
+    label_getter
 }
 
-fn create_output_stream(is_stdout: bool, output_file: &str) -> Box<dyn std::io::Write> {
-    if is_stdout {
-        return Box::new(std::io::stdout());
+fn validate_duration(value: String) -> Result<(), String> {
+    hcl::parse_duration(&value).map(|_| ())
+}
+
+fn parse_duration_arg(matches: &clap::ArgMatches, name: &str) -> Option<u64> {
+    matches
+        .value_of(name)
+        .map(|value| hcl::parse_duration(value).unwrap())
+}
+
+const FILTER_ARGS: [&str; 9] = [
+    "filter",
+    "filter-thread",
+    "filter-klass",
+    "filter-label",
+    "filter-exclude-label",
+    "filter-from",
+    "filter-to",
+    "filter-min-duration",
+    "filter-max-duration",
+];
+
+fn create_event_filter(
+    matches: &clap::ArgMatches,
+    label_getter: &hcl::LabelGetter,
+) -> Option<hcl::EventFilter> {
+    if !FILTER_ARGS.iter().any(|arg| matches.is_present(arg)) {
+        return None;
     }
 
-    let output_path = create_output_path(output_file);
-    eprintln!("Data will be saved at: {:?}", output_path);
-    Box::new(
-        std::fs::File::create(&output_path)
-            .unwrap_or_else(|_| panic!("Can't create output file {}", output_path)),
-    )
+    let mut filter = hcl::EventFilter::new(label_getter.clone());
+    for thread in matches.values_of("filter-thread").into_iter().flatten() {
+        filter.add_thread(thread);
+    }
+    for klass in matches.values_of("filter-klass").into_iter().flatten() {
+        filter.add_klass(klass);
+    }
+    // Patterns and the expression are checked by validators.
+    for pattern in matches.values_of("filter-label").into_iter().flatten() {
+        filter.add_label_pattern(pattern).unwrap();
+    }
+    for pattern in matches
+        .values_of("filter-exclude-label")
+        .into_iter()
+        .flatten()
+    {
+        filter.add_excluded_label_pattern(pattern).unwrap();
+    }
+    filter.set_time_window(
+        parse_duration_arg(matches, "filter-from"),
+        parse_duration_arg(matches, "filter-to"),
+    );
+    filter.set_duration_range(
+        parse_duration_arg(matches, "filter-min-duration"),
+        parse_duration_arg(matches, "filter-max-duration"),
+    );
+    if let Some(expression) = matches.value_of("filter") {
+        filter.set_expression(hcl::FilterExpression::parse(expression).unwrap());
+    }
+    Some(filter)
+}
+
+fn validate_time_offset(value: String) -> Result<(), String> {
+    hcl::parse_time_offset(&value).map(|_| ())
+}
+
+fn validate_time_scale(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(scale) if scale.is_finite() && scale > 0.0 => Ok(()),
+        _ => Err(format!("scale must be a positive number, got '{}'", value)),
+    }
+}
+
+fn parse_wall_clock_pair(value: &str) -> Result<(u64, u64), String> {
+    let (monotonic, wall_clock) = value
+        .split_once('=')
+        .ok_or_else(|| "expected MONOTONIC=WALL_CLOCK".to_owned())?;
+    Ok((
+        hcl::parse_duration(monotonic)?,
+        hcl::parse_wall_clock_time(wall_clock)?,
+    ))
+}
+
+fn parse_wall_clock_field(value: &str) -> Option<(&str, &str)> {
+    match value.rsplit_once('.') {
+        Some((klass, field)) if !klass.is_empty() && !field.is_empty() => Some((klass, field)),
+        _ => None,
+    }
+}
+
+const TIME_ARGS: [&str; 6] = [
+    "rebase-time",
+    "rebase-time-marker",
+    "wall-clock",
+    "wall-clock-event",
+    "time-offset",
+    "time-scale",
+];
+
+fn create_time_transform(
+    matches: &clap::ArgMatches,
+    label_getter: &hcl::LabelGetter,
+) -> Option<hcl::TimeTransform> {
+    if !TIME_ARGS.iter().any(|arg| matches.is_present(arg)) {
+        return None;
+    }
+
+    let mut transform = hcl::TimeTransform::new(label_getter.clone());
+    // All values are checked by validators.
+    if matches.is_present("rebase-time") {
+        transform.set_origin(hcl::TimeOrigin::FirstEvent);
+    } else if let Some(expression) = matches.value_of("rebase-time-marker") {
+        transform.set_origin(hcl::TimeOrigin::Marker(
+            hcl::FilterExpression::parse(expression).unwrap(),
+        ));
+    } else if let Some(pair) = matches.value_of("wall-clock") {
+        let (monotonic, wall_clock) = parse_wall_clock_pair(pair).unwrap();
+        transform.set_origin(hcl::TimeOrigin::WallClock {
+            monotonic,
+            wall_clock,
+        });
+    } else if let Some(value) = matches.value_of("wall-clock-event") {
+        let (klass_name, field) = parse_wall_clock_field(value).unwrap();
+        transform.set_origin(hcl::TimeOrigin::WallClockEvent {
+            klass_name: klass_name.to_owned(),
+            field: field.to_owned(),
+        });
+    }
+    if let Some(offset) = matches.value_of("time-offset") {
+        transform.set_offset(hcl::parse_time_offset(offset).unwrap());
+    }
+    if let Some(scale) = matches.value_of("time-scale") {
+        transform.set_scale(scale.parse().unwrap());
+    }
+    Some(transform)
+}
+
+fn report_time_transform(transform: &hcl::TimeTransform) {
+    if !transform.has_origin() {
+        eprintln!("Warning: time origin not found, all events were skipped");
+    } else if transform.get_dropped_count() > 0 {
+        eprintln!(
+            "Warning: skipped {} event(s) preceding the time origin",
+            transform.get_dropped_count()
+        );
+    }
+    if transform.get_clamped_count() > 0 {
+        eprintln!(
+            "Warning: time of {} event(s) was out of range and got clamped",
+            transform.get_clamped_count()
+        );
+    }
+}
+
+fn report_mapping_conflicts(label_map: &hcl::LabelMap) -> bool {
+    let conflicts = label_map.get_conflicts();
+    if !conflicts.is_empty() {
+        eprintln!("Found {} mapping conflict(s):", conflicts.len());
+        for conflict in conflicts {
+            eprintln!("  {}", conflict);
+        }
+    }
+    !conflicts.is_empty()
+}
+
+fn report_unresolved_labels(
+    label_map: &hcl::LabelMap,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    top_count: usize,
+) {
+    let unresolved = label_map.get_unresolved();
+    if unresolved.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "Labels for {} identifier(s) do not exist in the mapping. Most frequent:",
+        unresolved.len()
+    );
+    for label in unresolved.iter().take(top_count) {
+        let mut origins: std::vec::Vec<String> = label
+            .origins
+            .iter()
+            .map(|(origin, hits)| format!("{} ({})", origin.describe(reg), hits))
+            .collect();
+        origins.sort();
+        eprintln!(
+            "  {}: {} hit(s) in {}",
+            label.id,
+            label.hits,
+            origins.join(", ")
+        );
+    }
+    if unresolved.len() > top_count {
+        eprintln!("  ... and {} more", unresolved.len() - top_count);
+    }
+}
+
+fn write_unresolved_skeleton(
+    label_map: &hcl::LabelMap,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    path: &str,
+) {
+    let result = std::fs::File::create(path)
+        .and_then(|mut file| label_map.write_unresolved_skeleton(&mut file, reg));
+    match result {
+        Ok(_) => eprintln!("Mapping skeleton for unresolved labels saved at: {}", path),
+        Err(err) => eprintln!(
+            "Failed to write mapping skeleton to {}. Error: {}",
+            path, err
+        ),
+    }
+}
+
+fn save_mapping(label_map: &hcl::LabelMap, path: &str) {
+    match label_map.save_to_file(path) {
+        Ok(_) => eprintln!("Mapping saved at: {}", path),
+        Err(err) => eprintln!("Failed to save mapping to {}. Error: {}", path, err),
+    }
+}
+
+fn create_output_stream(
+    is_stdout: bool,
+    output_file: &str,
+    compression: Option<(hcl::Compression, Option<i32>)>,
+) -> Box<dyn std::io::Write> {
+    let stream: Box<dyn std::io::Write> = if is_stdout {
+        Box::new(std::io::stdout())
+    } else {
+        let output_path = create_output_path(output_file);
+        eprintln!("Data will be saved at: {:?}", output_path);
+        Box::new(
+            std::fs::File::create(&output_path)
+                .unwrap_or_else(|_| panic!("Can't create output file {}", output_path)),
+        )
+    };
+
+    // The compressed stream is finished when the converter is dropped, also after Ctrl+C.
+    match compression {
+        Some((compression, level)) => compression
+            .wrap_writer(stream, level)
+            .expect("Unable to create compressed output"),
+        None => stream,
+    }
+}
+
+// Compression set by --compression, not detected from the output file.
+fn get_compression_flag(matches: &clap::ArgMatches) -> Option<hcl::Compression> {
+    match matches.value_of("compression") {
+        None | Some("none") => None,
+        Some(compression) => Some(compression.parse().unwrap()),
+    }
+}
+
+fn get_output_compression(
+    matches: &clap::ArgMatches,
+    output_file: &str,
+) -> Option<(hcl::Compression, Option<i32>)> {
+    let compression = if matches.is_present("compression") || matches.is_present("stdout") {
+        get_compression_flag(matches)
+    } else {
+        hcl::Compression::from_path(output_file)
+    }?;
+    let level = matches
+        .value_of("compression-level")
+        .map(|level| level.parse().unwrap());
+    if let Err(err) = level.map_or(Ok(()), |level| compression.check_level(level)) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    Some((compression, level))
+}
+
+// Extension of the output file, without the compression extension (`trace.json.gz` is `json`).
+fn get_output_extension(output_file: &str) -> Option<&str> {
+    let path = std::path::Path::new(output_file);
+    let path = if hcl::Compression::from_path(output_file).is_some() {
+        std::path::Path::new(path.file_stem()?)
+    } else {
+        path
+    };
+    path.extension()?.to_str()
 }
 
 fn wait_for_connection(
     socket_addr: std::net::SocketAddr,
-) -> std::io::Result<Box<dyn std::io::Read>> {
+) -> std::io::Result<Box<dyn std::io::Read + Send>> {
     use std::io::ErrorKind;
     loop {
         let tcp_stream = std::net::TcpStream::connect(socket_addr);
@@ -72,23 +580,388 @@ fn create_spinner(message: &str) -> ProgressBar {
     pb
 }
 
-fn create_event_reader(
+fn is_file_source(source: &str) -> bool {
+    source.parse::<std::net::Ipv4Addr>().is_err() && source.parse::<std::net::SocketAddr>().is_err()
+}
+
+// Every format needs its own output file, unless there's only one.
+const DEFAULT_OUTPUT_NAME: &str = "hawktracer-trace-%Y-%m-%d-%H_%M_%S";
+
+// Formats not given are detected from extensions of output files; output files
+// not given are named after the format.
+fn get_outputs<'a>(
+    matches: &'a clap::ArgMatches,
+    converter_manager: &'a hcl::ConverterManager,
+) -> std::vec::Vec<(&'a str, String)> {
+    let formats: Option<std::vec::Vec<&str>> =
+        matches.values_of("format").map(|values| values.collect());
+    let output_files: Option<std::vec::Vec<&str>> = matches
+        .values_of("output-file")
+        .map(|values| values.collect());
+    let default_output_file = |format: &str, is_single: bool| {
+        let mut extension = converter_manager
+            .get_metadata(format)
+            .unwrap()
+            .file_extension;
+        if let Some(compression) = get_compression_flag(matches) {
+            extension = format!("{}.{}", extension, compression.get_file_extension());
+        }
+        if is_single {
+            format!("{}.{}", DEFAULT_OUTPUT_NAME, extension)
+        } else {
+            format!("{}-{}.{}", DEFAULT_OUTPUT_NAME, format, extension)
+        }
+    };
+
+    let default_format = converter_manager.get_converters()[0];
+
+    let outputs: std::vec::Vec<(&str, String)> = match (formats, output_files) {
+        (Some(formats), Some(output_files)) => {
+            if formats.len() > 1 && formats.len() != output_files.len() {
+                eprintln!("Each --format requires its own --output-file");
+                std::process::exit(1);
+            }
+            if formats.len() != output_files.len() {
+                eprintln!("Each --output-file requires its own --format");
+                std::process::exit(1);
+            }
+            formats
+                .into_iter()
+                .zip(output_files.into_iter().map(str::to_owned))
+                .collect()
+        }
+        (Some(formats), None) => {
+            let is_single = formats.len() == 1;
+            formats
+                .into_iter()
+                .map(|format| (format, default_output_file(format, is_single)))
+                .collect()
+        }
+        (None, Some(output_files)) => output_files
+            .into_iter()
+            .map(|output_file| {
+                let format = get_output_extension(output_file)
+                    .and_then(|extension| converter_manager.find_converter_by_extension(extension))
+                    .unwrap_or_else(|| {
+                        eprintln!(
+                            "Warning: unable to detect format of {} from its extension, using {}",
+                            output_file, default_format
+                        );
+                        default_format
+                    });
+                (format, output_file.to_owned())
+            })
+            .collect(),
+        (None, None) => vec![(default_format, default_output_file(default_format, true))],
+    };
+
+    if outputs.len() > 1 && matches.is_present("stdout") {
+        eprintln!("--stdout can not be used with multiple formats");
+        std::process::exit(1);
+    }
+    outputs
+}
+
+// Network sources stream until stopped, so converters keeping all events grow without bounds.
+fn report_buffering_converters(
+    converter_manager: &hcl::ConverterManager,
+    formats: &[&str],
     source: &str,
-) -> std::io::Result<hawktracer_parser::event_reader::EventReader> {
-    let source_obj: Box<dyn std::io::Read> =
-        if let Ok(ip_address) = source.parse::<std::net::Ipv4Addr>() {
-            wait_for_connection(std::net::SocketAddr::new(
-                std::net::IpAddr::V4(ip_address),
-                8765,
-            ))?
-        } else if let Ok(ip_address) = source.parse::<std::net::SocketAddr>() {
-            wait_for_connection(ip_address)?
+) {
+    if is_file_source(source) {
+        return;
+    }
+    for format in formats {
+        if !converter_manager.get_metadata(format).unwrap().is_streaming {
+            eprintln!(
+                "Warning: {} keeps all events until the conversion is done; its memory usage grows for as long as {} is streaming",
+                format, source
+            );
+        }
+    }
+}
+
+// Splits `[FORMAT.]NAME=VALUE` into its parts.
+fn parse_converter_option(value: &str) -> Option<(Option<&str>, &str, &str)> {
+    let eq_pos = value.find('=')?;
+    let (key, option_value) = (&value[..eq_pos], &value[eq_pos + 1..]);
+    let (format, name) = match key.find('.') {
+        Some(dot_pos) => (Some(&key[..dot_pos]), &key[dot_pos + 1..]),
+        None => (None, key),
+    };
+    if name.is_empty() || format == Some("") {
+        None
+    } else {
+        Some((format, name, option_value))
+    }
+}
+
+fn create_formats_help(converter_manager: &hcl::ConverterManager) -> String {
+    let mut help = String::from("FORMATS:");
+    for format in converter_manager.get_converters() {
+        let metadata = converter_manager.get_metadata(format).unwrap();
+        help.push_str(&format!(
+            "\n    {:<18}{} (.{}, {})",
+            format,
+            metadata.description,
+            metadata.file_extension,
+            if metadata.is_streaming {
+                "streamed"
+            } else {
+                "written at the end"
+            }
+        ));
+    }
+    help
+}
+
+fn create_compression_level_help() -> String {
+    let levels: std::vec::Vec<String> = [hcl::Compression::Gzip, hcl::Compression::Zstd]
+        .iter()
+        .map(|compression| {
+            let range = compression.get_level_range();
+            format!(
+                "{} to {} for {:?} ({} by default)",
+                range.start(),
+                range.end(),
+                compression,
+                compression.get_default_level()
+            )
+            .to_lowercase()
+        })
+        .collect();
+    format!("Compression level: {}", levels.join(", "))
+}
+
+fn create_options_help(converter_manager: &hcl::ConverterManager) -> String {
+    let mut help = String::from("CONVERTER OPTIONS (-O [FORMAT.]NAME=VALUE):");
+    for format in converter_manager.get_converters() {
+        let options = converter_manager.get_options(format).unwrap();
+        if options.is_empty() {
+            continue;
+        }
+        help.push_str(&format!("\n    {}:", format));
+        for option in options {
+            help.push_str(&format!(
+                "\n        {}=<{}>    {} [default: {}]",
+                option.name, option.option_type, option.help, option.default
+            ));
+        }
+    }
+    help
+}
+
+// Options without a format apply to all the formats declaring them.
+fn create_converter_options(
+    matches: &clap::ArgMatches,
+    converter_manager: &hcl::ConverterManager,
+    formats: &[&str],
+) -> std::vec::Vec<hcl::ConverterOptions> {
+    let options: std::vec::Vec<(Option<&str>, &str, &str)> = matches
+        .values_of("option")
+        .map(|values| values.filter_map(parse_converter_option).collect())
+        .unwrap_or_default();
+
+    for (format, name, _) in &options {
+        let is_used = formats
+            .iter()
+            .filter(|f| format.map_or(true, |format| format == **f))
+            .any(|f| {
+                converter_manager
+                    .get_options(f)
+                    .unwrap()
+                    .iter()
+                    .any(|spec| spec.name == *name)
+            });
+        if !is_used {
+            match format {
+                Some(format) => eprintln!("Format '{}' has no option '{}'", format, name),
+                None => eprintln!("None of the selected formats has option '{}'", name),
+            }
+            std::process::exit(1);
+        }
+    }
+
+    formats
+        .iter()
+        .map(|format| {
+            let schema = converter_manager.get_options(format).unwrap();
+            let values: std::vec::Vec<(&str, &str)> = options
+                .iter()
+                .filter(|(option_format, name, _)| {
+                    option_format.map_or(true, |f| f == *format)
+                        && schema.iter().any(|spec| spec.name == *name)
+                })
+                .map(|(_, name, value)| (*name, *value))
+                .collect();
+            hcl::ConverterOptions::parse(&schema, &values)
+                .and_then(|options| {
+                    converter_manager.check_options(format, &options)?;
+                    Ok(options)
+                })
+                .unwrap_or_else(|err| {
+                    eprintln!("{}: {}", format, err);
+                    std::process::exit(1);
+                })
+        })
+        .collect()
+}
+
+fn create_deferred_events(matches: &clap::ArgMatches) -> hcl::DeferredEvents {
+    hcl::DeferredEvents::new(
+        matches
+            .value_of("deferred-labels-buffer")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        std::time::Duration::from_millis(
+            matches
+                .value_of("deferred-labels-timeout")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        ),
+    )
+}
+
+fn process_event(
+    converter: &mut dyn hcl::Converter,
+    event: &hawktracer_parser::Event,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    error_stats: &mut hcl::ErrorStats,
+    is_verbose: bool,
+) {
+    let result = converter.process_event(event, reg);
+    if let (Err(err), true) = (&result, is_verbose) {
+        eprintln!("Error processing event: {}", err);
+    }
+    error_stats.record(event, reg, &result);
+}
+
+fn should_defer_event(
+    converter: &dyn hcl::Converter,
+    deferred_events: &hcl::DeferredEvents,
+    event: &hawktracer_parser::Event,
+    reg: &hawktracer_parser::EventKlassRegistry,
+) -> bool {
+    match converter.get_label_getter() {
+        // Mapping events are never deferred, as they resolve deferred events.
+        Some(label_getter) => {
+            !label_getter.is_mapping_event(event)
+                && (!deferred_events.is_empty() || label_getter.has_unresolved_label(event, reg))
+        }
+        None => false,
+    }
+}
+
+fn process_deferred_events(
+    converter: &mut dyn hcl::Converter,
+    deferred_events: &mut hcl::DeferredEvents,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    error_stats: &mut hcl::ErrorStats,
+    is_verbose: bool,
+    flush: bool,
+) {
+    loop {
+        let event = if flush {
+            deferred_events.pop()
         } else {
-            Box::new(std::fs::File::open(source)?)
+            let label_getter = converter.get_label_getter();
+            deferred_events.pop_ready(std::time::Instant::now(), |event| {
+                label_getter.map_or(true, |getter| !getter.has_unresolved_label(event, reg))
+            })
         };
+        match event {
+            Some(event) => process_event(converter, &event, reg, error_stats, is_verbose),
+            None => break,
+        }
+    }
+}
 
-    let provider = hawktracer_parser::data_provider::DataProvider::new(source_obj);
-    Ok(hawktracer_parser::event_reader::EventReader::new(provider))
+fn open_source(source: &str) -> std::io::Result<Box<dyn std::io::Read + Send>> {
+    if let Ok(ip_address) = source.parse::<std::net::Ipv4Addr>() {
+        wait_for_connection(std::net::SocketAddr::new(
+            std::net::IpAddr::V4(ip_address),
+            8765,
+        ))
+    } else if let Ok(ip_address) = source.parse::<std::net::SocketAddr>() {
+        wait_for_connection(ip_address)
+    } else {
+        Ok(Box::new(std::fs::File::open(source)?))
+    }
+}
+
+// Events of these klasses are never dropped by the pipeline, as later events depend on them.
+fn get_kept_klasses(
+    matches: &clap::ArgMatches,
+    label_getter: &hcl::LabelGetter,
+) -> std::vec::Vec<String> {
+    let mut klasses: std::vec::Vec<String> = label_getter
+        .get_mapping_klasses()
+        .iter()
+        .map(|mapping_klass| mapping_klass.klass_name.clone())
+        .collect();
+    if let Some((klass_name, _)) = matches
+        .value_of("wall-clock-event")
+        .and_then(parse_wall_clock_field)
+    {
+        klasses.push(klass_name.to_owned());
+    }
+    klasses
+}
+
+fn create_progress_message(stats: &hcl::PipelineStats) -> String {
+    format!(
+        "Getting data. Press [Ctrl+C to finish] ({} events, {:.0} events/s, queue {}/{}, dropped {})",
+        stats.parsed_events,
+        stats.get_events_per_second(),
+        stats.queue_depth,
+        stats.queue_size,
+        stats.dropped_events
+    )
+}
+
+// How long the main loop waits for an event before doing periodic work (progress, deferred events).
+const RECEIVE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+fn convert_event(
+    converter: &mut dyn hcl::Converter,
+    deferred_events: Option<&mut hcl::DeferredEvents>,
+    event: hawktracer_parser::Event,
+    reg: &hawktracer_parser::EventKlassRegistry,
+    error_stats: &mut hcl::ErrorStats,
+    is_verbose: bool,
+) {
+    match deferred_events {
+        Some(deferred_events) => {
+            if should_defer_event(converter, deferred_events, &event, reg) {
+                deferred_events.push(event, std::time::Instant::now());
+            } else {
+                process_event(converter, &event, reg, error_stats, is_verbose);
+            }
+            process_deferred_events(
+                converter,
+                deferred_events,
+                reg,
+                error_stats,
+                is_verbose,
+                false,
+            );
+        }
+        None => process_event(converter, &event, reg, error_stats, is_verbose),
+    }
+}
+
+// Errors are tolerated, unless limited by --fail-on-errors or --max-errors.
+fn exceeds_error_limit(matches: &clap::ArgMatches, error_stats: &hcl::ErrorStats) -> bool {
+    let max_errors = if matches.is_present("fail-on-errors") {
+        Some(0)
+    } else {
+        matches
+            .value_of("max-errors")
+            .map(|value| value.parse::<u64>().unwrap())
+    };
+    max_errors.is_some_and(|max_errors| error_stats.get_failed_event_count() > max_errors)
 }
 
 fn setup_stop_handler() -> std::sync::Arc<std::sync::atomic::AtomicBool> {
@@ -102,13 +975,67 @@ fn setup_stop_handler() -> std::sync::Arc<std::sync::atomic::AtomicBool> {
     running
 }
 
+// Values of an option given either as `--name VALUE`, or `--name=VALUE`.
+fn get_early_arg_values(args: &[String], name: &str) -> std::vec::Vec<String> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let mut values = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if *arg == flag {
+            values.extend(iter.next().cloned());
+        } else if arg.starts_with(&prefix) {
+            values.push(arg[prefix.len()..].to_owned());
+        }
+    }
+    values
+}
+
+// Plugins add formats, so they have to be loaded before the command line is parsed.
+fn load_plugins(converter_manager: &mut hcl::ConverterManager, args: &[String]) {
+    let exit_on_error = |err: hcl::PluginError| -> ! {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    };
+
+    if let Some(paths) = std::env::var_os("HAWKTRACER_CONVERTER_PLUGIN_PATH") {
+        for directory in std::env::split_paths(&paths).filter(|path| path.is_dir()) {
+            if let Err(err) = converter_manager.load_plugins_from_directory(&directory) {
+                exit_on_error(err);
+            }
+        }
+    }
+    for directory in get_early_arg_values(args, "plugin-dir") {
+        if let Err(err) = converter_manager.load_plugins_from_directory(directory.as_ref()) {
+            exit_on_error(err);
+        }
+    }
+    for path in get_early_arg_values(args, "plugin") {
+        if let Err(err) = converter_manager.load_plugin(path.as_ref()) {
+            exit_on_error(err);
+        }
+    }
+}
+
 fn main() {
-    let converter_manager = hcl::ConverterManager::new();
+    let mut converter_manager = hcl::ConverterManager::new();
+    load_plugins(
+        &mut converter_manager,
+        &std::env::args().collect::<std::vec::Vec<_>>(),
+    );
+    let converter_manager = converter_manager;
+    let after_help = format!(
+        "{}\n\n{}",
+        create_formats_help(&converter_manager),
+        create_options_help(&converter_manager)
+    );
+    let compression_level_help = create_compression_level_help();
 
     let matches = clap::App::new("hawktracer-converter")
         .about("Converts HawkTracer data stream to well-known data formats")
         .author("Marcin Kolny <marcin.kolny@gmail.com>")
         .version(env!("CARGO_PKG_VERSION"))
+        .after_help(&after_help[..])
         .arg(
             clap::Arg::with_name("source")
                 .long("source")
@@ -122,31 +1049,450 @@ fn main() {
                 .long("format")
                 .short("f")
                 .takes_value(true)
-                .required(true)
+                .multiple(true)
+                .number_of_values(1)
                 .possible_values(&converter_manager.get_converters()[..])
-                .default_value(converter_manager.get_converters()[0])
-                .help("Conversion format"),
+                .help("Conversion format (see the list below); can be repeated, each with its own --output-file. Detected from the --output-file extension if not given, chrome-tracing by default"),
         )
         .arg(
             clap::Arg::with_name("output-file")
                 .long("output-file")
                 .short("o")
                 .takes_value(true)
-                .required_unless("stdout")
-                .help("Output file")
-                .default_value("hawktracer-trace-%Y-%m-%d-%H_%M_%S.httrace"),
+                .multiple(true)
+                .number_of_values(1)
+                .help("Output file; one for every --format. Named hawktracer-trace-%Y-%m-%d-%H_%M_%S with the extension of the format if not given"),
+        )
+        .arg(
+            clap::Arg::with_name("option")
+                .long("option")
+                .short("O")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("[FORMAT.]NAME=VALUE")
+                .validator(|value| match parse_converter_option(&value) {
+                    Some(_) => Ok(()),
+                    None => Err(format!("expected [FORMAT.]NAME=VALUE, was '{}'", value)),
+                })
+                .help("Converter option (see the list below); can be repeated"),
+        )
+        .arg(
+            clap::Arg::with_name("plugin")
+                .long("plugin")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATH")
+                .help("Converter plugin (shared library) adding a format"),
+        )
+        .arg(
+            clap::Arg::with_name("plugin-dir")
+                .long("plugin-dir")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("DIR")
+                .help("Directory with converter plugins; all shared libraries in it are loaded"),
         )
         .arg(
             clap::Arg::with_name("stdout")
                 .long("stdout")
                 .help("Print data to standard output"),
         )
+        .arg(
+            clap::Arg::with_name("compression")
+                .long("compression")
+                .takes_value(true)
+                .possible_values(&["gzip", "zstd", "none"])
+                .help("Compress the output; detected from the --output-file extension (.gz, .zst) if not given"),
+        )
+        .arg(
+            clap::Arg::with_name("compression-level")
+                .long("compression-level")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("LEVEL")
+                .validator(|value| {
+                    value
+                        .parse::<i32>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help(&compression_level_help[..]),
+        )
         .arg(
             clap::Arg::with_name("map-files")
                 .long("map-files")
                 .short("m")
                 .min_values(1)
-                .help("List of mapping files"),
+                .help("List of mapping files (text, JSON, CSV or TOML)"),
+        )
+        .arg(
+            clap::Arg::with_name("mapping-klass")
+                .long("mapping-klass")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KLASS[=ID_FIELD,LABEL_FIELD[,CATEGORY_FIELD]]")
+                .validator(|value| parse_mapping_klass(&value).map(|_| ()))
+                .help("Event klass carrying mappings, in addition to HT_StringMappingEvent"),
+        )
+        .arg(
+            clap::Arg::with_name("save-mapping")
+                .long("save-mapping")
+                .takes_value(true)
+                .help("Save all mappings (loaded from files and received in the stream) to a file; format is chosen by extension"),
+        )
+        .arg(
+            clap::Arg::with_name("deferred-labels")
+                .long("deferred-labels")
+                .help("Resolve labels whose mapping arrives after the events using them (file sources are scanned twice, events from network sources are delayed)"),
+        )
+        .arg(
+            clap::Arg::with_name("deferred-labels-buffer")
+                .long("deferred-labels-buffer")
+                .takes_value(true)
+                .default_value("10000")
+                .validator(|value| {
+                    value
+                        .parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Maximum number of events delayed by --deferred-labels"),
+        )
+        .arg(
+            clap::Arg::with_name("deferred-labels-timeout")
+                .long("deferred-labels-timeout")
+                .takes_value(true)
+                .value_name("MS")
+                .default_value("1000")
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Maximum time (in milliseconds) an event is delayed by --deferred-labels"),
+        )
+        .arg(
+            clap::Arg::with_name("symbol-files")
+                .long("symbol-files")
+                .min_values(1)
+                .value_name("PATH[@LOAD_BASE]")
+                .help(
+                    "Binaries (ELF) used for resolving unmapped identifiers as function addresses",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("no-symbol-locations")
+                .long("no-symbol-locations")
+                .help("Do not append source file and line to labels resolved from symbol files"),
+        )
+        .arg(
+            clap::Arg::with_name("strict-mapping")
+                .long("strict-mapping")
+                .help("Fail if any of the mapping files contains malformed entries"),
+        )
+        .arg(
+            clap::Arg::with_name("mapping-conflicts")
+                .long("mapping-conflicts")
+                .takes_value(true)
+                .possible_values(&["first-wins", "last-wins", "error"])
+                .default_value("last-wins")
+                .help("Policy for identifiers mapped more than once"),
+        )
+        .arg(
+            clap::Arg::with_name("missing-labels-top")
+                .long("missing-labels-top")
+                .takes_value(true)
+                .default_value("10")
+                .validator(|value| {
+                    value
+                        .parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Number of most frequent unresolved label identifiers to print"),
+        )
+        .arg(
+            clap::Arg::with_name("missing-labels-skeleton")
+                .long("missing-labels-skeleton")
+                .takes_value(true)
+                .help("Write a mapping file skeleton for unresolved label identifiers"),
+        )
+        .arg(
+            clap::Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .takes_value(true)
+                .help("Configuration file (TOML)"),
+        )
+        .arg(
+            clap::Arg::with_name("label-fields")
+                .long("label-fields")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .help("Event fields used as a label, in order of precedence [default: label,name]"),
+        )
+        .arg(
+            clap::Arg::with_name("klass-label-fields")
+                .long("klass-label-fields")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KLASS=FIELDS")
+                .validator(|value| match parse_klass_label_fields(&value) {
+                    Some(_) => Ok(()),
+                    None => Err("expected KLASS=FIELD[,FIELD...]".to_owned()),
+                })
+                .help("Label fields for a specific event klass, e.g. MyIoEvent=path"),
+        )
+        .arg(
+            clap::Arg::with_name("label-template")
+                .long("label-template")
+                .takes_value(true)
+                .help("Template of the label, e.g. \"{label}(id={id})\""),
+        )
+        .arg(
+            clap::Arg::with_name("klass-label-template")
+                .long("klass-label-template")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KLASS=TEMPLATE")
+                .validator(|value| match parse_klass_label_template(&value) {
+                    Some(_) => Ok(()),
+                    None => Err("expected KLASS=TEMPLATE".to_owned()),
+                })
+                .help("Label template for a specific event klass"),
+        )
+        .arg(
+            clap::Arg::with_name("rewrite-rules")
+                .long("rewrite-rules")
+                .min_values(1)
+                .help("Files with regex rewrite rules (pattern => replacement) applied to every label"),
+        )
+        .arg(
+            clap::Arg::with_name("demangle")
+                .long("demangle")
+                .help("Demangle C++ and Rust symbols used as labels"),
+        )
+        .arg(
+            clap::Arg::with_name("demangle-no-templates")
+                .long("demangle-no-templates")
+                .help("Remove template arguments from demangled labels (implies --demangle)"),
+        )
+        .arg(
+            clap::Arg::with_name("demangle-no-args")
+                .long("demangle-no-args")
+                .help("Remove argument lists from demangled labels (implies --demangle)"),
+        )
+        .arg(
+            clap::Arg::with_name("demangle-keep-segments")
+                .long("demangle-keep-segments")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|value| {
+                    value
+                        .parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help(
+                    "Keep only the last N path segments of demangled labels (implies --demangle)",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .value_name("EXPRESSION")
+                .validator(|value| {
+                    hcl::FilterExpression::parse(&value)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Convert only events matching the expression, e.g. 'duration > 1ms && label =~ \"^IO\"'"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-thread")
+                .long("filter-thread")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("ID|NAME")
+                .help("Convert only events of the thread"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-klass")
+                .long("filter-klass")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KLASS")
+                .help("Convert only events of the klass"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-label")
+                .long("filter-label")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("REGEX")
+                .validator(|value| regex::Regex::new(&value).map(|_| ()).map_err(|err| err.to_string()))
+                .help("Convert only events whose label matches the pattern"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-exclude-label")
+                .long("filter-exclude-label")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("REGEX")
+                .validator(|value| regex::Regex::new(&value).map(|_| ()).map_err(|err| err.to_string()))
+                .help("Skip events whose label matches the pattern"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-from")
+                .long("filter-from")
+                .takes_value(true)
+                .value_name("TIME")
+                .validator(validate_duration)
+                .help("Skip events starting earlier than TIME (e.g. 1.5s) after the first event"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-to")
+                .long("filter-to")
+                .takes_value(true)
+                .value_name("TIME")
+                .validator(validate_duration)
+                .help("Skip events starting later than TIME (e.g. 3s) after the first event"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-min-duration")
+                .long("filter-min-duration")
+                .takes_value(true)
+                .value_name("TIME")
+                .validator(validate_duration)
+                .help("Skip events shorter than TIME (e.g. 10us)"),
+        )
+        .arg(
+            clap::Arg::with_name("filter-max-duration")
+                .long("filter-max-duration")
+                .takes_value(true)
+                .value_name("TIME")
+                .validator(validate_duration)
+                .help("Skip events longer than TIME (e.g. 5ms)"),
+        )
+        .arg(
+            clap::Arg::with_name("rebase-time")
+                .long("rebase-time")
+                .help("Make time relative to the earliest event"),
+        )
+        .arg(
+            clap::Arg::with_name("rebase-time-marker")
+                .long("rebase-time-marker")
+                .takes_value(true)
+                .value_name("EXPRESSION")
+                .validator(|value| {
+                    hcl::FilterExpression::parse(&value)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Make time relative to the first event matching the filter EXPRESSION"),
+        )
+        .arg(
+            clap::Arg::with_name("wall-clock")
+                .long("wall-clock")
+                .takes_value(true)
+                .value_name("MONOTONIC=WALL_CLOCK")
+                .validator(|value| parse_wall_clock_pair(&value).map(|_| ()))
+                .help("Convert time to wall-clock time (since the Unix epoch), given a pair of monotonic time (e.g. 71608470311129) and wall-clock time (e.g. 2020-02-01T12:00:00Z or 1580558400s)"),
+        )
+        .arg(
+            clap::Arg::with_name("wall-clock-event")
+                .long("wall-clock-event")
+                .takes_value(true)
+                .value_name("KLASS.FIELD")
+                .validator(|value| {
+                    parse_wall_clock_field(&value)
+                        .map(|_| ())
+                        .ok_or_else(|| "expected KLASS.FIELD".to_owned())
+                })
+                .help("Convert time to wall-clock time, taken from FIELD (nanoseconds since the Unix epoch) of the first KLASS event"),
+        )
+        .group(
+            clap::ArgGroup::with_name("time-origin").args(&[
+                "rebase-time",
+                "rebase-time-marker",
+                "wall-clock",
+                "wall-clock-event",
+            ]),
+        )
+        .arg(
+            clap::Arg::with_name("time-offset")
+                .long("time-offset")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("TIME")
+                .validator(validate_time_offset)
+                .help("Add TIME (e.g. 5ms or -1.5s) to timestamps"),
+        )
+        .arg(
+            clap::Arg::with_name("time-scale")
+                .long("time-scale")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .validator(validate_time_scale)
+                .help("Multiply time since the origin, and durations, by FACTOR"),
+        )
+        .arg(
+            clap::Arg::with_name("fail-on-errors")
+                .long("fail-on-errors")
+                .help("Exit with code 2 if any event failed to convert"),
+        )
+        .arg(
+            clap::Arg::with_name("max-errors")
+                .long("max-errors")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with("fail-on-errors")
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Exit with code 2 if more than N events failed to convert"),
+        )
+        .arg(
+            clap::Arg::with_name("queue-size")
+                .long("queue-size")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("10000")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(0) => Err("queue size must be positive".to_owned()),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Maximum number of events read ahead of the conversion"),
+        )
+        .arg(
+            clap::Arg::with_name("backpressure")
+                .long("backpressure")
+                .takes_value(true)
+                .possible_values(&["block", "drop"])
+                .default_value("block")
+                .help("What to do with events when the queue is full: wait for the conversion (and slow down reading the source), or drop them"),
+        )
+        .arg(
+            clap::Arg::with_name("klass-as-category")
+                .long("klass-as-category")
+                .help("Use event klass name as a category if no category is mapped"),
         )
         .arg(
             clap::Arg::with_name("verbose")
@@ -157,46 +1503,201 @@ fn main() {
         .get_matches();
 
     let is_verbose = matches.is_present("verbose");
+    let config = load_config(matches.value_of("config"));
+    let conflict_policy = parse_conflict_policy(matches.value_of("mapping-conflicts").unwrap());
     let source = matches.value_of("source").unwrap();
 
-    let mut converter = converter_manager
-        .create_converter(
-            matches.value_of("format").unwrap(),
-            create_output_stream(
-                matches.is_present("stdout"),
-                matches.value_of("output-file").unwrap(),
-            ),
-            create_label_getter(matches.values_of("map-files")),
+    let (formats, output_files): (std::vec::Vec<&str>, std::vec::Vec<String>) =
+        get_outputs(&matches, &converter_manager)
+            .into_iter()
+            .unzip();
+    report_buffering_converters(&converter_manager, &formats, source);
+    let converter_options = create_converter_options(&matches, &converter_manager, &formats);
+
+    let is_deferred = matches.is_present("deferred-labels");
+    // Mapping and symbol files are loaded only once; the converters, the filter and
+    // the time transform get copies of the (prescanned) label getter.
+    let mut setup = hcl::ConversionSetup::new(create_label_getter(&matches, &config));
+    if let Some(time_transform) = create_time_transform(&matches, setup.get_label_getter()) {
+        setup.set_time_transform(time_transform);
+    }
+    if is_file_source(source) && (is_deferred || setup.has_time_transform()) {
+        let source_stream = open_source(source)
+            .unwrap_or_else(|_| panic!("Unable to create reader from source: {}", source));
+        setup.prescan(source_stream, is_deferred);
+    }
+    if let Some(event_filter) = create_event_filter(&matches, setup.get_label_getter()) {
+        setup.set_filter(event_filter);
+    }
+    let kept_klasses = get_kept_klasses(&matches, setup.get_label_getter());
+    let label_getters: std::vec::Vec<hcl::LabelGetter> = (0..formats.len())
+        .map(|_| setup.get_label_getter().clone())
+        .collect();
+    // Streams can't be scanned ahead, so events with unresolved labels wait for the mapping instead.
+    let mut deferred_events = if is_deferred && !is_file_source(source) {
+        Some(create_deferred_events(&matches))
+    } else {
+        None
+    };
+
+    let compressions: std::vec::Vec<Option<(hcl::Compression, Option<i32>)>> = output_files
+        .iter()
+        .map(|output_file| get_output_compression(&matches, output_file))
+        .collect();
+    if matches.is_present("compression-level") && compressions.iter().all(Option::is_none) {
+        eprintln!("--compression-level requires compressed output (--compression, or a .gz or .zst output file)");
+        std::process::exit(1);
+    }
+    let outputs = formats
+        .iter()
+        .zip(output_files.iter().zip(compressions))
+        .zip(label_getters)
+        .zip(converter_options)
+        .map(
+            |(((format, (output_file, compression)), label_getter), options)| {
+                (
+                    *format,
+                    create_output_stream(matches.is_present("stdout"), output_file, compression),
+                    label_getter,
+                    options,
+                )
+            },
         )
-        .expect("Unable to create converter");
+        .collect();
+    let mut converter = setup.wrap_converter(
+        converter_manager
+            .create_composite_converter(outputs)
+            .expect("Unable to create converter"),
+    );
 
-    let connection_spinner = create_spinner(&format!(
-        "Waiting for connection to source: {}",
-        &source[..]
-    ));
+    let connection_spinner =
+        create_spinner(&format!("Waiting for connection to source: {}", source));
 
-    let mut reader = create_event_reader(source)
-        .unwrap_or_else(|_| panic!("Unable to create reader from source: {}", &source[..]));
-    connection_spinner.finish_with_message(&format!("Connected to source: {}", &source[..]));
-    let mut reg = hawktracer_parser::EventKlassRegistry::new();
+    let source_stream = open_source(source)
+        .unwrap_or_else(|_| panic!("Unable to create reader from source: {}", source));
+    connection_spinner.finish_with_message(&format!("Connected to source: {}", source));
 
-    let data_read_spinner = create_spinner(&"Getting data. Press [Ctrl+C to finish]".to_string());
+    let data_read_spinner = create_spinner("Getting data. Press [Ctrl+C to finish]");
 
     let running_flag = setup_stop_handler();
+    // Reading and parsing run on their own threads, so a slow conversion doesn't stall the source.
+    let mut pipeline = hcl::EventPipeline::start(
+        source_stream,
+        matches.value_of("queue-size").unwrap().parse().unwrap(),
+        matches.value_of("backpressure").unwrap().parse().unwrap(),
+        kept_klasses,
+        running_flag.clone(),
+    );
+    let mut error_stats = hcl::ErrorStats::new();
+    let mut reported_at = std::time::Instant::now();
 
     while running_flag.load(std::sync::atomic::Ordering::SeqCst) {
-        match reader.read_event(&mut reg) {
-            Ok(event) => {
-                if let Err(err) = converter.process_event(&event.flat_event(), &reg) {
-                    // TODO flat optional from command line
-                    if is_verbose {
-                        eprintln!("Error processing event: {}", err);
-                    }
+        // Deferred events time out even if the stream goes quiet.
+        let timeout = deferred_events
+            .as_ref()
+            .and_then(|deferred_events| deferred_events.get_expiry())
+            .map_or(RECEIVE_TIMEOUT, |expiry| {
+                expiry
+                    .saturating_duration_since(std::time::Instant::now())
+                    .min(RECEIVE_TIMEOUT)
+            });
+        match pipeline.recv_timeout(timeout) {
+            Ok(event) => convert_event(
+                converter.as_mut(),
+                deferred_events.as_mut(),
+                event,
+                pipeline.get_registry(),
+                &mut error_stats,
+                is_verbose,
+            ),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                if let Some(deferred_events) = deferred_events.as_mut() {
+                    process_deferred_events(
+                        converter.as_mut(),
+                        deferred_events,
+                        pipeline.get_registry(),
+                        &mut error_stats,
+                        is_verbose,
+                        false,
+                    );
                 }
             }
-            Err(_) => break,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if reported_at.elapsed() >= std::time::Duration::from_secs(1) {
+            data_read_spinner.set_message(&create_progress_message(&pipeline.get_stats()));
+            reported_at = std::time::Instant::now();
         }
     }
+    // Events already read from the source are converted, even when stopped.
+    while let Ok(event) = pipeline.try_recv() {
+        convert_event(
+            converter.as_mut(),
+            deferred_events.as_mut(),
+            event,
+            pipeline.get_registry(),
+            &mut error_stats,
+            is_verbose,
+        );
+    }
+    let pipeline_stats = pipeline.get_stats();
+    let reg = pipeline.get_registry();
+    if let Some(deferred_events) = deferred_events.as_mut() {
+        process_deferred_events(
+            converter.as_mut(),
+            deferred_events,
+            reg,
+            &mut error_stats,
+            is_verbose,
+            true,
+        );
+    }
 
     data_read_spinner.finish_with_message("Done tracing!");
+
+    let mut has_conflicts = false;
+    if let Some(label_getter) = converter.get_label_getter() {
+        let label_map = label_getter.get_label_map();
+        has_conflicts = report_mapping_conflicts(label_map);
+        report_unresolved_labels(
+            label_map,
+            reg,
+            matches
+                .value_of("missing-labels-top")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        );
+        if let Some(path) = matches.value_of("missing-labels-skeleton") {
+            write_unresolved_skeleton(label_map, reg, path);
+        }
+        if let Some(path) = matches.value_of("save-mapping") {
+            save_mapping(label_map, path);
+        }
+    }
+
+    if let Some(time_transform) = converter.get_time_transform() {
+        report_time_transform(time_transform);
+    }
+
+    // Some converters write the output only once they're finished.
+    error_stats.record_finish(&converter.finish());
+    drop(converter);
+
+    if pipeline_stats.dropped_events > 0 || is_verbose {
+        pipeline_stats
+            .write_summary(&mut std::io::stderr())
+            .unwrap();
+    }
+    let is_output_finished = error_stats.get_finish_errors().is_empty();
+    if error_stats.get_failed_event_count() > 0 || !is_output_finished || is_verbose {
+        error_stats.write_summary(&mut std::io::stderr()).unwrap();
+    }
+
+    if !is_output_finished || (has_conflicts && conflict_policy == hcl::ConflictPolicy::Error) {
+        std::process::exit(1);
+    }
+    if exceeds_error_limit(&matches, &error_stats) {
+        std::process::exit(2);
+    }
 }
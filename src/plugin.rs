@@ -0,0 +1,571 @@
+mod abi;
+pub use self::abi::PluginDescriptor;
+pub use self::abi::PluginEntryFn;
+pub use self::abi::PluginEvent;
+pub use self::abi::PluginField;
+pub use self::abi::PluginOptionSpec;
+pub use self::abi::PluginOptionValue;
+pub use self::abi::PluginWriteFn;
+pub use self::abi::PLUGIN_ABI_VERSION;
+pub use self::abi::PLUGIN_ENTRY_SYMBOL;
+pub use self::abi::PLUGIN_OPTION_BOOL;
+pub use self::abi::PLUGIN_OPTION_CHOICE;
+pub use self::abi::PLUGIN_OPTION_INTEGER;
+pub use self::abi::PLUGIN_OPTION_STRING;
+pub use self::abi::PLUGIN_VALUE_SIGNED;
+pub use self::abi::PLUGIN_VALUE_STRING;
+pub use self::abi::PLUGIN_VALUE_UNSIGNED;
+
+use crate::converters::Converter;
+use crate::ConversionError;
+use crate::ConverterFactory;
+use crate::ConverterOptions;
+use crate::LabelGetter;
+use crate::OptionError;
+use crate::{OptionSpec, OptionType};
+use hawktracer_parser::{Event, EventKlassRegistry, Value};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_int, c_void};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum PluginError {
+    Load {
+        path: String,
+        error: libloading::Error,
+    },
+    IncompatibleAbi {
+        path: String,
+        version: u32,
+    },
+    InvalidDescriptor {
+        path: String,
+        reason: String,
+    },
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PluginError::Load { path, error } => {
+                write!(f, "unable to load plugin {}: {}", path, error)
+            }
+            PluginError::IncompatibleAbi { path, version } => write!(
+                f,
+                "plugin {} was built for ABI version {}, expected {}",
+                path, version, PLUGIN_ABI_VERSION
+            ),
+            PluginError::InvalidDescriptor { path, reason } => {
+                write!(f, "invalid plugin {}: {}", path, reason)
+            }
+            PluginError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<std::io::Error> for PluginError {
+    fn from(err: std::io::Error) -> Self {
+        PluginError::Io(err)
+    }
+}
+
+// Strings given to a plugin can't contain NUL characters.
+fn to_c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap()
+}
+
+unsafe fn from_c_string(value: *const std::os::raw::c_char) -> Option<String> {
+    if value.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+    }
+}
+
+unsafe extern "C" fn write_to_output(context: *mut c_void, data: *const u8, size: usize) -> c_int {
+    let writable = &mut *(context as *mut Box<dyn std::io::Write>);
+    let data = if size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, size)
+    };
+    match writable.write_all(data) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Converter factory implemented by a shared library, see `abi` for the interface.
+pub struct PluginConverterFactory {
+    descriptor: *const PluginDescriptor,
+    name: String,
+    options: std::vec::Vec<OptionSpec>,
+    // Keeps the descriptor and the functions valid; must be dropped last.
+    library: Option<Arc<libloading::Library>>,
+}
+
+impl PluginConverterFactory {
+    /// Loads a plugin from a shared library.
+    pub fn load(path: &std::path::Path) -> Result<PluginConverterFactory, PluginError> {
+        let path_str = path.to_string_lossy().into_owned();
+        let load_error = |error| PluginError::Load {
+            path: path_str.clone(),
+            error,
+        };
+
+        // Loading a library runs its initialization code; plugins are trusted the same
+        // way as the converter itself.
+        let library = unsafe { libloading::Library::new(path) }.map_err(load_error)?;
+        let descriptor = unsafe {
+            let entry = library
+                .get::<PluginEntryFn>(PLUGIN_ENTRY_SYMBOL.as_bytes())
+                .map_err(load_error)?;
+            entry()
+        };
+
+        let mut factory =
+            unsafe { PluginConverterFactory::from_descriptor(descriptor, &path_str)? };
+        factory.library = Some(Arc::new(library));
+        Ok(factory)
+    }
+
+    /// Creates a factory for a plugin linked into the application.
+    ///
+    /// # Safety
+    ///
+    /// `descriptor` must either be null, or point to a descriptor of the same ABI
+    /// version which outlives the factory and all the converters created by it.
+    pub unsafe fn from_descriptor(
+        descriptor: *const PluginDescriptor,
+        path: &str,
+    ) -> Result<PluginConverterFactory, PluginError> {
+        let invalid = |reason: &str| PluginError::InvalidDescriptor {
+            path: path.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        if descriptor.is_null() {
+            return Err(invalid("entry point returned no descriptor"));
+        }
+        let abi_version = (*descriptor).abi_version;
+        if abi_version != PLUGIN_ABI_VERSION {
+            return Err(PluginError::IncompatibleAbi {
+                path: path.to_owned(),
+                version: abi_version,
+            });
+        }
+
+        let descriptor_ref = &*descriptor;
+        let name = from_c_string(descriptor_ref.name)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| invalid("format name is not set"))?;
+
+        let mut options = vec![];
+        if descriptor_ref.option_count > 0 {
+            if descriptor_ref.options.is_null() {
+                return Err(invalid("options are not set"));
+            }
+            let specs =
+                std::slice::from_raw_parts(descriptor_ref.options, descriptor_ref.option_count);
+            for spec in specs {
+                let option_name =
+                    from_c_string(spec.name).ok_or_else(|| invalid("option name is not set"))?;
+                let option_type = match spec.option_type {
+                    PLUGIN_OPTION_BOOL => OptionType::Bool,
+                    PLUGIN_OPTION_INTEGER => OptionType::Integer,
+                    PLUGIN_OPTION_STRING => OptionType::String,
+                    PLUGIN_OPTION_CHOICE => OptionType::Choice(
+                        from_c_string(spec.choices)
+                            .ok_or_else(|| invalid("choices are not set"))?
+                            .split('|')
+                            .map(str::to_owned)
+                            .collect(),
+                    ),
+                    _ => return Err(invalid("unknown option type")),
+                };
+                let option = OptionSpec::new(
+                    &option_name,
+                    option_type,
+                    &from_c_string(spec.default_value).unwrap_or_default(),
+                    &from_c_string(spec.help).unwrap_or_default(),
+                );
+                if let Err(err) = option.parse_value(&option.default) {
+                    return Err(invalid(&format!("invalid default value: {}", err)));
+                }
+                options.push(option);
+            }
+        }
+
+        Ok(PluginConverterFactory {
+            descriptor,
+            name,
+            options,
+            library: None,
+        })
+    }
+}
+
+impl PluginConverterFactory {
+    // Returns a null handle if the plugin failed to create the converter.
+    fn create_handle(
+        &self,
+        writable: &mut Box<Box<dyn std::io::Write>>,
+        options: &ConverterOptions,
+    ) -> *mut c_void {
+        let option_values: std::vec::Vec<(CString, CString)> = self
+            .options
+            .iter()
+            .map(|spec| {
+                let value = match options.get(&spec.name) {
+                    Some(value) => value.to_string(),
+                    None => spec.default.clone(),
+                };
+                (to_c_string(&spec.name), to_c_string(&value))
+            })
+            .collect();
+        let c_options: std::vec::Vec<PluginOptionValue> = option_values
+            .iter()
+            .map(|(name, value)| PluginOptionValue {
+                name: name.as_ptr(),
+                value: value.as_ptr(),
+            })
+            .collect();
+
+        unsafe {
+            ((*self.descriptor).create)(
+                write_to_output,
+                writable.as_mut() as *mut Box<dyn std::io::Write> as *mut c_void,
+                c_options.as_ptr(),
+                c_options.len(),
+            )
+        }
+    }
+}
+
+impl ConverterFactory for PluginConverterFactory {
+    fn construct(
+        &self,
+        writable: Box<dyn std::io::Write>,
+        label_getter: LabelGetter,
+        options: &ConverterOptions,
+    ) -> Box<dyn Converter> {
+        // Double boxing gives the plugin a thin pointer to the output.
+        let mut writable = Box::new(writable);
+        let handle = self.create_handle(&mut writable, options);
+
+        Box::new(PluginConverter {
+            descriptor: self.descriptor,
+            handle,
+            writable,
+            label_getter,
+            _library: self.library.clone(),
+        })
+    }
+
+    /// Creates (and destroys) a converter writing nowhere, so plugins rejecting
+    /// the options fail before the conversion starts.
+    fn check_options(&self, options: &ConverterOptions) -> Result<(), OptionError> {
+        let mut writable: Box<Box<dyn std::io::Write>> = Box::new(Box::new(std::io::sink()));
+        let handle = self.create_handle(&mut writable, options);
+        if handle.is_null() {
+            return Err(OptionError {
+                option: String::new(),
+                reason: format!("plugin {} failed to create the converter", self.name),
+            });
+        }
+        unsafe { ((*self.descriptor).destroy)(handle) };
+        Ok(())
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_options(&self) -> std::vec::Vec<OptionSpec> {
+        self.options.clone()
+    }
+}
+
+struct PluginConverter {
+    descriptor: *const PluginDescriptor,
+    // Null if the plugin failed to create the converter, or once it is finished.
+    handle: *mut c_void,
+    writable: Box<Box<dyn std::io::Write>>,
+    label_getter: LabelGetter,
+    _library: Option<Arc<libloading::Library>>,
+}
+
+impl Converter for PluginConverter {
+    fn process_event(
+        &mut self,
+        event: &Event,
+        reg: &EventKlassRegistry,
+    ) -> Result<(), ConversionError> {
+        if self.handle.is_null() {
+            return Err(ConversionError::Plugin(
+                "plugin failed to create the converter".to_owned(),
+            ));
+        }
+
+        let klass_name = reg
+            .get_klass_by_id(event.get_klass_id())
+            .map(|klass| to_c_string(klass.get_name()));
+        let category = self
+            .label_getter
+            .get_category(event, reg)
+            .map(|category| to_c_string(category));
+        let label = self
+            .label_getter
+            .get_label(event, reg)
+            .map(|(_, label)| to_c_string(label));
+
+        let values: std::vec::Vec<(CString, Option<CString>, &Value)> = event
+            .get_all_values()
+            .iter()
+            .map(|(name, value)| {
+                let string_value = match value {
+                    Value::Str(value) => Some(to_c_string(value)),
+                    Value::Struct(_) => Some(to_c_string(&value.to_string())),
+                    _ => None,
+                };
+                (to_c_string(name), string_value, value)
+            })
+            .collect();
+        let fields: std::vec::Vec<PluginField> = values
+            .iter()
+            .map(|(name, string_value, value)| {
+                let mut field = PluginField {
+                    name: name.as_ptr(),
+                    value_type: PLUGIN_VALUE_UNSIGNED,
+                    signed_value: 0,
+                    unsigned_value: 0,
+                    string_value: string_value
+                        .as_ref()
+                        .map_or(std::ptr::null(), |value| value.as_ptr()),
+                };
+                match value {
+                    Value::U8(v) => field.unsigned_value = u64::from(*v),
+                    Value::U16(v) => field.unsigned_value = u64::from(*v),
+                    Value::U32(v) => field.unsigned_value = u64::from(*v),
+                    Value::U64(v) => field.unsigned_value = *v,
+                    Value::I8(v) => field.signed_value = i64::from(*v),
+                    Value::I16(v) => field.signed_value = i64::from(*v),
+                    Value::I32(v) => field.signed_value = i64::from(*v),
+                    Value::I64(v) => field.signed_value = *v,
+                    Value::Str(_) | Value::Struct(_) => {}
+                }
+                field.value_type = match value {
+                    Value::I8(_) | Value::I16(_) | Value::I32(_) | Value::I64(_) => {
+                        PLUGIN_VALUE_SIGNED
+                    }
+                    Value::Str(_) | Value::Struct(_) => PLUGIN_VALUE_STRING,
+                    _ => PLUGIN_VALUE_UNSIGNED,
+                };
+                field
+            })
+            .collect();
+
+        let c_event = PluginEvent {
+            klass_id: event.get_klass_id(),
+            klass_name: klass_name
+                .as_ref()
+                .map_or(std::ptr::null(), |name| name.as_ptr()),
+            label: label
+                .as_ref()
+                .map_or(std::ptr::null(), |label| label.as_ptr()),
+            category: category
+                .as_ref()
+                .map_or(std::ptr::null(), |category| category.as_ptr()),
+            fields: fields.as_ptr(),
+            field_count: fields.len(),
+        };
+
+        let result = unsafe { ((*self.descriptor).process_event)(self.handle, &c_event) };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(ConversionError::Plugin(format!(
+                "plugin failed to process event (error code {})",
+                result
+            )))
+        }
+    }
+
+    /// Destroys the plugin's converter, which finishes its output.
+    fn finish(&mut self) -> Result<(), ConversionError> {
+        if !self.handle.is_null() {
+            unsafe { ((*self.descriptor).destroy)(self.handle) };
+            self.handle = std::ptr::null_mut();
+        }
+        self.writable.flush()?;
+        Ok(())
+    }
+
+    fn get_label_getter(&self) -> Option<&LabelGetter> {
+        Some(&self.label_getter)
+    }
+}
+
+impl Drop for PluginConverter {
+    fn drop(&mut self) {
+        // Errors can't be reported from drop.
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LabelMap;
+    use std::os::raw::c_char;
+
+    // Plugin writing "<label>/<category>/<prefix option>" for every event.
+    struct TestPlugin {
+        write: PluginWriteFn,
+        context: *mut c_void,
+        prefix: String,
+    }
+
+    unsafe extern "C" fn create(
+        write: PluginWriteFn,
+        context: *mut c_void,
+        options: *const PluginOptionValue,
+        option_count: usize,
+    ) -> *mut c_void {
+        let options = std::slice::from_raw_parts(options, option_count);
+        let prefix = from_c_string(options[0].value).unwrap();
+        if prefix == "!" {
+            return std::ptr::null_mut();
+        }
+        Box::into_raw(Box::new(TestPlugin {
+            write,
+            context,
+            prefix,
+        })) as *mut c_void
+    }
+
+    unsafe extern "C" fn process_event(converter: *mut c_void, event: *const PluginEvent) -> c_int {
+        let plugin = &*(converter as *const TestPlugin);
+        let event = &*event;
+        if event.label.is_null() {
+            return 1;
+        }
+        let line = format!(
+            "{}{}/{}/{};",
+            plugin.prefix,
+            from_c_string(event.label).unwrap(),
+            from_c_string(event.category).unwrap_or_default(),
+            event.field_count
+        );
+        (plugin.write)(plugin.context, line.as_ptr(), line.len())
+    }
+
+    unsafe extern "C" fn destroy(converter: *mut c_void) {
+        let plugin = Box::from_raw(converter as *mut TestPlugin);
+        (plugin.write)(plugin.context, b"end".as_ptr(), 3);
+    }
+
+    const OPTION_SPECS: [PluginOptionSpec; 1] = [PluginOptionSpec {
+        name: b"prefix\0".as_ptr() as *const c_char,
+        option_type: PLUGIN_OPTION_STRING,
+        choices: std::ptr::null(),
+        default_value: b">\0".as_ptr() as *const c_char,
+        help: b"Line prefix\0".as_ptr() as *const c_char,
+    }];
+
+    fn make_descriptor(abi_version: u32) -> PluginDescriptor {
+        PluginDescriptor {
+            abi_version,
+            name: b"test-plugin\0".as_ptr() as *const c_char,
+            options: OPTION_SPECS.as_ptr(),
+            option_count: OPTION_SPECS.len(),
+            create,
+            process_event,
+            destroy,
+        }
+    }
+
+    #[derive(Clone)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<std::vec::Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn plugin_converter_should_receive_events_and_options() {
+        let descriptor = make_descriptor(PLUGIN_ABI_VERSION);
+        let factory =
+            unsafe { PluginConverterFactory::from_descriptor(&descriptor, "test") }.unwrap();
+        assert_eq!(factory.get_name(), "test-plugin");
+
+        let schema = factory.get_options();
+        assert_eq!(schema[0].name, "prefix");
+        let options = ConverterOptions::parse(&schema, &[("prefix", "#")]).unwrap();
+
+        let buffer = SharedBuffer(Default::default());
+        let mut label_map = LabelMap::new();
+        label_map.add_mapping_with_category(7, "seven", "numbers");
+        let label_getter = LabelGetter::new(label_map, vec!["name".to_owned()]);
+        let mut converter = factory.construct(Box::new(buffer.clone()), label_getter, &options);
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("name".to_owned(), Value::U64(7));
+        values.insert("duration".to_owned(), Value::U32(3));
+        let reg = EventKlassRegistry::new();
+        converter
+            .process_event(&Event::new(99, values), &reg)
+            .unwrap();
+        assert!(converter
+            .process_event(&Event::new(99, std::collections::HashMap::new()), &reg)
+            .is_err());
+        drop(converter);
+
+        assert_eq!(
+            std::str::from_utf8(&buffer.0.borrow()).unwrap(),
+            "#seven/numbers/2;end"
+        );
+    }
+
+    #[test]
+    fn check_options_should_fail_if_plugin_cannot_create_converter() {
+        let descriptor = make_descriptor(PLUGIN_ABI_VERSION);
+        let factory =
+            unsafe { PluginConverterFactory::from_descriptor(&descriptor, "test") }.unwrap();
+        let schema = factory.get_options();
+
+        let options = ConverterOptions::parse(&schema, &[("prefix", "#")]).unwrap();
+        assert!(factory.check_options(&options).is_ok());
+        let options = ConverterOptions::parse(&schema, &[("prefix", "!")]).unwrap();
+        assert!(factory.check_options(&options).is_err());
+    }
+
+    #[test]
+    fn from_descriptor_should_reject_incompatible_abi() {
+        let descriptor = make_descriptor(PLUGIN_ABI_VERSION + 1);
+        match unsafe { PluginConverterFactory::from_descriptor(&descriptor, "test") } {
+            Err(PluginError::IncompatibleAbi { version, .. }) => {
+                assert_eq!(version, PLUGIN_ABI_VERSION + 1)
+            }
+            _ => panic!("expected ABI error"),
+        }
+    }
+
+    #[test]
+    fn load_should_fail_for_non_existing_library() {
+        assert!(matches!(
+            PluginConverterFactory::load(std::path::Path::new("/non/existing/plugin.so")),
+            Err(PluginError::Load { .. })
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+//! C ABI shared with converter plugins; mirrors `include/hawktracer_converter_plugin.h`.
+//!
+//! All the structures are only ever extended at the end, and every change bumps
+//! `PLUGIN_ABI_VERSION`; the host refuses plugins built for a different version.
+
+use std::os::raw::{c_char, c_int, c_void};
+
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Name of the function exported by a plugin, returning `*const PluginDescriptor`.
+pub const PLUGIN_ENTRY_SYMBOL: &str = "hawktracer_converter_plugin_entry";
+
+pub type PluginEntryFn = unsafe extern "C" fn() -> *const PluginDescriptor;
+
+/// Writes `size` bytes to the converter output; returns 0 on success.
+pub type PluginWriteFn =
+    unsafe extern "C" fn(context: *mut c_void, data: *const u8, size: usize) -> c_int;
+
+pub const PLUGIN_VALUE_SIGNED: u32 = 0;
+pub const PLUGIN_VALUE_UNSIGNED: u32 = 1;
+pub const PLUGIN_VALUE_STRING: u32 = 2;
+
+#[repr(C)]
+pub struct PluginField {
+    pub name: *const c_char,
+    /// One of `PLUGIN_VALUE_*`; only the matching value member is set.
+    pub value_type: u32,
+    pub signed_value: i64,
+    pub unsigned_value: u64,
+    pub string_value: *const c_char,
+}
+
+#[repr(C)]
+pub struct PluginEvent {
+    pub klass_id: u32,
+    /// `NULL` if the klass is not known.
+    pub klass_name: *const c_char,
+    /// Resolved label; `NULL` if the event doesn't have one.
+    pub label: *const c_char,
+    /// `NULL` if no category is mapped.
+    pub category: *const c_char,
+    pub fields: *const PluginField,
+    pub field_count: usize,
+}
+
+pub const PLUGIN_OPTION_BOOL: u32 = 0;
+pub const PLUGIN_OPTION_INTEGER: u32 = 1;
+pub const PLUGIN_OPTION_STRING: u32 = 2;
+pub const PLUGIN_OPTION_CHOICE: u32 = 3;
+
+#[repr(C)]
+pub struct PluginOptionSpec {
+    pub name: *const c_char,
+    /// One of `PLUGIN_OPTION_*`.
+    pub option_type: u32,
+    /// `|`-separated list of values of a `PLUGIN_OPTION_CHOICE` option, `NULL` otherwise.
+    pub choices: *const c_char,
+    pub default_value: *const c_char,
+    pub help: *const c_char,
+}
+
+#[repr(C)]
+pub struct PluginOptionValue {
+    pub name: *const c_char,
+    pub value: *const c_char,
+}
+
+#[repr(C)]
+pub struct PluginDescriptor {
+    /// Must be the first member, so it can be checked before anything else is read.
+    pub abi_version: u32,
+    /// Format name, as used with `--format`.
+    pub name: *const c_char,
+    pub options: *const PluginOptionSpec,
+    pub option_count: usize,
+    /// Returns `NULL` on failure. Every declared option is passed, with either
+    /// a user-defined or a default value. Before the conversion, a converter
+    /// writing nowhere is created and destroyed to check the options.
+    pub create: unsafe extern "C" fn(
+        write: PluginWriteFn,
+        write_context: *mut c_void,
+        options: *const PluginOptionValue,
+        option_count: usize,
+    ) -> *mut c_void,
+    /// Returns 0 on success. The event, and all the strings it points to,
+    /// are only valid during the call.
+    pub process_event:
+        unsafe extern "C" fn(converter: *mut c_void, event: *const PluginEvent) -> c_int,
+    /// Finishes the output and releases the converter.
+    pub destroy: unsafe extern "C" fn(converter: *mut c_void),
+}
@@ -0,0 +1,404 @@
+use crate::event_filter::{integer_value, EvaluationContext};
+use crate::parse_duration;
+use crate::FilterExpression;
+use crate::LabelGetter;
+use hawktracer_parser::{CoreEventKlassId, Event, EventKlassRegistry, Value};
+use std::convert::TryFrom;
+
+/// Parses a signed time, e.g. `-1.5ms`, into nanoseconds.
+pub fn parse_time_offset(value: &str) -> Result<i64, String> {
+    let (sign, duration) = match value.strip_prefix('-') {
+        Some(duration) => (-1, duration),
+        None => (1, value),
+    };
+    i64::try_from(parse_duration(duration)?)
+        .map(|offset| sign * offset)
+        .map_err(|_| format!("offset '{}' is too large", value))
+}
+
+/// Parses a wall-clock time, either in RFC 3339 format (e.g.
+/// `2020-02-01T12:00:00.5Z`) or as a time since the Unix epoch (e.g.
+/// `1580558400s`), into nanoseconds since the Unix epoch.
+pub fn parse_wall_clock_time(value: &str) -> Result<u64, String> {
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return date_time
+            .timestamp_nanos_opt()
+            .and_then(|nanos| u64::try_from(nanos).ok())
+            .ok_or_else(|| format!("'{}' is out of range", value));
+    }
+    parse_duration(value).map_err(|err| format!("invalid wall-clock time '{}': {}", value, err))
+}
+
+/// Defines the point in time the transformed timestamps are relative to.
+pub enum TimeOrigin {
+    /// Timestamps are relative to the beginning of the monotonic clock, as recorded.
+    Recorded,
+    /// The earliest timestamp becomes zero.
+    FirstEvent,
+    /// The timestamp of the first event matching the expression becomes zero.
+    Marker(FilterExpression),
+    /// Timestamps are converted to wall-clock time (nanoseconds since the
+    /// Unix epoch), using a pair of monotonic and wall-clock time.
+    WallClock { monotonic: u64, wall_clock: u64 },
+    /// Like `WallClock`, with the pair taken from the first event of the klass:
+    /// its timestamp, and the field holding the wall-clock time in nanoseconds.
+    WallClockEvent { klass_name: String, field: String },
+}
+
+/// Rewrites timestamps (and durations) of events: the time since the origin
+/// is multiplied by the scale, and the offset (in nanoseconds) is added.
+pub struct TimeTransform {
+    label_getter: LabelGetter,
+    origin: TimeOrigin,
+    // Monotonic time of the origin, and the time it's converted to.
+    reference: Option<(u64, u64)>,
+    offset: i64,
+    scale: f64,
+    dropped_events: u64,
+    clamped_events: u64,
+}
+
+impl TimeTransform {
+    /// The label getter is used for marker expressions only; it should be
+    /// configured the same way as the converters' ones.
+    pub fn new(label_getter: LabelGetter) -> TimeTransform {
+        TimeTransform {
+            label_getter,
+            origin: TimeOrigin::Recorded,
+            reference: Some((0, 0)),
+            offset: 0,
+            scale: 1.0,
+            dropped_events: 0,
+            clamped_events: 0,
+        }
+    }
+
+    pub fn set_origin(&mut self, origin: TimeOrigin) {
+        self.reference = match origin {
+            TimeOrigin::Recorded => Some((0, 0)),
+            TimeOrigin::WallClock {
+                monotonic,
+                wall_clock,
+            } => Some((monotonic, wall_clock)),
+            _ => None,
+        };
+        self.origin = origin;
+    }
+
+    pub fn set_offset(&mut self, offset: i64) {
+        self.offset = offset;
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    /// Returns true once the origin is known.
+    pub fn has_origin(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Number of events skipped, because they came before the origin was known.
+    pub fn get_dropped_count(&self) -> u64 {
+        self.dropped_events
+    }
+
+    /// Number of events whose time didn't fit the output range (e.g. was
+    /// before zero), and was clamped.
+    pub fn get_clamped_count(&self) -> u64 {
+        self.clamped_events
+    }
+
+    /// Learns the origin from an event read ahead of the conversion (e.g. when
+    /// scanning a file); for `FirstEvent`, the earliest timestamp of all the
+    /// scanned events is used, as events aren't ordered by their timestamps.
+    pub fn prescan_event(&mut self, event: &Event, reg: &EventKlassRegistry) {
+        if self.is_passed_through(event, reg) {
+            return;
+        }
+        if let (TimeOrigin::FirstEvent, Some((origin, _)), Ok(timestamp)) = (
+            &self.origin,
+            self.reference,
+            event.get_value_u64("timestamp"),
+        ) {
+            if timestamp < origin {
+                self.reference = Some((timestamp, 0));
+            }
+        }
+        self.learn_origin(event, reg);
+    }
+
+    /// Returns the event with transformed time, or None if the event should be
+    /// skipped. Core and mapping events, and events without a timestamp, are
+    /// never skipped nor changed.
+    pub fn transform(&mut self, event: &Event, reg: &EventKlassRegistry) -> Option<Event> {
+        if self.is_passed_through(event, reg) {
+            return Some(copy_event(event, |_, _| None));
+        }
+        self.learn_origin(event, reg);
+
+        let (monotonic, base) = match self.reference {
+            Some(reference) => reference,
+            None => {
+                self.dropped_events += 1;
+                return None;
+            }
+        };
+
+        let mut is_clamped = false;
+        let event = copy_event(event, |name, value| match name {
+            "timestamp" => integer_value(value).map(|timestamp| {
+                let time = self.scale_time(timestamp - i128::from(monotonic))
+                    + i128::from(base)
+                    + i128::from(self.offset);
+                Value::U64(clamp_time(time, &mut is_clamped))
+            }),
+            "duration" => integer_value(value)
+                .map(|duration| Value::U64(clamp_time(self.scale_time(duration), &mut is_clamped))),
+            _ => None,
+        });
+        if is_clamped {
+            self.clamped_events += 1;
+        }
+        Some(event)
+    }
+
+    fn scale_time(&self, time: i128) -> i128 {
+        // Avoids losing precision of large values, if there's nothing to scale.
+        if self.scale == 1.0 {
+            time
+        } else {
+            (time as f64 * self.scale).round() as i128
+        }
+    }
+
+    fn is_passed_through(&mut self, event: &Event, reg: &EventKlassRegistry) -> bool {
+        if CoreEventKlassId::is_core_klass(event.get_klass_id())
+            || self.label_getter.is_mapping_event(event)
+        {
+            // Mappings are needed for resolving labels in marker expressions.
+            self.label_getter.get_label(event, reg);
+            return true;
+        }
+        // Events not bound to time (e.g. HT_SystemInfoEvent) have zero timestamps.
+        event
+            .get_raw_value("timestamp")
+            .and_then(integer_value)
+            .map_or(true, |timestamp| timestamp == 0)
+    }
+
+    fn learn_origin(&mut self, event: &Event, reg: &EventKlassRegistry) {
+        if self.reference.is_some() {
+            return;
+        }
+        let timestamp = match event.get_value_u64("timestamp") {
+            Ok(timestamp) => timestamp,
+            Err(_) => return,
+        };
+        let klass_name = reg
+            .get_klass_by_id(event.get_klass_id())
+            .map(|klass| klass.get_name().as_str());
+
+        self.reference = match &self.origin {
+            TimeOrigin::FirstEvent => Some((timestamp, 0)),
+            TimeOrigin::Marker(expression) => {
+                let category = self.label_getter.get_category(event, reg).cloned();
+                let label = self
+                    .label_getter
+                    .get_label(event, reg)
+                    .map(|(_, label)| label.clone());
+                if expression.evaluate(&EvaluationContext {
+                    event,
+                    label: label.as_deref(),
+                    category: category.as_deref(),
+                    klass_name,
+                }) {
+                    Some((timestamp, 0))
+                } else {
+                    None
+                }
+            }
+            TimeOrigin::WallClockEvent {
+                klass_name: reference_klass,
+                field,
+            } if Some(reference_klass.as_str()) == klass_name => event
+                .get_raw_value(field)
+                .and_then(integer_value)
+                .and_then(|wall_clock| u64::try_from(wall_clock).ok())
+                .map(|wall_clock| (timestamp, wall_clock)),
+            _ => None,
+        };
+    }
+}
+
+fn clamp_time(time: i128, is_clamped: &mut bool) -> u64 {
+    u64::try_from(time).unwrap_or_else(|_| {
+        *is_clamped = true;
+        if time < 0 {
+            0
+        } else {
+            u64::MAX
+        }
+    })
+}
+
+fn copy_value(value: &Value) -> Value {
+    match value {
+        Value::U8(v) => Value::U8(*v),
+        Value::I8(v) => Value::I8(*v),
+        Value::U16(v) => Value::U16(*v),
+        Value::I16(v) => Value::I16(*v),
+        Value::U32(v) => Value::U32(*v),
+        Value::I32(v) => Value::I32(*v),
+        Value::U64(v) => Value::U64(*v),
+        Value::I64(v) => Value::I64(*v),
+        Value::Str(v) => Value::Str(v.clone()),
+        Value::Struct(event) => Value::Struct(copy_event(event, |_, _| None)),
+    }
+}
+
+// Copies the event, with values replaced by the ones returned from `replace`.
+fn copy_event<F>(event: &Event, mut replace: F) -> Event
+where
+    F: FnMut(&str, &Value) -> Option<Value>,
+{
+    let values = event
+        .get_all_values()
+        .iter()
+        .map(|(name, value)| {
+            let value = replace(name, value).unwrap_or_else(|| copy_value(value));
+            (name.clone(), value)
+        })
+        .collect();
+    Event::new(event.get_klass_id(), values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LabelMap;
+
+    fn make_event(timestamp: u64, duration: u64, name: u64) -> Event {
+        let mut values = std::collections::HashMap::new();
+        values.insert("timestamp".to_owned(), Value::U64(timestamp));
+        values.insert("duration".to_owned(), Value::U64(duration));
+        values.insert("name".to_owned(), Value::U64(name));
+        Event::new(99, values)
+    }
+
+    fn make_transform() -> TimeTransform {
+        let mut label_map = LabelMap::new();
+        label_map.add_mapping(1, "start");
+        TimeTransform::new(LabelGetter::new(label_map, vec!["name".to_owned()]))
+    }
+
+    fn get_time(event: Option<Event>) -> (u64, u64) {
+        let event = event.unwrap();
+        (
+            event.get_value_u64("timestamp").unwrap(),
+            event.get_value_u64("duration").unwrap(),
+        )
+    }
+
+    #[test]
+    fn parse_time_offset_should_accept_negative_values() {
+        assert_eq!(parse_time_offset("-1.5ms"), Ok(-1_500_000));
+        assert_eq!(parse_time_offset("2us"), Ok(2_000));
+        assert!(parse_time_offset("--2us").is_err());
+    }
+
+    #[test]
+    fn parse_wall_clock_time_should_accept_rfc3339_and_epoch_time() {
+        assert_eq!(
+            parse_wall_clock_time("2020-02-01T12:00:00.5Z"),
+            Ok(1_580_558_400_500_000_000)
+        );
+        assert_eq!(
+            parse_wall_clock_time("1580558400.5s"),
+            Ok(1_580_558_400_500_000_000)
+        );
+        assert!(parse_wall_clock_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn transform_should_rebase_scale_and_offset_time() {
+        let reg = EventKlassRegistry::new();
+        let mut transform = make_transform();
+        transform.set_origin(TimeOrigin::FirstEvent);
+        transform.set_scale(0.5);
+        transform.set_offset(-10);
+
+        assert_eq!(
+            get_time(transform.transform(&make_event(1000, 40, 2), &reg)),
+            (0, 20)
+        );
+        assert_eq!(
+            get_time(transform.transform(&make_event(1100, 40, 2), &reg)),
+            (40, 20)
+        );
+        // Events before the origin can't go below zero.
+        assert_eq!(
+            get_time(transform.transform(&make_event(900, 40, 2), &reg)),
+            (0, 20)
+        );
+        assert_eq!(transform.get_clamped_count(), 2);
+    }
+
+    #[test]
+    fn transform_should_skip_events_before_marker() {
+        let reg = EventKlassRegistry::new();
+        let mut transform = make_transform();
+        transform.set_origin(TimeOrigin::Marker(
+            FilterExpression::parse("label == \"start\"").unwrap(),
+        ));
+
+        assert!(transform.transform(&make_event(500, 10, 2), &reg).is_none());
+        assert!(transform
+            .transform(
+                &Event::new(
+                    CoreEventKlassId::KlassInfo as u32,
+                    std::collections::HashMap::new()
+                ),
+                &reg
+            )
+            .is_some());
+        assert_eq!(
+            get_time(transform.transform(&make_event(1000, 10, 1), &reg)),
+            (0, 10)
+        );
+        assert_eq!(
+            get_time(transform.transform(&make_event(1500, 10, 2), &reg)),
+            (500, 10)
+        );
+        assert_eq!(transform.get_dropped_count(), 1);
+    }
+
+    #[test]
+    fn prescan_should_find_earliest_timestamp() {
+        let reg = EventKlassRegistry::new();
+        let mut transform = make_transform();
+        transform.set_origin(TimeOrigin::FirstEvent);
+        transform.prescan_event(&make_event(1000, 10, 2), &reg);
+        transform.prescan_event(&make_event(800, 10, 2), &reg);
+
+        assert_eq!(
+            get_time(transform.transform(&make_event(1000, 10, 2), &reg)),
+            (200, 10)
+        );
+    }
+
+    #[test]
+    fn transform_should_convert_to_wall_clock_time() {
+        let reg = EventKlassRegistry::new();
+        let mut transform = make_transform();
+        transform.set_origin(TimeOrigin::WallClock {
+            monotonic: 1000,
+            wall_clock: 1_580_558_400_000_000_000,
+        });
+
+        assert_eq!(
+            get_time(transform.transform(&make_event(1500, 10, 2), &reg)),
+            (1_580_558_400_000_000_500, 10)
+        );
+    }
+}
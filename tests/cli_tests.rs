@@ -8,7 +8,7 @@ fn get_resource_path(file_name: &str) -> String {
     let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     d.push("resources/test");
     d.push(file_name);
-    return d.to_str().unwrap().to_owned();
+    d.to_str().unwrap().to_owned()
 }
 
 #[test]
@@ -17,7 +17,7 @@ fn chrome_tracing_converter() {
     d.push("resources/test/test_trace.htdump");
 
     let mut command = Command::new("cargo");
-    command.args(&[
+    command.args([
         "run",
         "--",
         "--source",
@@ -40,3 +40,326 @@ fn chrome_tracing_converter() {
 
     assert_json_eq!(value, expected);
 }
+
+#[test]
+fn multiple_formats_should_be_converted_from_single_capture() {
+    let output_dir = std::env::temp_dir();
+    let chrome_path = output_dir.join("hawktracer-converter-fan-out.json");
+    let flamegraph_path = output_dir.join("hawktracer-converter-fan-out.html");
+
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--format",
+        "chrome-tracing",
+        "--output-file",
+        chrome_path.to_str().unwrap(),
+        "--format",
+        "flamegraph",
+        "--output-file",
+        flamegraph_path.to_str().unwrap(),
+    ]);
+    assert!(command.status().unwrap().success());
+
+    let mut output = std::fs::read_to_string(&chrome_path).unwrap();
+    output.remove(output.len() - 1);
+    output.push(']');
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(get_resource_path("chrome_tracing_test_trace.json")).unwrap(),
+    )
+    .unwrap();
+    assert_json_eq!(value, expected);
+    assert!(std::fs::metadata(&flamegraph_path).unwrap().len() > 0);
+
+    std::fs::remove_file(chrome_path).unwrap();
+    std::fs::remove_file(flamegraph_path).unwrap();
+}
+
+#[test]
+fn converter_options_should_be_validated_against_selected_formats() {
+    let run = |option: &str| {
+        let mut command = Command::new("cargo");
+        command.args([
+            "run",
+            "--",
+            "--source",
+            &get_resource_path("test_trace.htdump"),
+            "--stdout",
+            "-O",
+            option,
+        ]);
+        command.output().unwrap()
+    };
+
+    let ns_timestamp = regex::Regex::new(r#""ts":\d+\.\d{3},"#).unwrap();
+    let output = run("chrome-tracing.precision=ns");
+    assert!(output.status.success());
+    assert!(ns_timestamp.is_match(&String::from_utf8(output.stdout).unwrap()));
+    let output = run("chrome-tracing.precision=us");
+    assert!(output.status.success());
+    assert!(!ns_timestamp.is_match(&String::from_utf8(output.stdout).unwrap()));
+
+    assert!(!run("precision=ms").status.success());
+    assert!(!run("mode=threads").status.success());
+    assert!(!run("flamegraph.precision=ns").status.success());
+}
+
+#[test]
+fn filter_should_skip_events_not_matching_expression() {
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--stdout",
+        "--filter",
+        "label =~ \"^_test\" || duration > 1s",
+    ]);
+
+    let mut output = String::from_utf8(command.output().unwrap().stdout).unwrap();
+    output.remove(output.len() - 1);
+    output.push(']');
+
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let names: std::vec::Vec<&str> = value
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["_test"]);
+}
+
+#[test]
+fn rebase_time_should_start_trace_at_zero() {
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--stdout",
+        "--rebase-time",
+        "--time-offset",
+        "1ms",
+    ]);
+
+    let mut output = String::from_utf8(command.output().unwrap().stdout).unwrap();
+    output.remove(output.len() - 1);
+    output.push(']');
+
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let first_ts = value
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["ts"].as_u64().unwrap())
+        .min();
+    assert_eq!(first_ts, Some(1000));
+}
+
+#[test]
+fn rebase_time_should_keep_events_at_time_zero() {
+    let convert = |extra_args: &[&str]| {
+        let mut command = Command::new("cargo");
+        command.args([
+            "run",
+            "--",
+            "--source",
+            &get_resource_path("test_trace.htdump"),
+            "--stdout",
+        ]);
+        command.args(extra_args);
+
+        let mut output = String::from_utf8(command.output().unwrap().stdout).unwrap();
+        output.remove(output.len() - 1);
+        output.push(']');
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        value.as_array().unwrap().clone()
+    };
+
+    let events = convert(&[]);
+    let rebased_events = convert(&["--rebase-time"]);
+    assert_eq!(rebased_events.len(), events.len());
+    assert!(rebased_events.iter().any(|event| event["ts"] == 0));
+}
+
+#[test]
+fn fail_on_errors_should_set_exit_code() {
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--stdout",
+        "--label-fields",
+        "missing_field",
+        "--fail-on-errors",
+    ]);
+
+    let output = command.output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unresolved label: 11"));
+}
+
+#[test]
+fn drop_backpressure_should_report_pipeline_stats() {
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--stdout",
+        "--verbose",
+        "--backpressure",
+        "drop",
+        "--queue-size",
+        "1",
+    ]);
+
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("parsed 53 events"));
+    assert!(stderr.contains("Event queue depth"));
+}
+
+#[test]
+fn format_should_be_detected_from_output_file_extension() {
+    let output_path = std::env::temp_dir().join("hawktracer-converter-detected.html");
+
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--output-file",
+        output_path.to_str().unwrap(),
+    ]);
+    assert!(command.status().unwrap().success());
+
+    let output = std::fs::read_to_string(&output_path).unwrap();
+    assert!(output.trim_start().starts_with("<!doctype html>"));
+    std::fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn unknown_output_file_extension_should_fall_back_to_chrome_tracing() {
+    let output_path = std::env::temp_dir().join("hawktracer-converter-unknown.httrace");
+
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--output-file",
+        output_path.to_str().unwrap(),
+    ]);
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unable to detect format"));
+
+    let mut output = std::fs::read_to_string(&output_path).unwrap();
+    output.remove(output.len() - 1);
+    output.push(']');
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(get_resource_path("chrome_tracing_test_trace.json")).unwrap(),
+    )
+    .unwrap();
+    assert_json_eq!(value, expected);
+    std::fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn output_should_be_compressed_by_output_file_extension() {
+    use std::io::Read;
+
+    let output_path = std::env::temp_dir().join("hawktracer-converter-compressed.json.gz");
+
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--output-file",
+        output_path.to_str().unwrap(),
+    ]);
+    assert!(command.status().unwrap().success());
+
+    let mut output = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(&output_path).unwrap())
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(output.starts_with("[{\"name\":\"_second_tracepoint\""));
+    std::fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn compression_level_should_require_compressed_output() {
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--stdout",
+        "--compression-level",
+        "-5",
+    ]);
+    assert_eq!(command.output().unwrap().status.code(), Some(1));
+
+    command.args(["--compression", "zstd"]);
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    let decompressed = String::from_utf8(zstd::decode_all(&output.stdout[..]).unwrap()).unwrap();
+    assert!(decompressed.starts_with("[{"));
+}
+
+#[test]
+fn mapping_files_should_be_loaded_once_for_all_formats() {
+    let json_path = std::env::temp_dir().join("hawktracer-converter-loaded-once.json");
+    let txt_path = std::env::temp_dir().join("hawktracer-converter-loaded-once.txt");
+
+    let mut command = Command::new("cargo");
+    command.args([
+        "run",
+        "--",
+        "--source",
+        &get_resource_path("test_trace.htdump"),
+        "--map-files",
+        &get_resource_path("label_map_valid.txt"),
+        "--format",
+        "chrome-tracing",
+        "--output-file",
+        json_path.to_str().unwrap(),
+        "--format",
+        "debug",
+        "--output-file",
+        txt_path.to_str().unwrap(),
+        "--filter-klass",
+        "HT_CallstackIntEvent",
+        "--rebase-time",
+    ]);
+
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("Successfully loaded mapping").count(), 1);
+    std::fs::remove_file(json_path).unwrap();
+    std::fs::remove_file(txt_path).unwrap();
+}